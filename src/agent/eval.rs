@@ -0,0 +1,476 @@
+use std::{error::Error, fs, sync::OnceLock};
+
+use crate::core::{board::{piece_attacks, Board}, piece::PieceType};
+
+pub const MIDDLEGAME: usize = 0;
+pub const ENDGAME: usize = 1;
+
+/// Tuned weights are written here and loaded by all agents, if the file exists
+pub const PARAMS_FILE: &str = "data/eval.params";
+
+const PIECE_TYPES: [PieceType; 6] = [
+    PieceType::Pawn,
+    PieceType::Knight,
+    PieceType::Bishop,
+    PieceType::Rook,
+    PieceType::Queen,
+    PieceType::King
+];
+
+/// Game phase contributed by each knight, bishop, rook and queen, the starting position has the full phase
+const PHASE_WEIGHTS: [isize; 4] = [1, 1, 2, 4];
+const FULL_PHASE: isize = 24;
+/// Mobility of knights, bishops, rooks and queens counts relative to these numbers of squares
+const MOBILITY_BASELINES: [isize; 4] = [4, 6, 7, 13];
+
+const FILE_A: u64 = 0x0101_0101_0101_0101;
+const RANK_1: u64 = 0xFF;
+
+/// Weights of the evaluation in centipawns, indexed by `MIDDLEGAME` and `ENDGAME` first.
+/// Piece types are ordered pawn, knight, bishop, rook, queen and king
+#[derive(Clone, Debug, PartialEq)]
+pub struct EvalParams {
+    /// Values of pawn, knight, bishop, rook and queen
+    pub material: [[isize; 5]; 2],
+    /// Piece-square tables from whites point of view, starting with a8 like a FEN
+    pub piece_squares: [[[isize; 64]; 6]; 2],
+    /// Bonus of passed pawns by the rank they reached, counted from their own side
+    pub passed_pawn: [[isize; 8]; 2],
+    pub isolated_pawn: [isize; 2],
+    /// Per pawn behind another pawn of the same player
+    pub doubled_pawn: [isize; 2],
+    /// Per square a knight, bishop, rook or queen reaches beyond its baseline
+    pub mobility: [[isize; 4]; 2],
+    /// Per pawn on the two ranks in front of the own king
+    pub king_shield: [isize; 2],
+    /// Per attack of a piece on the enemy king and the squares around it
+    pub king_attack: [isize; 2],
+    pub bishop_pair: [isize; 2]
+}
+
+#[rustfmt::skip]
+const PAWN_MIDDLEGAME: [isize; 64] = [
+     0,   0,   0,   0,   0,   0,   0,   0,
+    50,  50,  50,  50,  50,  50,  50,  50,
+    10,  10,  20,  30,  30,  20,  10,  10,
+     5,   5,  10,  25,  25,  10,   5,   5,
+     0,   0,   0,  20,  20,   0,   0,   0,
+     5,  -5, -10,   0,   0, -10,  -5,   5,
+     5,  10,  10, -20, -20,  10,  10,   5,
+     0,   0,   0,   0,   0,   0,   0,   0
+];
+
+#[rustfmt::skip]
+const PAWN_ENDGAME: [isize; 64] = [
+     0,   0,   0,   0,   0,   0,   0,   0,
+    80,  80,  80,  80,  80,  80,  80,  80,
+    50,  50,  50,  50,  50,  50,  50,  50,
+    30,  30,  30,  30,  30,  30,  30,  30,
+    20,  20,  20,  20,  20,  20,  20,  20,
+    10,  10,  10,  10,  10,  10,  10,  10,
+    10,  10,  10,  10,  10,  10,  10,  10,
+     0,   0,   0,   0,   0,   0,   0,   0
+];
+
+#[rustfmt::skip]
+const KNIGHT: [isize; 64] = [
+   -50, -40, -30, -30, -30, -30, -40, -50,
+   -40, -20,   0,   0,   0,   0, -20, -40,
+   -30,   0,  10,  15,  15,  10,   0, -30,
+   -30,   5,  15,  20,  20,  15,   5, -30,
+   -30,   0,  15,  20,  20,  15,   0, -30,
+   -30,   5,  10,  15,  15,  10,   5, -30,
+   -40, -20,   0,   5,   5,   0, -20, -40,
+   -50, -40, -30, -30, -30, -30, -40, -50
+];
+
+#[rustfmt::skip]
+const BISHOP: [isize; 64] = [
+   -20, -10, -10, -10, -10, -10, -10, -20,
+   -10,   0,   0,   0,   0,   0,   0, -10,
+   -10,   0,   5,  10,  10,   5,   0, -10,
+   -10,   5,   5,  10,  10,   5,   5, -10,
+   -10,   0,  10,  10,  10,  10,   0, -10,
+   -10,  10,  10,  10,  10,  10,  10, -10,
+   -10,   5,   0,   0,   0,   0,   5, -10,
+   -20, -10, -10, -10, -10, -10, -10, -20
+];
+
+#[rustfmt::skip]
+const ROOK: [isize; 64] = [
+     0,   0,   0,   0,   0,   0,   0,   0,
+     5,  10,  10,  10,  10,  10,  10,   5,
+    -5,   0,   0,   0,   0,   0,   0,  -5,
+    -5,   0,   0,   0,   0,   0,   0,  -5,
+    -5,   0,   0,   0,   0,   0,   0,  -5,
+    -5,   0,   0,   0,   0,   0,   0,  -5,
+    -5,   0,   0,   0,   0,   0,   0,  -5,
+     0,   0,   0,   5,   5,   0,   0,   0
+];
+
+#[rustfmt::skip]
+const QUEEN: [isize; 64] = [
+   -20, -10, -10,  -5,  -5, -10, -10, -20,
+   -10,   0,   0,   0,   0,   0,   0, -10,
+   -10,   0,   5,   5,   5,   5,   0, -10,
+    -5,   0,   5,   5,   5,   5,   0,  -5,
+     0,   0,   5,   5,   5,   5,   0,  -5,
+   -10,   5,   5,   5,   5,   5,   0, -10,
+   -10,   0,   5,   0,   0,   0,   0, -10,
+   -20, -10, -10,  -5,  -5, -10, -10, -20
+];
+
+#[rustfmt::skip]
+const KING_MIDDLEGAME: [isize; 64] = [
+   -30, -40, -40, -50, -50, -40, -40, -30,
+   -30, -40, -40, -50, -50, -40, -40, -30,
+   -30, -40, -40, -50, -50, -40, -40, -30,
+   -30, -40, -40, -50, -50, -40, -40, -30,
+   -20, -30, -30, -40, -40, -30, -30, -20,
+   -10, -20, -20, -20, -20, -20, -20, -10,
+    20,  20,   0,   0,   0,   0,  20,  20,
+    20,  30,  10,   0,   0,  10,  30,  20
+];
+
+#[rustfmt::skip]
+const KING_ENDGAME: [isize; 64] = [
+   -50, -40, -30, -20, -20, -30, -40, -50,
+   -30, -20, -10,   0,   0, -10, -20, -30,
+   -30, -10,  20,  30,  30,  20, -10, -30,
+   -30, -10,  30,  40,  40,  30, -10, -30,
+   -30, -10,  30,  40,  40,  30, -10, -30,
+   -30, -10,  20,  30,  30,  20, -10, -30,
+   -30, -30,   0,   0,   0,   0, -30, -30,
+   -50, -30, -30, -30, -30, -30, -30, -50
+];
+
+impl Default for EvalParams {
+    fn default() -> EvalParams {
+        EvalParams {
+            material: [[82, 337, 365, 477, 1025], [94, 281, 297, 512, 936]],
+            piece_squares: [
+                [PAWN_MIDDLEGAME, KNIGHT, BISHOP, ROOK, QUEEN, KING_MIDDLEGAME],
+                [PAWN_ENDGAME, KNIGHT, BISHOP, ROOK, QUEEN, KING_ENDGAME]
+            ],
+            passed_pawn: [[0, 5, 5, 10, 20, 35, 60, 0], [0, 5, 10, 20, 35, 60, 100, 0]],
+            isolated_pawn: [-10, -15],
+            doubled_pawn: [-10, -20],
+            mobility: [[4, 5, 2, 1], [4, 5, 4, 2]],
+            king_shield: [10, 0],
+            king_attack: [8, 2],
+            bishop_pair: [30, 50]
+        }
+    }
+}
+
+impl EvalParams {
+    /// The weights by name, in the order of `to_vector`
+    fn groups_mut(&mut self) -> [(&'static str, Vec<&mut isize>); 9] {
+        [
+            ("material", self.material.iter_mut().flatten().collect()),
+            ("piece_squares", self.piece_squares.iter_mut().flatten().flatten().collect()),
+            ("passed_pawn", self.passed_pawn.iter_mut().flatten().collect()),
+            ("isolated_pawn", self.isolated_pawn.iter_mut().collect()),
+            ("doubled_pawn", self.doubled_pawn.iter_mut().collect()),
+            ("mobility", self.mobility.iter_mut().flatten().collect()),
+            ("king_shield", self.king_shield.iter_mut().collect()),
+            ("king_attack", self.king_attack.iter_mut().collect()),
+            ("bishop_pair", self.bishop_pair.iter_mut().collect())
+        ]
+    }
+
+    /// All weights as one vector, e.g. for tuning them
+    pub fn to_vector(&self) -> Vec<isize> {
+        self.clone().groups_mut().into_iter()
+            .flat_map(|(_, weights)| weights.into_iter().map(|weight| *weight))
+            .collect()
+    }
+
+    pub fn from_vector(vector: &[isize]) -> Result<EvalParams, String> {
+        let mut params: EvalParams = EvalParams::default();
+        let mut weights: Vec<&mut isize> = params.groups_mut().into_iter()
+            .flat_map(|(_, weights)| weights)
+            .collect();
+        if weights.len() != vector.len() {
+            return Err(format!("Expected {} evaluation weights, found {}", weights.len(), vector.len()));
+        }
+
+        weights.iter_mut().zip(vector).for_each(|(weight, value)| **weight = *value);
+        Ok(params)
+    }
+
+    /// Reads a file with one line per weight group, its name followed by the weights.
+    /// Groups missing in the file keep their default weights
+    pub fn read_from_file(file_path: &str) -> Result<EvalParams, Box<dyn Error>> {
+        let file_content: String = fs::read_to_string(file_path)?;
+        let mut params: EvalParams = EvalParams::default();
+        let mut groups = params.groups_mut();
+
+        for line in file_content.lines().filter(|line| !line.trim().is_empty()) {
+            let mut tokens = line.split_whitespace();
+            let name: &str = tokens.next().unwrap_or_default();
+            let Some((_, weights)) = groups.iter_mut().find(|(group_name, _)| *group_name == name) else {
+                return Err(format!("Unknown evaluation weights '{}' in {}", name, file_path).into());
+            };
+
+            let values: Vec<isize> = tokens.map(|token| token.parse::<isize>()).collect::<Result<_, _>>()?;
+            if values.len() != weights.len() {
+                return Err(format!("Expected {} weights for '{}', found {}", weights.len(), name, values.len()).into());
+            }
+            weights.iter_mut().zip(values).for_each(|(weight, value)| **weight = value);
+        }
+
+        Ok(params)
+    }
+
+    pub fn write_to_file(&self, file_path: &str) -> Result<(), Box<dyn Error>> {
+        let file_content: String = self.clone().groups_mut().into_iter()
+            .map(|(name, weights)| {
+                let weights: Vec<String> = weights.into_iter().map(|weight| weight.to_string()).collect();
+                format!("{} {}\n", name, weights.join(" "))
+            })
+            .collect();
+        fs::write(file_path, file_content)?;
+        Ok(())
+    }
+}
+
+static PARAMS: OnceLock<EvalParams> = OnceLock::new();
+
+/// The weights all agents evaluate with, read from `PARAMS_FILE` on first use if it exists
+pub fn params() -> &'static EvalParams {
+    PARAMS.get_or_init(|| {
+        if !std::path::Path::new(PARAMS_FILE).is_file() {
+            return EvalParams::default()
+        }
+        EvalParams::read_from_file(PARAMS_FILE).unwrap_or_else(|error| {
+            eprintln!("Using the default evaluation, as {} could not be read: {}", PARAMS_FILE, error);
+            EvalParams::default()
+        })
+    })
+}
+
+/// Static evaluation in centipawns from whites perspective
+pub fn evaluate(board: &Board) -> isize {
+    evaluate_with(board, params())
+}
+
+/// Blends the middlegame and endgame scores by the material left on the board
+pub fn evaluate_with(board: &Board, params: &EvalParams) -> isize {
+    let (white_scores, white_phase) = evaluate_player(board, true, params);
+    let (black_scores, black_phase) = evaluate_player(board, false, params);
+
+    let phase: isize = (white_phase + black_phase).min(FULL_PHASE);
+    let middlegame: isize = white_scores[MIDDLEGAME] - black_scores[MIDDLEGAME];
+    let endgame: isize = white_scores[ENDGAME] - black_scores[ENDGAME];
+
+    (middlegame * phase + endgame * (FULL_PHASE - phase)) / FULL_PHASE
+}
+
+/// Middlegame and endgame score of one player and the phase their pieces add
+fn evaluate_player(board: &Board, player: bool, params: &EvalParams) -> ([isize; 2], isize) {
+    let player_layer: u64 = if player { board.layer_color } else { !board.layer_color };
+    let opponent_layer: u64 = !player_layer;
+    let occupied: u64 = !board.get_empty_layer();
+
+    let mut scores: [isize; 2] = [0, 0];
+    let mut phase: isize = 0;
+
+    let opponent_king: u64 = board.layer_king & opponent_layer;
+    let opponent_king_zone: u64 = if opponent_king != 0 {
+        opponent_king | piece_attacks(PieceType::King, opponent_king.trailing_zeros(), occupied)
+    } else {
+        0
+    };
+
+    let piece_layers: [u64; 6] = [
+        board.layer_pawn,
+        board.layer_knight,
+        board.layer_bishop,
+        board.layer_rook,
+        board.layer_queen,
+        board.layer_king
+    ];
+    for (piece_index, piece_layer) in piece_layers.into_iter().enumerate() {
+        let mut pieces: u64 = piece_layer & player_layer;
+        while pieces != 0 {
+            let square: u32 = pieces.trailing_zeros();
+            pieces &= pieces - 1;
+
+            let table_square: usize = table_square(square, player);
+            for stage in [MIDDLEGAME, ENDGAME] {
+                scores[stage] += params.piece_squares[stage][piece_index][table_square];
+                if let Some(value) = params.material[stage].get(piece_index) {
+                    scores[stage] += value;
+                }
+            }
+
+            // Knights, bishops, rooks and queens
+            if (1..5).contains(&piece_index) {
+                let attacks: u64 = piece_attacks(PIECE_TYPES[piece_index], square, occupied);
+                let mobility: isize = (attacks & !(occupied & player_layer)).count_ones() as isize
+                    - MOBILITY_BASELINES[piece_index - 1];
+                let king_attacks: isize = (attacks & opponent_king_zone).count_ones() as isize;
+                for stage in [MIDDLEGAME, ENDGAME] {
+                    scores[stage] += params.mobility[stage][piece_index - 1] * mobility
+                        + params.king_attack[stage] * king_attacks;
+                }
+                phase += PHASE_WEIGHTS[piece_index - 1];
+            }
+        }
+    }
+
+    let pawn_scores: [isize; 2] = evaluate_pawns(
+        board.layer_pawn & player_layer,
+        board.layer_pawn & opponent_layer,
+        player,
+        params
+    );
+    let king_shield: isize = king_shield(board.layer_king & player_layer, board.layer_pawn & player_layer, player);
+    let bishop_pair: bool = (board.layer_bishop & player_layer).count_ones() >= 2;
+    for stage in [MIDDLEGAME, ENDGAME] {
+        scores[stage] += pawn_scores[stage] + params.king_shield[stage] * king_shield;
+        if bishop_pair {
+            scores[stage] += params.bishop_pair[stage];
+        }
+    }
+
+    (scores, phase)
+}
+
+/// Passed, isolated and doubled pawns
+fn evaluate_pawns(pawns: u64, opponent_pawns: u64, player: bool, params: &EvalParams) -> [isize; 2] {
+    let mut scores: [isize; 2] = [0, 0];
+
+    for column in 0..8 {
+        let file_pawns: isize = (pawns & FILE_A << column).count_ones() as isize;
+        if file_pawns == 0 {
+            continue;
+        }
+        let isolated: bool = pawns & adjacent_files(column) == 0;
+        for stage in [MIDDLEGAME, ENDGAME] {
+            scores[stage] += params.doubled_pawn[stage] * (file_pawns - 1);
+            if isolated {
+                scores[stage] += params.isolated_pawn[stage] * file_pawns;
+            }
+        }
+    }
+
+    let mut remaining: u64 = pawns;
+    while remaining != 0 {
+        let square: u32 = remaining.trailing_zeros();
+        remaining &= remaining - 1;
+
+        let (row, column) = (square / 8, square % 8);
+        let files: u64 = FILE_A << column | adjacent_files(column);
+        if opponent_pawns & files & ranks_ahead(row, player) == 0 {
+            let rank: usize = if player { row as usize } else { 7 - row as usize };
+            for stage in [MIDDLEGAME, ENDGAME] {
+                scores[stage] += params.passed_pawn[stage][rank];
+            }
+        }
+    }
+
+    scores
+}
+
+/// Own pawns on the king file and its neighbours, one or two ranks in front of the king
+fn king_shield(king: u64, pawns: u64, player: bool) -> isize {
+    if king == 0 {
+        return 0
+    }
+
+    let (row, column) = (king.trailing_zeros() / 8, king.trailing_zeros() % 8);
+    let shield_rows: [Option<u32>; 2] = if player {
+        [row.checked_add(1), row.checked_add(2)]
+    } else {
+        [row.checked_sub(1), row.checked_sub(2)]
+    };
+    let ranks: u64 = shield_rows.into_iter()
+        .flatten()
+        .filter(|row| *row < 8)
+        .fold(0, |ranks, row| ranks | RANK_1 << (row * 8));
+
+    (pawns & ranks & (FILE_A << column | adjacent_files(column))).count_ones() as isize
+}
+
+fn adjacent_files(column: u32) -> u64 {
+    let left: u64 = if column > 0 { FILE_A << (column - 1) } else { 0 };
+    let right: u64 = if column < 7 { FILE_A << (column + 1) } else { 0 };
+    left | right
+}
+
+/// Ranks in front of `row`, in the direction the pawns of `player` move
+fn ranks_ahead(row: u32, player: bool) -> u64 {
+    if player {
+        u64::MAX.checked_shl((row + 1) * 8).unwrap_or(0)
+    } else {
+        (0b1 << (row * 8)) - 1
+    }
+}
+
+/// Index into the piece-square tables, which start at a8, black uses them mirrored
+fn table_square(square: u32, player: bool) -> usize {
+    let (row, column) = (square as usize / 8, square as usize % 8);
+    if player {
+        (7 - row) * 8 + column
+    } else {
+        row * 8 + column
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Swaps the colors and flips the board vertically
+    fn mirror(placement: &str) -> String {
+        placement.split('/').rev()
+            .map(|rank| rank.chars()
+                .map(|symbol| if symbol.is_ascii_uppercase() { symbol.to_ascii_lowercase() } else { symbol.to_ascii_uppercase() })
+                .collect::<String>())
+            .collect::<Vec<String>>()
+            .join("/")
+    }
+
+    #[test]
+    fn mirrored_positions_are_balanced() {
+        assert_eq!(evaluate(&Board::new()), 0);
+
+        let placement: &str = "r1bqk2r/pp3ppp/2n2n2/3pp3/1b2P3/2NP1N2/PPP2PPP/R1BQKB1R";
+        let board: Board = Board::from_fen(placement, "-").unwrap();
+        let mirrored: Board = Board::from_fen(&mirror(placement), "-").unwrap();
+        assert_ne!(evaluate(&board), 0);
+        assert_eq!(evaluate(&board), -evaluate(&mirrored));
+    }
+
+    #[test]
+    fn rewards_pawn_structure() {
+        // Both sides have three pawns, but only whites are connected and one of them is passed
+        let board: Board = Board::from_fen("4k3/p1p3p1/8/8/8/8/5PPP/4K3", "-").unwrap();
+        assert!(evaluate(&board) > 0);
+
+        let doubled: Board = Board::from_fen("4k3/8/8/8/8/6P1/6PP/4K3", "-").unwrap();
+        let healthy: Board = Board::from_fen("4k3/8/8/8/8/8/5PPP/4K3", "-").unwrap();
+        assert!(evaluate(&doubled) < evaluate(&healthy));
+    }
+
+    #[test]
+    fn params_round_trip_through_files() {
+        let mut params: EvalParams = EvalParams { bishop_pair: [12, 34], ..EvalParams::default() };
+        params.piece_squares[ENDGAME][5][63] = -7;
+
+        let file_path: String = std::env::temp_dir().join("chess-ai-eval.params").to_string_lossy().into_owned();
+        params.write_to_file(&file_path).unwrap();
+        assert_eq!(EvalParams::read_from_file(&file_path).unwrap(), params);
+        assert_eq!(EvalParams::from_vector(&params.to_vector()).unwrap(), params);
+        assert!(EvalParams::from_vector(&[1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn prefers_the_bishop_pair() {
+        let pair: Board = Board::from_fen("4k3/8/8/8/8/8/8/2B1KB2", "-").unwrap();
+        let knight: Board = Board::from_fen("4k3/8/8/8/8/8/8/2B1KN2", "-").unwrap();
+        assert!(evaluate(&pair) > evaluate(&knight));
+    }
+}
@@ -0,0 +1,124 @@
+use crate::core::{game::Game, zobrist};
+
+/// Plies without a capture or pawn move, after which the game counts as drawn
+pub const FIFTY_MOVE_PLIES: usize = 100;
+
+/// Hashes of the positions played so far, followed by the current path of a search
+#[derive(Clone, Debug)]
+pub struct PositionHistory {
+    hashes: Vec<u64>,
+    /// Index of the position on the board, where searches start
+    root: usize
+}
+
+impl PositionHistory {
+    pub fn new(game: &Game) -> PositionHistory {
+        PositionHistory { hashes: vec![zobrist::hash_game(game)], root: 0 }
+    }
+
+    /// Adds the position after a move that was played
+    pub fn push(&mut self, hash: u64) {
+        self.hashes.truncate(self.root + 1);
+        self.hashes.push(hash);
+        self.root += 1;
+    }
+
+    /// Adds the position `ply` plies into a search, replacing the path that was searched before
+    pub fn visit(&mut self, ply: usize, hash: u64) {
+        self.hashes.truncate(self.root + ply);
+        self.hashes.push(hash);
+    }
+
+    /// Whether the current position repeats. Only the positions since the last capture or pawn move
+    /// can repeat, and only every second one has the same player to move. Returning to a position of the
+    /// search path is enough, as it could be repeated again, played positions have to occur twice before
+    pub fn is_repetition(&self, halfmove_clock: usize) -> bool {
+        let Some((hash, earlier)) = self.hashes.split_last() else {
+            return false
+        };
+
+        let mut played_repetitions: usize = 0;
+        for (index, earlier_hash) in earlier.iter().enumerate().rev().take(halfmove_clock).skip(1).step_by(2) {
+            if earlier_hash != hash {
+                continue;
+            }
+            if index > self.root {
+                return true
+            }
+            played_repetitions += 1;
+            if played_repetitions == 2 {
+                return true
+            }
+        }
+        false
+    }
+
+    /// Draws by repetition or the fifty-move rule, which `Game` does not detect by itself
+    pub fn is_draw(&self, game: &Game) -> bool {
+        game.halfmove_clock >= FIFTY_MOVE_PLIES || self.is_repetition(game.halfmove_clock)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::position::Position;
+
+    /// Origin and target as row and column
+    type Step = ((u8, u8), (u8, u8));
+
+    const KNIGHT_TOUR: [Step; 4] = [((0, 6), (2, 5)), ((7, 6), (5, 5)), ((2, 5), (0, 6)), ((5, 5), (7, 6))];
+
+    #[test]
+    fn detects_repetitions_and_fifty_moves() {
+        let mut game: Game = Game::new();
+        let mut history: PositionHistory = PositionHistory::new(&game);
+
+        // Played positions are drawn on their third occurrence
+        for (from_pos, to_pos) in KNIGHT_TOUR.into_iter().chain(KNIGHT_TOUR) {
+            assert!(!history.is_draw(&game));
+            game.perform_move(&Position::new(from_pos.0, from_pos.1), &Position::new(to_pos.0, to_pos.1));
+            history.push(zobrist::hash_game(&game));
+        }
+        assert_eq!(game.halfmove_clock, 8);
+        assert!(history.is_draw(&game));
+
+        game.perform_move(&Position::new(1, 4), &Position::new(3, 4));
+        history.push(zobrist::hash_game(&game));
+        assert_eq!(game.halfmove_clock, 0);
+        assert!(!history.is_draw(&game));
+
+        let game: Game = Game::from_fen("4k3/8/8/8/8/8/8/4K2R w - - 100 80").unwrap();
+        assert!(PositionHistory::new(&game).is_draw(&game));
+    }
+
+    #[test]
+    fn searched_positions_repeat_once() {
+        let mut game: Game = Game::new();
+        let mut history: PositionHistory = PositionHistory::new(&game);
+        let search = |game: &mut Game, history: &mut PositionHistory, moves: &[Step]| {
+            for (ply, (from_pos, to_pos)) in moves.iter().enumerate() {
+                game.perform_move(&Position::new(from_pos.0, from_pos.1), &Position::new(to_pos.0, to_pos.1));
+                history.visit(ply + 1, zobrist::hash_game(game));
+            }
+        };
+
+        // The root was played once, the position after the first searched move was not
+        search(&mut game, &mut history, &KNIGHT_TOUR);
+        assert!(!history.is_draw(&game));
+        let mut game: Game = Game::new();
+        search(&mut game, &mut history, &[KNIGHT_TOUR.as_slice(), &KNIGHT_TOUR[..1]].concat());
+        assert!(history.is_draw(&game));
+
+        // Returning to a root that was played twice
+        let mut game: Game = Game::new();
+        let mut history: PositionHistory = PositionHistory::new(&game);
+        for (from_pos, to_pos) in KNIGHT_TOUR {
+            game.perform_move(&Position::new(from_pos.0, from_pos.1), &Position::new(to_pos.0, to_pos.1));
+            history.push(zobrist::hash_game(&game));
+        }
+        assert!(!history.is_draw(&game));
+        search(&mut game, &mut history, &KNIGHT_TOUR);
+        assert!(history.is_draw(&game));
+    }
+}
@@ -1,157 +1,900 @@
-use std::{cmp::{max, min}, isize};
+use std::{cmp::{max, min}, sync::atomic::{AtomicBool, AtomicUsize, Ordering}, thread};
 
 use good_web_game::timer;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
 
-use crate::core::{board::Board, game::Game, move_generator::get_all_possible_moves, position::Position};
+use crate::{agent::{eval, history::PositionHistory, move_ordering::MoveOrdering, syzygy::{self, Syzygy}, tablebase::{self, Tablebases, Wdl}, transposition::{Bound, TranspositionTable, DEFAULT_HASH_SIZE_MB}, Agent, InfoReporter, SearchInfo, SearchLimits}, core::{board::Board, game::Game, move_generator::get_all_possible_moves, piece::PieceType, position::Position, zobrist}};
 
 type Move = (Position, Position);
 
-pub fn get_turn(game: &Game, max_compute_time: f64, silent: bool) -> (Position, Position) {
+/// Wins score `MATE_SCORE` minus the plies from the root to the mate, so faster mates score higher
+const MATE_SCORE: isize = 1_000_000;
+/// Evaluations stay far below this, so any score beyond is a mate
+const MATE_THRESHOLD: isize = MATE_SCORE - 10_000;
+/// Wins known from Syzygy tables, whose distance to mate is unknown, score above every evaluation and below every mate
+const SYZYGY_WIN_SCORE: isize = 50_000;
+/// Beyond every score, for full windows
+const INFINITY: isize = MATE_SCORE + 1;
+
+/// Half width of the first root window around the score of the previous iteration
+const ASPIRATION_WINDOW: isize = 50;
+
+/// Captures that cannot lift the score this close to alpha are skipped in the quiescence search
+const DELTA_MARGIN: isize = 200;
+/// Positions this far beyond beta per remaining ply are cut off without a search
+const REVERSE_FUTILITY_MARGIN: isize = 120;
+/// Quiet moves one ply above the horizon are skipped, if even this gain would not reach alpha
+const FUTILITY_MARGIN: isize = 250;
+const NULL_MOVE_REDUCTION: usize = 2;
+/// Quiet moves from this index on are searched with reduced depth first
+const LATE_MOVE_INDEX: usize = 3;
+
+#[derive(Clone, Debug)]
+pub struct MinimaxConfig {
+    /// Memory of the transposition table in megabytes
+    pub hash_size_mb: usize,
+    /// Also resolves checking moves in the first ply of the quiescence search
+    pub quiescence_checks: bool,
+    /// Lets the player to move pass, switched off if they only have pawns left
+    pub null_move_pruning: bool,
+    pub late_move_reductions: bool,
+    /// Reverse futility pruning and futility pruning near the horizon
+    pub futility_pruning: bool,
+    pub check_extensions: bool,
+    /// Search threads sharing the transposition table (Lazy SMP), a single thread searches deterministically
+    pub threads: usize,
+    /// Centipawns a draw is worse than an equal position for the searching player,
+    /// negative values make them seek draws
+    pub contempt: isize
+}
+
+impl Default for MinimaxConfig {
+    fn default() -> MinimaxConfig {
+        MinimaxConfig {
+            hash_size_mb: DEFAULT_HASH_SIZE_MB,
+            quiescence_checks: false,
+            null_move_pruning: true,
+            late_move_reductions: true,
+            futility_pruning: true,
+            check_extensions: true,
+            threads: num_cpus::get(),
+            contempt: 0
+        }
+    }
+}
+
+pub struct MinimaxAgent {
+    game: Game,
+    history: PositionHistory,
+    reporter: Option<InfoReporter>,
+    rng: StdRng,
+    config: MinimaxConfig,
+    transposition_table: TranspositionTable
+}
+
+impl MinimaxAgent {
+    pub fn with_config(config: MinimaxConfig) -> MinimaxAgent {
+        MinimaxAgent::build(config, StdRng::from_rng(&mut rand::rng()))
+    }
+
+    /// Equal moves are picked at random, a fixed seed and a single thread make the choice reproducible
+    #[cfg(test)]
+    pub fn with_seed(seed: u64) -> MinimaxAgent {
+        let config: MinimaxConfig = MinimaxConfig { threads: 1, ..MinimaxConfig::default() };
+        MinimaxAgent::build(config, StdRng::seed_from_u64(seed))
+    }
+
+    fn build(config: MinimaxConfig, rng: StdRng) -> MinimaxAgent {
+        MinimaxAgent {
+            game: Game::new(),
+            history: PositionHistory::new(&Game::new()),
+            reporter: None,
+            rng,
+            transposition_table: TranspositionTable::new(config.hash_size_mb),
+            config
+        }
+    }
+}
+
+impl Agent for MinimaxAgent {
+    fn name(&self) -> String {
+        String::from("Minimax")
+    }
+
+    fn new_game(&mut self, game: &Game) {
+        self.game = game.clone();
+        self.history = PositionHistory::new(game);
+        self.transposition_table.clear();
+    }
+
+    fn notify_move(&mut self, from_pos: &Position, to_pos: &Position) {
+        self.game.perform_move(from_pos, to_pos);
+        self.history.push(zobrist::hash_game(&self.game));
+    }
+
+    fn choose_move(&mut self, limits: &SearchLimits) -> (Position, Position) {
+        get_turn(
+            &self.game,
+            &self.history,
+            limits,
+            &self.config,
+            &self.transposition_table,
+            self.reporter.as_ref(),
+            &mut self.rng
+        )
+    }
+
+    fn set_reporter(&mut self, reporter: InfoReporter) {
+        self.reporter = Some(reporter);
+    }
+}
+
+struct SearchContext<'a> {
+    limits: &'a SearchLimits,
+    config: &'a MinimaxConfig,
+    start_time: f64,
+    /// Nodes of all search threads
+    nodes: &'a AtomicUsize,
+    /// Set by the main thread once it is done, so the helper threads end as well
+    cancel: &'a AtomicBool,
+    transposition_table: &'a TranspositionTable,
+    tablebases: &'a Tablebases,
+    syzygy: &'a Syzygy,
+    /// The game so far and the current search path, for detecting repetitions
+    history: PositionHistory,
+    /// Score of draws from whites perspective, which includes the contempt of the searching player
+    draw_score: isize,
+    move_ordering: MoveOrdering,
+    /// Triangular table, the line at index `ply` is the best continuation found from that ply
+    pv_table: Vec<Vec<Move>>,
+    root_step: usize,
+    /// Remaining depth of the root in the current iteration, which limits check extensions
+    root_depth: usize,
+    /// No further null moves are tried below a null move
+    in_null_move: bool
+}
+
+impl<'a> SearchContext<'a> {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        game: &Game,
+        history: &PositionHistory,
+        limits: &'a SearchLimits,
+        config: &'a MinimaxConfig,
+        start_time: f64,
+        nodes: &'a AtomicUsize,
+        cancel: &'a AtomicBool,
+        transposition_table: &'a TranspositionTable
+    ) -> SearchContext<'a> {
+        SearchContext {
+            limits,
+            config,
+            start_time,
+            nodes,
+            cancel,
+            transposition_table,
+            tablebases: tablebase::tablebases(),
+            syzygy: syzygy::syzygy(),
+            history: history.clone(),
+            draw_score: if game.player_turn { -config.contempt } else { config.contempt },
+            move_ordering: MoveOrdering::new(),
+            pv_table: vec![],
+            root_step: game.step_counter,
+            root_depth: 0,
+            in_null_move: false
+        }
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+        || self.limits.is_exhausted(self.start_time, self.nodes.load(Ordering::Relaxed))
+    }
+
+    fn count_node(&self) {
+        self.nodes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn ply(&self, game: &Game) -> usize {
+        game.step_counter - self.root_step
+    }
+
+    fn clear_pv(&mut self, ply: usize) {
+        if self.pv_table.len() < ply + 2 {
+            self.pv_table.resize(ply + 2, vec![]);
+        }
+        self.pv_table[ply].clear();
+    }
+
+    /// Makes `best_move` followed by the line of the next ply the best line at `ply`
+    fn update_pv(&mut self, ply: usize, best_move: Move) {
+        let (lines, next_lines) = self.pv_table.split_at_mut(ply + 1);
+        let line: &mut Vec<Move> = &mut lines[ply];
+        line.clear();
+        line.push(best_move);
+        line.extend_from_slice(&next_lines[0]);
+    }
+}
+
+pub fn get_turn(
+    game: &Game,
+    history: &PositionHistory,
+    limits: &SearchLimits,
+    config: &MinimaxConfig,
+    transposition_table: &TranspositionTable,
+    report: Option<&InfoReporter>,
+    rng: &mut impl Rng
+) -> (Position, Position) {
+    let start_time: f64 = timer::time();
+    let nodes: AtomicUsize = AtomicUsize::new(0);
+    let cancel: AtomicBool = AtomicBool::new(false);
+
+    // Equally good moves are searched in random order, so the first one found is a random pick
+    // In Syzygy positions, only the moves that keep the best outcome are searched
+    let mut root_moves: Vec<Move> = syzygy::syzygy().root_moves(game)
+        .unwrap_or_else(|| get_all_possible_moves(&game.board, game.player_turn));
+    root_moves.shuffle(rng);
+    let helper_seeds: Vec<u64> = (1..config.threads.max(1)).map(|_| rng.random::<u64>()).collect();
+
+    thread::scope(|scope| {
+        // Helpers only fill the transposition table, their different move orders and depths
+        // make them search other parts of the tree than the main thread
+        for (helper_index, seed) in helper_seeds.into_iter().enumerate() {
+            let mut helper_moves: Vec<Move> = root_moves.clone();
+            let (nodes, cancel) = (&nodes, &cancel);
+            scope.spawn(move || {
+                helper_moves.shuffle(&mut StdRng::seed_from_u64(seed));
+                let mut context: SearchContext = SearchContext::new(
+                    game, history, limits, config, start_time, nodes, cancel, transposition_table
+                );
+                iterative_deepening(game, helper_moves, helper_index % 2, &mut context, None);
+            });
+        }
+
+        let mut context: SearchContext = SearchContext::new(
+            game, history, limits, config, start_time, &nodes, &cancel, transposition_table
+        );
+        let best_move: Move = iterative_deepening(game, root_moves, 0, &mut context, report);
+        cancel.store(true, Ordering::Relaxed);
+        best_move
+    })
+}
+
+fn iterative_deepening(
+    game: &Game,
+    mut root_moves: Vec<Move>,
+    start_depth: usize,
+    context: &mut SearchContext,
+    report: Option<&InfoReporter>
+) -> Move {
     let maximizing_player: bool = game.player_turn;
-    let now: f64 = timer::time();
-    let mut depth: usize = 0;
+    let mut depth: usize = start_depth;
 
-    let mut best_move_total = None;
-    let mut best_score_total = if maximizing_player { isize::MIN } else { isize::MAX };
-    let mut last_depth_time_elapsed: f64 = 0.0;
+    let mut best_move_total: Option<Move> = None;
+    let mut previous_score: Option<isize> = None;
 
-    'outer_loop: loop {
-        let mut best_move = None;
-        let mut best_score = if maximizing_player { isize::MIN } else { isize::MAX };
-    
-        for (move_used, future_game) in get_all_game_states_with_move(game) {
-            let score = minimax(
-                &future_game,
-                depth,
-                isize::MIN,
-                isize::MAX,
-                now + max_compute_time
-            );
-            if score.is_none() {
+    'outer_loop: while best_move_total.is_none() || context.limits.allows_depth(depth + 1) {
+        // The best move of the previous iteration is searched first
+        context.move_ordering.order(&game.board, &mut root_moves, best_move_total.as_ref(), 0);
+        context.root_depth = depth + 1;
+
+        let mut window: isize = ASPIRATION_WINDOW;
+        let score: isize = loop {
+            let (alpha, beta) = match previous_score {
+                Some(previous_score) if !is_mate_score(previous_score) =>
+                    ((previous_score - window).max(-INFINITY), (previous_score + window).min(INFINITY)),
+                _ => (-INFINITY, INFINITY)
+            };
+
+            let Some(score) = search_root(game, &root_moves, depth, alpha, beta, context) else {
+                // Stopped before any depth was completed, so take the most promising move
+                if best_move_total.is_none() {
+                    best_move_total = root_moves.first().cloned();
+                }
                 break 'outer_loop;
+            };
+
+            if score <= alpha && alpha != -INFINITY || score >= beta && beta != INFINITY {
+                window = window.saturating_mul(4).min(INFINITY);
+                continue;
             }
+            break score;
+        };
+
+        let pv: Vec<Move> = complete_pv(game, context.pv_table[0].clone(), depth + 1, context.transposition_table);
+        best_move_total = pv.first().or(root_moves.first()).cloned();
+        previous_score = Some(score);
+        depth += 1;
+
+        if let (Some(report), Some(best_move)) = (report, &best_move_total) {
+            let (centipawns, mate) = score_for_player(score, maximizing_player);
+            report(&SearchInfo {
+                depth,
+                score: centipawns,
+                mate,
+                nodes: context.nodes.load(Ordering::Relaxed),
+                time: timer::time() - context.start_time,
+                pv: if pv.is_empty() { vec![best_move.clone()] } else { pv }
+            });
+        }
+    }
+    
+    best_move_total.unwrap_or_else(|| panic!("Unable to find any minimax move"))
+}
+
+/// Transposition table cutoffs cut the PV short, so it is continued with the stored best moves
+fn complete_pv(game: &Game, mut pv: Vec<Move>, length: usize, transposition_table: &TranspositionTable) -> Vec<Move> {
+    let mut game: Game = game.clone();
+    for (from_pos, to_pos) in pv.iter() {
+        game.perform_move(from_pos, to_pos);
+    }
+
+    while pv.len() < length && game.get_winner().is_none() {
+        let Some((from_pos, to_pos)) = transposition_table.probe(zobrist::hash_game(&game))
+            .and_then(|entry| entry.best_move)
+            .filter(|(from_pos, to_pos)| game.valid_turn(from_pos, to_pos)) else {
+            break;
+        };
+        game.perform_move(&from_pos, &to_pos);
+        pv.push((from_pos, to_pos));
+    }
+
+    pv
+}
+
+/// Searches the root moves in the given order, the best line ends up in the PV table
+fn search_root(
+    game: &Game,
+    root_moves: &[Move],
+    depth: usize,
+    mut alpha: isize,
+    mut beta: isize,
+    context: &mut SearchContext
+) -> Option<isize> {
+    context.clear_pv(0);
+
+    let maximizing_player: bool = game.player_turn;
+    let mut best_eval: isize = if maximizing_player { -INFINITY } else { INFINITY };
 
-            let noise: f64 = rand::random::<f64>() % 1.0;
+    for (index, (from_pos, to_pos)) in root_moves.iter().enumerate() {
+        let mut future_game: Game = game.clone();
+        future_game.perform_move(from_pos, to_pos);
+        let eval: isize = if index == 0 {
+            minimax(&future_game, depth, alpha, beta, context)?
+        } else {
+            null_window_search(&future_game, depth, alpha, beta, maximizing_player, context)?
+        };
 
-            if maximizing_player && score.unwrap() as f64 + noise >= best_score as f64 {
-                best_move = Some(move_used);
-                best_score = score.unwrap();
-            } else if !maximizing_player && score.unwrap() as f64 + noise <= best_score as f64 {
-                best_move = Some(move_used);
-                best_score = score.unwrap();
+        if maximizing_player && eval > best_eval {
+            best_eval = eval;
+            if eval > alpha {
+                alpha = eval;
+                context.update_pv(0, (from_pos.clone(), to_pos.clone()));
+            }
+        } else if !maximizing_player && eval < best_eval {
+            best_eval = eval;
+            if eval < beta {
+                beta = eval;
+                context.update_pv(0, (from_pos.clone(), to_pos.clone()));
             }
         }
 
-        best_move_total = best_move;
-        best_score_total = best_score;
-        last_depth_time_elapsed = timer::time() - now;
-        depth += 1;
+        if beta <= alpha {
+            break;
+        }
     }
 
-    if !silent {
-        println!("\nMinimax:\n > Execution time {:.3?}\n > best score {}\n > depth: {}",
-            last_depth_time_elapsed, best_score_total, depth);
+    Some(best_eval)
+}
+
+/// Principal variation search: after the first move, the others only have to be proven worse,
+/// which a null window does cheaply. Only a move that turns out better is searched again
+fn null_window_search(
+    game: &Game,
+    depth: usize,
+    alpha: isize,
+    beta: isize,
+    maximizing_player: bool,
+    context: &mut SearchContext
+) -> Option<isize> {
+    let eval: isize = if maximizing_player {
+        minimax(game, depth, alpha, alpha.saturating_add(1), context)?
+    } else {
+        minimax(game, depth, beta.saturating_sub(1), beta, context)?
+    };
+
+    if alpha < eval && eval < beta {
+        minimax(game, depth, alpha, beta, context)
+    } else {
+        Some(eval)
     }
-    
-    best_move_total.unwrap_or_else(|| panic!("Unable to find any minimax move"))
 }
 
-pub fn minimax(
+fn minimax(
     game: &Game,
     depth: usize,
     mut alpha: isize,
     mut beta: isize,
-    stop_time: f64
+    context: &mut SearchContext
 ) -> Option<isize> {
-    if timer::time() > stop_time {
+    if context.is_exhausted() {
         return None
     }
+    context.count_node();
 
-    match game.get_winner() {
-        Some(2) => return Some(0),
-        Some(1) => return Some(isize::MAX - game.step_counter as isize),
-        Some(0) => return Some(isize::MIN + game.step_counter as isize),
-        _ => ()
+    let ply: usize = context.ply(game);
+    context.clear_pv(ply);
+
+    if let Some(score) = terminal_score(game, ply, context.draw_score) {
+        return Some(score)
+    }
+
+    // Repetitions depend on the path, so they are detected before the table is probed
+    let key: u64 = zobrist::hash_game(game);
+    context.history.visit(ply, key);
+    if context.history.is_draw(game) {
+        return Some(context.draw_score)
+    }
+
+    if let Some(score) = tablebase_score(context.tablebases, context.syzygy, game, ply, context.draw_score) {
+        return Some(score)
     }
 
     if depth == 0 {
-        return Some(evaluate_board(&game.board))
+        return quiescence(game, alpha, beta, 0, context)
+    }
+
+    let (alpha_original, beta_original) = (alpha, beta);
+    let mut hash_move: Option<Move> = None;
+
+    if let Some(entry) = context.transposition_table.probe(key) {
+        if entry.depth >= depth {
+            let score: isize = score_from_table(entry.score, ply);
+            match entry.bound {
+                Bound::Exact => return Some(score),
+                Bound::Lower => alpha = max(alpha, score),
+                Bound::Upper => beta = min(beta, score)
+            }
+            if beta <= alpha {
+                return Some(score)
+            }
+        }
+        hash_move = entry.best_move;
     }
 
-    let games_after_one_move = get_all_game_states_after_move(game);
     let maximizing_player: bool = game.player_turn;
+    let in_check: bool = game.check;
+    let is_pv_node: bool = alpha.abs_diff(beta) > 1;
+    let prunable: bool = !is_pv_node && !in_check && !is_mate_score(alpha) && !is_mate_score(beta);
+    let static_eval: isize = eval::evaluate(&game.board);
 
-    if maximizing_player {
-        let mut max_eval = isize::MIN + game.step_counter as isize;
+    if prunable && context.config.futility_pruning && depth <= 3 {
+        let margin: isize = REVERSE_FUTILITY_MARGIN * depth as isize;
+        if maximizing_player && static_eval - margin >= beta
+        || !maximizing_player && static_eval + margin <= alpha {
+            return Some(static_eval)
+        }
+    }
 
-        for future_game in games_after_one_move {
-            let eval = minimax(&future_game, depth - 1, alpha, beta, stop_time);
-            eval?;
-            max_eval = max(max_eval, eval.unwrap());
+    if prunable && context.config.null_move_pruning && depth >= 3 && !context.in_null_move
+    && has_pieces(&game.board, maximizing_player)
+    && (maximizing_player && static_eval >= beta || !maximizing_player && static_eval <= alpha) {
+        if let Some(eval) = null_move_search(game, depth, alpha, beta, context) {
+            if maximizing_player && eval >= beta || !maximizing_player && eval <= alpha {
+                return Some(eval)
+            }
+        } else {
+            return None
+        }
+    }
 
-            alpha = max(alpha, eval.unwrap());
-            if beta <= alpha {
-                break;
+    let mut moves: Vec<Move> = get_all_possible_moves(&game.board, game.player_turn);
+    context.move_ordering.order(&game.board, &mut moves, hash_move.as_ref(), ply);
+
+    let mut best_move: Option<Move> = None;
+    let mut best_eval: isize = if maximizing_player { -INFINITY } else { INFINITY };
+
+    // Quiet moves cannot bring the score back into the window close to the horizon
+    let futile: bool = prunable && context.config.futility_pruning && depth == 1
+        && (maximizing_player && static_eval + FUTILITY_MARGIN <= alpha
+            || !maximizing_player && static_eval - FUTILITY_MARGIN >= beta);
+    if futile {
+        best_eval = if maximizing_player { static_eval + FUTILITY_MARGIN } else { static_eval - FUTILITY_MARGIN };
+    }
+
+    for (index, (from_pos, to_pos)) in moves.into_iter().enumerate() {
+        let is_capture: bool = game.board.get_piece_at(&to_pos).piece_type() != PieceType::Empty;
+        let mut future_game: Game = game.clone();
+        future_game.perform_move(&from_pos, &to_pos);
+        let is_quiet: bool = !is_capture && !future_game.check;
+
+        if futile && index > 0 && is_quiet {
+            continue;
+        }
+
+        let extension: usize = usize::from(
+            context.config.check_extensions && future_game.check && ply < 2 * context.root_depth
+        );
+        let reduction: usize = if context.config.late_move_reductions && index >= LATE_MOVE_INDEX
+            && depth >= 3 && is_quiet && !in_check {
+            if index >= 2 * LATE_MOVE_INDEX { 2 } else { 1 }
+        } else {
+            0
+        };
+        let new_depth: usize = depth - 1 + extension;
+
+        let eval: isize = if index == 0 {
+            minimax(&future_game, new_depth, alpha, beta, context)?
+        } else if reduction > 0 {
+            // A reduced null window search has to beat the best move, before it is searched fully
+            let reduced_eval: isize = if maximizing_player {
+                minimax(&future_game, new_depth - reduction, alpha, alpha.saturating_add(1), context)?
+            } else {
+                minimax(&future_game, new_depth - reduction, beta.saturating_sub(1), beta, context)?
+            };
+            if maximizing_player && reduced_eval > alpha || !maximizing_player && reduced_eval < beta {
+                null_window_search(&future_game, new_depth, alpha, beta, maximizing_player, context)?
+            } else {
+                reduced_eval
+            }
+        } else {
+            null_window_search(&future_game, new_depth, alpha, beta, maximizing_player, context)?
+        };
+
+        if maximizing_player && (eval > best_eval || best_move.is_none()) {
+            best_eval = eval;
+            best_move = Some((from_pos, to_pos));
+            if eval > alpha {
+                alpha = eval;
+                context.update_pv(ply, best_move.clone().unwrap());
+            }
+        } else if !maximizing_player && (eval < best_eval || best_move.is_none()) {
+            best_eval = eval;
+            best_move = Some((from_pos, to_pos));
+            if eval < beta {
+                beta = eval;
+                context.update_pv(ply, best_move.clone().unwrap());
             }
         }
-        Some(max_eval)
+
+        if beta <= alpha {
+            context.move_ordering.record_cutoff(&game.board, best_move.as_ref().unwrap(), ply, depth);
+            break;
+        }
+    }
+
+    let bound: Bound = if best_eval <= alpha_original {
+        Bound::Upper
+    } else if best_eval >= beta_original {
+        Bound::Lower
     } else {
-        let mut min_eval = isize::MAX - game.step_counter as isize;
+        Bound::Exact
+    };
+    context.transposition_table.store(key, depth, bound, score_to_table(best_eval, ply), best_move);
+
+    Some(best_eval)
+}
 
-        for future_game in games_after_one_move {
-            let eval = minimax(&future_game, depth - 1, alpha, beta, stop_time);
-            eval?;
-            min_eval = min(min_eval, eval.unwrap());
+/// Passes the move with a reduced depth. If the position is still too good for the opponent
+/// to bring back into the window, real moves are assumed to be even better. Passing is no legal move,
+/// so mates found behind it are not proven and the score is kept below the mate scores
+fn null_move_search(
+    game: &Game,
+    depth: usize,
+    alpha: isize,
+    beta: isize,
+    context: &mut SearchContext
+) -> Option<isize> {
+    let mut null_game: Game = game.clone();
+    null_game.next_player();
+    null_game.check = false;
+    null_game.step_counter += 1;
+    // Positions before the pass do not count as repeated
+    null_game.halfmove_clock = 0;
 
-            beta = min(beta, eval.unwrap());
-            if beta <= alpha {
-                break;
+    let reduction: usize = NULL_MOVE_REDUCTION + usize::from(depth > 6);
+    let null_depth: usize = depth.saturating_sub(1 + reduction);
+
+    context.in_null_move = true;
+    let eval: Option<isize> = if game.player_turn {
+        minimax(&null_game, null_depth, beta.saturating_sub(1), beta, context)
+    } else {
+        minimax(&null_game, null_depth, alpha, alpha.saturating_add(1), context)
+    };
+    context.in_null_move = false;
+
+    eval.map(|eval| eval.clamp(-MATE_THRESHOLD + 1, MATE_THRESHOLD - 1))
+}
+
+/// Zugzwang is common once a player has only pawns left, so passing is no good guess then
+fn has_pieces(board: &Board, player: bool) -> bool {
+    let player_layer: u64 = if player { board.layer_color } else { !board.layer_color };
+    (board.layer_knight | board.layer_bishop | board.layer_rook | board.layer_queen) & player_layer != 0
+}
+
+/// Scores of finished games, with wins for white being positive
+fn terminal_score(game: &Game, ply: usize, draw_score: isize) -> Option<isize> {
+    match game.get_winner() {
+        Some(2) => Some(draw_score),
+        Some(1) => Some(MATE_SCORE - ply as isize),
+        Some(0) => Some(-MATE_SCORE + ply as isize),
+        _ => None
+    }
+}
+
+/// Exact scores of positions in the tablebases, with mates counted from the root like in `terminal_score`.
+/// Syzygy tables only tell the outcome, their wins are preferred the closer they are
+fn tablebase_score(tablebases: &Tablebases, syzygy: &Syzygy, game: &Game, ply: usize, draw_score: isize) -> Option<isize> {
+    let (wdl, mate_score) = match tablebases.probe(game) {
+        Some(probe) => (probe.wdl, MATE_SCORE - (ply + probe.dtm) as isize),
+        None => (syzygy.probe_wdl(game)?.wdl(), SYZYGY_WIN_SCORE - ply as isize)
+    };
+    let score: isize = match wdl {
+        Wdl::Win => mate_score,
+        Wdl::Draw => return Some(draw_score),
+        Wdl::Loss => -mate_score
+    };
+    Some(if game.player_turn { score } else { -score })
+}
+
+fn is_mate_score(score: isize) -> bool {
+    score.abs() >= MATE_THRESHOLD
+}
+
+/// Mate scores count plies from the root, the table counts them from the stored position instead,
+/// as it may be reached at another ply later
+fn score_to_table(score: isize, ply: usize) -> isize {
+    if score >= MATE_THRESHOLD {
+        score + ply as isize
+    } else if score <= -MATE_THRESHOLD {
+        score - ply as isize
+    } else {
+        score
+    }
+}
+
+fn score_from_table(score: isize, ply: usize) -> isize {
+    if score >= MATE_THRESHOLD {
+        score - ply as isize
+    } else if score <= -MATE_THRESHOLD {
+        score + ply as isize
+    } else {
+        score
+    }
+}
+
+/// Resolves captures until the position is quiet, so the static evaluation is not taken
+/// in the middle of an exchange. The player to move may stand pat unless in check
+fn quiescence(
+    game: &Game,
+    mut alpha: isize,
+    mut beta: isize,
+    quiescence_ply: usize,
+    context: &mut SearchContext
+) -> Option<isize> {
+    if context.is_exhausted() {
+        return None
+    }
+    context.count_node();
+    let ply: usize = context.ply(game);
+    context.clear_pv(ply);
+
+    if let Some(score) = terminal_score(game, ply, context.draw_score) {
+        return Some(score)
+    }
+
+    let maximizing_player: bool = game.player_turn;
+    let stand_pat: isize = eval::evaluate(&game.board);
+    let in_check: bool = game.check;
+
+    let mut best_eval: isize = if in_check {
+        if maximizing_player { -INFINITY } else { INFINITY }
+    } else {
+        stand_pat
+    };
+    if !in_check {
+        if maximizing_player {
+            if stand_pat >= beta {
+                return Some(stand_pat)
+            }
+            alpha = max(alpha, stand_pat);
+        } else {
+            if stand_pat <= alpha {
+                return Some(stand_pat)
             }
+            beta = min(beta, stand_pat);
         }
-        Some(min_eval)
     }
-}
 
-fn get_all_game_states_with_move(
-    game: &Game
-)  -> impl Iterator<Item=(Move, Game)> + '_ {
-    get_all_possible_moves(&game.board, game.player_turn)
-        .into_iter().map(|(from_pos, to_pos)| {
-            let mut new_game: Game = game.clone();
-            new_game.perform_move(&from_pos, &to_pos);
-            ((from_pos, to_pos), new_game)
+    let include_checks: bool = context.config.quiescence_checks && quiescence_ply == 0;
+    let mut moves: Vec<Move> = get_all_possible_moves(&game.board, game.player_turn)
+        .into_iter()
+        .filter(|(_, to_pos)| {
+            in_check || include_checks || game.board.get_piece_at(to_pos).piece_type() != PieceType::Empty
         })
+        .collect();
+    context.move_ordering.order(&game.board, &mut moves, None, ply);
+
+    for (from_pos, to_pos) in moves {
+        let victim: PieceType = game.board.get_piece_at(&to_pos).piece_type();
+        let is_capture: bool = victim != PieceType::Empty;
+
+        // Delta pruning: even winning the victim for free would not reach alpha or beta
+        let best_gain: isize = victim.value() + DELTA_MARGIN;
+        if !in_check && is_capture && (maximizing_player && stand_pat + best_gain <= alpha
+            || !maximizing_player && stand_pat - best_gain >= beta) {
+            continue;
+        }
+
+        // Captures that lose material are no way out of the window either
+        if !in_check && is_capture && game.board.static_exchange(&from_pos, &to_pos) < 0 {
+            continue;
+        }
+
+        let mut future_game: Game = game.clone();
+        future_game.perform_move(&from_pos, &to_pos);
+        if !in_check && !is_capture && !future_game.check {
+            continue;
+        }
+
+        let eval: isize = quiescence(&future_game, alpha, beta, quiescence_ply + 1, context)?;
+        if maximizing_player {
+            best_eval = max(best_eval, eval);
+            alpha = max(alpha, eval);
+        } else {
+            best_eval = min(best_eval, eval);
+            beta = min(beta, eval);
+        }
+
+        if beta <= alpha {
+            break;
+        }
+    }
+
+    Some(best_eval)
 }
 
-fn get_all_game_states_after_move(
-    game: &Game
-)  -> impl Iterator<Item=Game> + '_ {
-    get_all_game_states_with_move(game)
-        .map(|(_, game)| game)
+/// Scores are measured in centipawns from whites perspective. Reports need them,
+/// or for mates the moves until mate, from the perspective of the player to move
+fn score_for_player(score: isize, player_turn: bool) -> (isize, Option<isize>) {
+    let score: isize = if player_turn { score } else { -score };
+    if is_mate_score(score) {
+        let mate_plies: isize = MATE_SCORE - score.abs();
+        let mate_moves: isize = (mate_plies + 1) / 2;
+        (score.signum() * 100_000, Some(score.signum() * mate_moves))
+    } else {
+        (score, None)
+    }
 }
 
-fn evaluate_board(board: &Board) -> isize {
-    let mut score: isize = 0;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{agent::{capture_reports, tablebase::Material}, tablebase_generator};
+
+    #[test]
+    fn node_limited_search_is_deterministic() {
+        let moves: Vec<Move> = (0..2).map(|_| {
+            let mut agent: MinimaxAgent = MinimaxAgent::with_seed(7);
+            agent.new_game(&Game::from_fen("r3k3/8/8/8/8/8/8/4K2R w - - 0 1").unwrap());
+            agent.choose_move(&SearchLimits::nodes(2000))
+        }).collect();
 
-    score += (board.layer_pawn & board.layer_color).count_ones() as isize * 10;
-    score += (board.layer_knight & board.layer_color).count_ones() as isize * 30;
-    score += (board.layer_bishop & board.layer_color).count_ones() as isize * 30;
-    score += (board.layer_rook & board.layer_color).count_ones() as isize * 50;
-    score += (board.layer_queen & board.layer_color).count_ones() as isize * 70;
+        assert_eq!(moves[0], moves[1]);
+    }
 
-    score -= (board.layer_pawn & !board.layer_color).count_ones() as isize * 10;
-    score -= (board.layer_knight & !board.layer_color).count_ones() as isize * 30;
-    score -= (board.layer_bishop & !board.layer_color).count_ones() as isize * 30;
-    score -= (board.layer_rook & !board.layer_color).count_ones() as isize * 50;
-    score -= (board.layer_queen & !board.layer_color).count_ones() as isize * 70;
+    #[test]
+    fn null_moves_prove_no_mates() {
+        // After passing, black has to play Kg8 and is mated by Ra8, which a window above the material finds
+        let game: Game = Game::from_fen("7k/8/6K1/8/8/8/8/R7 w - - 0 1").unwrap();
+        let (limits, config) = (SearchLimits::default(), MinimaxConfig::default());
+        let (nodes, cancel) = (AtomicUsize::new(0), AtomicBool::new(false));
+        let transposition_table: TranspositionTable = TranspositionTable::new(1);
+        let mut context: SearchContext = SearchContext::new(
+            &game, &PositionHistory::new(&game), &limits, &config, timer::time(), &nodes, &cancel, &transposition_table
+        );
 
-    let center_control_white =
-        (0b1100000011000000000000000000000000000 & board.layer_color).count_ones() as isize;
-    let center_control_black =
-        (0b1100000011000000000000000000000000000 & !board.layer_color).count_ones() as isize;
-    
-    score += center_control_white;
-    score -= center_control_black;
+        let eval: isize = null_move_search(&game, 8, 999, 1000, &mut context).unwrap();
+        assert_eq!(eval, MATE_THRESHOLD - 1);
+    }
 
-    score
+    #[test]
+    fn stopped_search_returns_a_move() {
+        let limits: SearchLimits = SearchLimits::infinite();
+        limits.stop.store(true, std::sync::atomic::Ordering::Relaxed);
+
+        let mut agent: MinimaxAgent = MinimaxAgent::with_seed(7);
+        let (from_pos, to_pos) = agent.choose_move(&limits);
+        assert!(Game::new().valid_turn(&from_pos, &to_pos));
+    }
+
+    #[test]
+    fn quiescence_sees_recaptures() {
+        let mut agent: MinimaxAgent = MinimaxAgent::with_seed(7);
+        agent.new_game(&Game::from_fen("7k/8/4p3/3p4/8/8/8/K2Q4 w - - 0 1").unwrap());
+
+        let (from_pos, to_pos) = agent.choose_move(&SearchLimits::depth(1));
+        assert_ne!((from_pos, to_pos), (Position::new(0, 3), Position::new(4, 3)));
+    }
+
+    #[test]
+    fn reports_legal_principal_variations() {
+        let mut agent: MinimaxAgent = MinimaxAgent::with_seed(7);
+        let reports = capture_reports(&mut agent);
+        let best_move: Move = agent.choose_move(&SearchLimits::depth(3));
+
+        let reports = reports.lock().unwrap();
+        let pv: &Vec<Move> = &reports.last().unwrap().pv;
+        assert_eq!(pv.len(), 3);
+        assert_eq!(pv[0], best_move);
+
+        let mut game: Game = Game::new();
+        for (from_pos, to_pos) in pv {
+            assert!(game.valid_turn(from_pos, to_pos));
+            game.perform_move(from_pos, to_pos);
+        }
+    }
+
+    #[test]
+    fn reports_distance_to_mate() {
+        let mut agent: MinimaxAgent = MinimaxAgent::with_seed(7);
+        agent.new_game(&Game::from_fen("7k/6pp/8/8/8/8/8/R6K w - - 0 1").unwrap());
+        let reports = capture_reports(&mut agent);
+        let best_move: Move = agent.choose_move(&SearchLimits::depth(4));
+
+        assert_eq!(best_move, (Position::new(0, 0), Position::new(7, 0)));
+        assert_eq!(reports.lock().unwrap().last().unwrap().mate, Some(1));
+    }
+
+    #[test]
+    fn behind_player_repeats_the_position() {
+        let mut agent: MinimaxAgent = MinimaxAgent::with_seed(7);
+        agent.new_game(&Game::from_fen("rnb1kbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap());
+        // Ng8 brings up the start position for the third time
+        let moves: [((u8, u8), (u8, u8)); 4] = [((0, 6), (2, 5)), ((7, 6), (5, 5)), ((2, 5), (0, 6)), ((5, 5), (7, 6))];
+        for (from_pos, to_pos) in moves.into_iter().chain(moves.into_iter().take(3)) {
+            agent.notify_move(&Position::new(from_pos.0, from_pos.1), &Position::new(to_pos.0, to_pos.1));
+        }
+
+        let best_move: Move = agent.choose_move(&SearchLimits::depth(3));
+        assert_eq!(best_move, (Position::new(5, 5), Position::new(7, 6)));
+    }
+
+    #[test]
+    fn helper_threads_share_the_table() {
+        let config: MinimaxConfig = MinimaxConfig { threads: 4, ..MinimaxConfig::default() };
+        let mut agent: MinimaxAgent = MinimaxAgent::with_config(config);
+        agent.new_game(&Game::from_fen("r3k3/8/8/8/8/8/8/4K2R w - - 0 1").unwrap());
+
+        let (from_pos, to_pos) = agent.choose_move(&SearchLimits::depth(4));
+        assert!(agent.game.valid_turn(&from_pos, &to_pos));
+    }
+
+    #[test]
+    fn depth_limit_ends_the_search() {
+        let mut agent: MinimaxAgent = MinimaxAgent::with_seed(7);
+        let reports = capture_reports(&mut agent);
+        agent.choose_move(&SearchLimits::depth(2));
+
+        let depths: Vec<usize> = reports.lock().unwrap().iter().map(|info| info.depth).collect();
+        assert_eq!(depths, vec![1, 2]);
+    }
+
+    #[test]
+    fn scores_tablebase_positions_as_mates() {
+        let mut tablebases: Tablebases = Tablebases::default();
+        for name in ["KvK", "KRvK"] {
+            let table = tablebase_generator::generate(&Material::from_name(name).unwrap(), &tablebases);
+            tablebases.insert(table);
+        }
+
+        let game: Game = Game::from_fen("8/8/8/3k4/8/8/8/R6K b - - 0 1").unwrap();
+        assert_eq!(tablebase_score(&tablebases, &Syzygy::default(), &game, 3, 0), Some(MATE_SCORE - 3 - 24));
+        let game: Game = Game::from_fen("8/8/8/3K4/8/8/8/r6k w - - 0 1").unwrap();
+        assert_eq!(tablebase_score(&tablebases, &Syzygy::default(), &game, 3, 0), Some(-MATE_SCORE + 3 + 24));
+        let game: Game = Game::from_fen("8/8/8/8/8/2k5/8/2K5 w - - 0 1").unwrap();
+        assert_eq!(tablebase_score(&tablebases, &Syzygy::default(), &game, 3, -15), Some(-15));
+        // Castling rights are not covered
+        let game: Game = Game::from_fen("4k3/8/8/8/8/8/8/R3K3 w Q - 0 1").unwrap();
+        assert_eq!(tablebase_score(&tablebases, &Syzygy::default(), &game, 3, 0), None);
+    }
 }
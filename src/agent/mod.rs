@@ -1,60 +1,207 @@
-use crate::{agent::{monte_carlo::Tree, neural_network::Network}, core::{game::Game, position::Position}};
+use std::sync::{atomic::{AtomicBool, Ordering}, Arc};
+#[cfg(test)]
+use std::sync::Mutex;
 
+use good_web_game::timer;
+
+use crate::{agent::history::PositionHistory, core::{game::Game, position::Position, zobrist}};
+
+pub mod book;
+pub mod eval;
+pub mod history;
 pub mod minimax;
+pub mod move_ordering;
 pub mod monte_carlo;
 pub mod neural_network;
+pub mod playout;
 pub mod random;
+pub mod syzygy;
+pub mod tablebase;
+pub mod transposition;
 
-#[derive(Clone)]
-pub struct Agent {
-    pub agent_type: AgentType,
-    pub game: Game,
-    pub max_compute_time: f64
+/// Progress of a search, as reported to protocol front-ends
+#[derive(Clone, Debug)]
+pub struct SearchInfo {
+    pub depth: usize,
+    /// Centipawns from the perspective of the player to move
+    pub score: isize,
+    /// Moves until mate, negative if the player to move gets mated
+    pub mate: Option<isize>,
+    pub nodes: usize,
+    pub time: f64,
+    pub pv: Vec<(Position, Position)>
 }
 
-#[allow(unused)]
-#[derive(Clone)]
-pub enum AgentType {
-    Random,
-    Minimax,
-    MonteCarlo(Tree),
-    NeuralNetwork(Network)
-}
+/// Time kept back per move for communication and move making
+pub const MOVE_OVERHEAD: f64 = 0.05;
+/// Assumed number of moves left when the time control has no sessions
+pub const DEFAULT_MOVES_TO_GO: usize = 30;
 
-impl Agent {
-    pub fn new(agent_type: AgentType, max_compute_time: f64) -> Agent {
-        Agent {
-            agent_type,
-            game: Game::new(),
-            max_compute_time
+impl SearchInfo {
+    /// Mates as `#N`, otherwise pawns like `+0.35`
+    pub fn format_score(&self) -> String {
+        match self.mate {
+            Some(mate) => format!("#{}", mate),
+            None => format!("{:+.2}", self.score as f64 / 100.0)
         }
     }
+}
 
-    pub fn inform_about_move(&mut self, from_pos: &Position, to_pos: &Position) {
-        match &mut self.agent_type {
-            AgentType::Random |
-            AgentType::Minimax |
-            AgentType::NeuralNetwork(_) => {
-                self.game.perform_move(from_pos, to_pos);
-            },
-            AgentType::MonteCarlo(ref mut tree) => {
-                self.game.perform_move(from_pos, to_pos);
-                tree.walk_edge_permanently(from_pos, to_pos);
-            }
-        }
+pub type InfoReporter = Arc<dyn Fn(&SearchInfo) + Send + Sync>;
+
+/// Bounds for a single `choose_move` call. Without any limit the search runs until `stop` is set
+#[derive(Clone, Debug, Default)]
+pub struct SearchLimits {
+    /// Fixed time for this move
+    pub time: Option<f64>,
+    /// Clock of the player to move, from which a share is budgeted for this move
+    pub remaining_time: Option<f64>,
+    pub increment: f64,
+    pub moves_to_go: Option<usize>,
+    pub depth: Option<usize>,
+    pub nodes: Option<usize>,
+    /// Ignores all other limits, so only `stop` ends the search
+    pub infinite: bool,
+    /// Shared with the caller to end a running search early
+    pub stop: Arc<AtomicBool>
+}
+
+impl SearchLimits {
+    pub fn time(seconds: f64) -> SearchLimits {
+        SearchLimits { time: Some(seconds), ..SearchLimits::default() }
+    }
+
+    #[cfg(test)]
+    pub fn depth(depth: usize) -> SearchLimits {
+        SearchLimits { depth: Some(depth), ..SearchLimits::default() }
+    }
+
+    #[cfg(test)]
+    pub fn nodes(nodes: usize) -> SearchLimits {
+        SearchLimits { nodes: Some(nodes), ..SearchLimits::default() }
+    }
+
+    #[cfg(test)]
+    pub fn infinite() -> SearchLimits {
+        SearchLimits { infinite: true, ..SearchLimits::default() }
+    }
+
+    /// The time this move may take, derived from the fixed time and the clock
+    pub fn time_budget(&self) -> Option<f64> {
+        let clock_budget: Option<f64> = self.remaining_time.map(|remaining_time| {
+            let moves_to_go: usize = self.moves_to_go.unwrap_or(DEFAULT_MOVES_TO_GO).max(1);
+            (remaining_time / moves_to_go as f64 + self.increment * 0.75)
+                .min(remaining_time - MOVE_OVERHEAD)
+        });
+
+        match (self.time, clock_budget) {
+            (Some(time), Some(clock_budget)) => Some(time.min(clock_budget)),
+            (time, clock_budget) => time.or(clock_budget)
+        }.map(|time| time.max(0.01))
+    }
+
+    /// True once a search started at `start_time` has to return its best move so far
+    pub fn is_exhausted(&self, start_time: f64, nodes: usize) -> bool {
+        self.stop.load(Ordering::Relaxed)
+        || !self.infinite && (
+            self.nodes.is_some_and(|max_nodes| nodes >= max_nodes)
+            || self.time_budget().is_some_and(|max_time| timer::time() - start_time > max_time)
+        )
+    }
+
+    pub fn allows_depth(&self, depth: usize) -> bool {
+        self.infinite || self.depth.is_none_or(|max_depth| depth <= max_depth)
     }
+}
+
+/// A player that keeps track of the game through `notify_move` and picks moves on request
+pub trait Agent: Send {
+    fn name(&self) -> String;
+
+    /// Makes the agent continue from an arbitrary game, e.g. the initial one or one set up from a FEN
+    fn new_game(&mut self, game: &Game);
+
+    fn notify_move(&mut self, from_pos: &Position, to_pos: &Position);
+
+    fn choose_move(&mut self, limits: &SearchLimits) -> (Position, Position);
+
+    /// Agents are silent unless a reporter is set
+    fn set_reporter(&mut self, _reporter: InfoReporter) {}
+}
+
+/// Prints every search report of the agent named `name` to the console
+pub fn console_reporter(name: &str) -> InfoReporter {
+    let name: String = name.to_string();
+    Arc::new(move |info: &SearchInfo| {
+        println!("\n{}:\n > Execution time {:.3?}\n > best score {}\n > depth: {}\n > nodes: {}",
+            name, info.time, info.format_score(), info.depth, info.nodes);
+    })
+}
+
+/// Collects every search report of `agent` from now on
+#[cfg(test)]
+pub fn capture_reports(agent: &mut dyn Agent) -> Arc<Mutex<Vec<SearchInfo>>> {
+    let reports: Arc<Mutex<Vec<SearchInfo>>> = Arc::default();
+    let reported: Arc<Mutex<Vec<SearchInfo>>> = reports.clone();
+    agent.set_reporter(Arc::new(move |info: &SearchInfo| reported.lock().unwrap().push(info.clone())));
+    reports
+}
 
-    pub fn get_next_turn(&mut self) -> (Position, Position) {
-        let res = match &mut self.agent_type {
-            AgentType::Random => random::get_turn(&self.game),
-            AgentType::Minimax => minimax::get_turn(&self.game, self.max_compute_time, false),
-            AgentType::MonteCarlo(ref mut tree) => {
-                monte_carlo::get_turn(&self.game, tree, self.max_compute_time)
-            },
-            AgentType::NeuralNetwork(network) => {
-                neural_network::get_turn(&self.game, network, false)
-            }
+/// Plays a game between two agents and returns the final position with the moves played.
+/// It ends after `max_steps` moves at the latest, on repetitions and the fifty-move rule, or when an
+/// agent picks an illegal move. Games the Syzygy tables decide end early, `syzygy::adjudicate` tells their result
+pub fn play_match(
+    white_agent: &mut dyn Agent,
+    black_agent: &mut dyn Agent,
+    limits: &SearchLimits,
+    max_steps: usize
+) -> (Game, Vec<(Position, Position)>) {
+    let mut game: Game = Game::new();
+    let mut history: PositionHistory = PositionHistory::new(&game);
+    let mut moves: Vec<(Position, Position)> = vec![];
+    white_agent.new_game(&game);
+    black_agent.new_game(&game);
+
+    while game.get_winner().is_none() && syzygy::adjudicate(&game).is_none()
+    && !history.is_draw(&game) && game.step_counter < max_steps {
+        let (from_pos, to_pos) = if game.player_turn {
+            white_agent.choose_move(limits)
+        } else {
+            black_agent.choose_move(limits)
         };
-        res
+        if !game.valid_turn(&from_pos, &to_pos) {
+            break;
+        }
+
+        game.perform_move(&from_pos, &to_pos);
+        history.push(zobrist::hash_game(&game));
+        white_agent.notify_move(&from_pos, &to_pos);
+        black_agent.notify_move(&from_pos, &to_pos);
+        moves.push((from_pos, to_pos));
+    }
+
+    (game, moves)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::{minimax::MinimaxAgent, monte_carlo::{MctsConfig, MonteCarloAgent}, neural_network::{Network, NeuralNetworkAgent}, random::RandomAgent};
+
+    #[test]
+    fn every_agent_honours_depth_limits() {
+        let game: Game = Game::new();
+        let agents: Vec<Box<dyn Agent>> = vec![
+            Box::new(MinimaxAgent::with_seed(7)),
+            Box::new(MonteCarloAgent::with_config(MctsConfig::default())),
+            Box::new(NeuralNetworkAgent::new(Network::minimal())),
+            Box::new(RandomAgent::new())
+        ];
+
+        for mut agent in agents {
+            agent.new_game(&game);
+            let (from_pos, to_pos) = agent.choose_move(&SearchLimits::depth(3));
+            assert!(game.valid_turn(&from_pos, &to_pos), "{} played an invalid move", agent.name());
+        }
     }
 }
@@ -1,461 +1,822 @@
 use core::f64;
-use std::{sync::{Arc, Mutex}, thread};
+use std::{collections::BinaryHeap, ops::Range, sync::{atomic::{AtomicBool, AtomicU16, AtomicU32, AtomicU64, AtomicUsize, Ordering}, Arc}, thread};
 use good_web_game::timer;
-use rand::seq::IteratorRandom;
 
-use crate::{agent::random, core::{board::Board, game::Game, move_generator::get_all_possible_moves, piece::PieceType, position::Position}};
+use crate::{agent::{history::PositionHistory, playout::{tablebase_reward, Leaf, LeafEvaluator, PlayoutPolicy}, random, syzygy::{self, Syzygy}, tablebase::{self, Tablebases}, Agent, InfoReporter, SearchInfo, SearchLimits}, core::{game::Game, move_generator::get_all_possible_moves, position::Position, zobrist}};
+
+/// Share of the node budget a reused or pruned tree keeps, so that the search has room to grow
+const KEPT_TREE_SHARE: f64 = 0.5;
+/// Losses a thread adds to the nodes on its path until it backs up its reward, so that other threads spread out
+const VIRTUAL_LOSS: u32 = 1;
+
+/// How the move is picked among the children of the root once the search is done
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FinalMovePolicy {
+    /// The most visited child, which is the most robust choice
+    MostVisits,
+    /// The child with the best mean reward, even if it was rarely visited
+    BestMean,
+    /// The most visited child if it also has the best mean reward,
+    /// otherwise the one with the best lower confidence bound
+    RobustMax
+}
+
+impl FinalMovePolicy {
+    pub const NAMES: [&'static str; 3] = ["MostVisits", "BestMean", "RobustMax"];
+
+    pub fn from_name(name: &str) -> Option<FinalMovePolicy> {
+        match name.to_ascii_lowercase().as_str() {
+            "mostvisits" | "robust" => Some(FinalMovePolicy::MostVisits),
+            "bestmean" | "max" => Some(FinalMovePolicy::BestMean),
+            "robustmax" => Some(FinalMovePolicy::RobustMax),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FinalMovePolicy::MostVisits => FinalMovePolicy::NAMES[0],
+            FinalMovePolicy::BestMean => FinalMovePolicy::NAMES[1],
+            FinalMovePolicy::RobustMax => FinalMovePolicy::NAMES[2]
+        }
+    }
+}
 
-const EXPLORATION_C: f64 = 0.7;
-const SIMULATION_DEPTH_LIMIT: usize = 25;
-const THREAD_COUNT: usize = 6;
-const EPSILON_SIMULATION: f64 = 0.5;
+#[derive(Clone, Debug)]
+pub struct MctsConfig {
+    /// Weight of the exploration term of UCT
+    pub exploration_c: f64,
+    /// How leaves are evaluated, unless the agent is given its own `LeafEvaluator`
+    pub playout: PlayoutPolicy,
+    /// Plies a simulation plays before the position is evaluated
+    pub simulation_depth_limit: usize,
+    /// Search threads sharing the tree
+    pub threads: usize,
+    /// Share of random moves in simulations, the others are good captures where possible
+    pub epsilon_simulation: f64,
+    pub final_move: FinalMovePolicy,
+    /// Nodes the tree may hold, each taking 64 bytes as the tree is compacted into a second arena
+    pub node_budget: usize,
+    /// Centipawns a draw is worse than an equal position for the agent
+    pub contempt: isize
+}
+
+impl Default for MctsConfig {
+    fn default() -> MctsConfig {
+        MctsConfig {
+            exploration_c: 0.7,
+            playout: PlayoutPolicy::Capture,
+            simulation_depth_limit: 25,
+            threads: 6,
+            epsilon_simulation: 0.5,
+            final_move: FinalMovePolicy::MostVisits,
+            node_budget: 500_000,
+            contempt: 0
+        }
+    }
+}
 
-#[derive(Clone)]
+impl MctsConfig {
+    /// A pawn is worth 1/40 of a reward in `playout::evaluate_simulation`
+    fn draw_reward(&self) -> f64 {
+        -(self.contempt as f64) / 4000.0
+    }
+}
+
+/// The search tree, which the search threads descend concurrently.
+/// Its nodes sit in a fixed arena, the children of a node in one block, and the root is always the first node.
+/// Rewards and proofs are those of the player `color`, so subtrees are only reused when that player is to move again
 pub struct Tree {
-    tree_state: Arc<Mutex<TreeState>>
+    arena: Arena,
+    /// Target of compactions, swapped with `arena` afterwards
+    spare: Arena,
+    color: bool
 }
 
-pub struct TreeState {
-    pub nodes: Vec<Node>,
-    pub color: bool
+struct Arena {
+    nodes: Box<[Node]>,
+    /// Nodes handed out, which runs past the end once an allocation did not fit
+    len: AtomicUsize
+}
+
+impl Arena {
+    fn new(capacity: usize) -> Arena {
+        Arena {
+            nodes: (0..capacity.max(1)).map(|_| Node::new_root()).collect(),
+            len: AtomicUsize::new(1)
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.len.load(Ordering::Relaxed) > self.nodes.len()
+    }
+
+    /// First index of a block of `count` nodes, none once the arena is full
+    fn allocate(&self, count: usize) -> Option<usize> {
+        let first: usize = self.len.fetch_add(count, Ordering::Relaxed);
+        (first + count <= self.nodes.len()).then_some(first)
+    }
+
+    fn clear(&mut self) {
+        self.nodes[0].reset(None);
+        *self.len.get_mut() = 1;
+    }
 }
 
 impl Tree {
-    pub fn blank() -> Tree {
+    pub fn new(node_budget: usize) -> Tree {
         Tree {
-            tree_state: Arc::new(Mutex::new(TreeState {
-                nodes: vec![],
-                color: false
-            }))
+            arena: Arena::new(node_budget),
+            spare: Arena::new(node_budget),
+            color: false
         }
     }
 
-    pub fn new() -> Tree {
-        let root_node: Node = Node::new_root();
-        Tree {
-            tree_state: Arc::new(Mutex::new(TreeState {
-                nodes: vec![root_node],
-                color: false
-            }))
+    pub fn root(&self) -> &Node {
+        &self.arena.nodes[0]
+    }
+
+    /// Empty until the node is expanded
+    pub fn children(&self, node: &Node) -> &[Node] {
+        &self.arena.nodes[node.child_range()]
+    }
+
+    /// Only the first thread to reach the node expands it, the others simulate from it meanwhile.
+    /// The node stays a leaf if its children do not fit into the arena
+    fn expand(&self, node: &Node, moves: impl FnOnce() -> Vec<(Position, Position)>) {
+        if node.expanding.swap(true, Ordering::Relaxed) {
+            return
+        }
+        let moves: Vec<(Position, Position)> = moves();
+        let Some(first) = self.arena.allocate(moves.len()) else {
+            return
+        };
+        for (child, edge) in self.arena.nodes[first..].iter().zip(&moves) {
+            child.reset(Some(edge));
         }
+        node.set_children(first, moves.len());
     }
 
     pub fn walk_edge_permanently(&mut self, from_pos: &Position, to_pos: &Position) {
-        let index_root_self_op: Option<usize> = self.get_root_node().children
-            .into_iter()
-            .find(|child| {
-                self.tree_state.lock().unwrap().nodes.get(*child).unwrap().edge_to_this_node
-                == Some((from_pos.clone(), to_pos.clone()))
-            });
-        
-        if let Some(index_root_self) = index_root_self_op {
-            let mut tree: Tree = Tree::blank();
-            self.copy_rec_nodes(&mut tree, index_root_self, None);
-            *self = tree;
-        } else {
-            *self = Tree::new();
+        let edge: (Position, Position) = (from_pos.clone(), to_pos.clone());
+        let root: &Node = self.root();
+        match self.children(root).iter().position(|child| child.edge() == edge) {
+            Some(index) => self.compact(root.child_range().start + index),
+            None => self.arena.clear()
+        }
+    }
+
+    pub fn refresh(&mut self, player_turn: bool) {
+        self.arena.clear();
+        self.color = player_turn;
+    }
+
+    /// Makes the subtree of the node at `index` the tree, copying the most visited nodes first.
+    /// Nodes whose children do not fit into the kept share of the budget lose their subtrees
+    fn compact(&mut self, index: usize) {
+        let limit: usize = ((self.arena.nodes.len() as f64 * KEPT_TREE_SHARE) as usize).max(1);
+        let (nodes, spare) = (&self.arena.nodes, &self.spare.nodes);
+        spare[0].copy_from(&nodes[index]);
+        let mut len: usize = 1;
+
+        // Visits, index in the arena and index in the spare arena
+        let mut queue: BinaryHeap<(usize, usize, usize)> = BinaryHeap::from([(nodes[index].total_visits(), index, 0)]);
+        while let Some((_, old_index, new_index)) = queue.pop() {
+            let children: Range<usize> = nodes[old_index].child_range();
+            if children.is_empty() || len + children.len() > limit {
+                continue
+            }
+            for (offset, child) in children.clone().enumerate() {
+                spare[len + offset].copy_from(&nodes[child]);
+                queue.push((nodes[child].total_visits(), child, len + offset));
+            }
+            spare[new_index].expanding.store(true, Ordering::Relaxed);
+            spare[new_index].set_children(len, children.len());
+            len += children.len();
+        }
+
+        *self.spare.len.get_mut() = len;
+        std::mem::swap(&mut self.arena, &mut self.spare);
+    }
+}
+
+/// Game-theoretic value of a node for the root player, once the game ends in it or all its lines are decided
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Proof {
+    Win,
+    Draw,
+    Loss
+}
+
+impl Proof {
+    fn value(&self) -> f64 {
+        match self {
+            Proof::Win => 1.0,
+            Proof::Draw => 0.0,
+            Proof::Loss => -1.0
         }
     }
 
-    fn copy_rec_nodes(&mut self, tree: &mut Tree, index_self: usize, parent_tree_index: Option<usize>) {
-        let new_index: usize = tree.tree_state.lock().unwrap().nodes.len();
-
-        let mut node: Node = self.get_node(index_self);
-        node.parent = parent_tree_index;
-        
-        if let Some(parent_index) = parent_tree_index {
-            let mutex = &mut tree.tree_state.lock().unwrap();
-            let parent: &mut Node = mutex.nodes.get_mut(parent_index).unwrap();
-            parent.children.iter_mut()
-                .for_each(|parents_child| {
-                    if *parents_child == index_self {
-                        *parents_child = new_index;
-                    }
-                })
+    fn reward(&self, draw_reward: f64) -> f64 {
+        match self {
+            Proof::Draw => draw_reward,
+            proof => proof.value()
         }
+    }
+}
 
-        let nodes_children: Vec<usize> = node.children.clone();
-        tree.tree_state.lock().unwrap().nodes.push(node);
+/// Visits, scores and expansion are atomic, so that threads do not wait on each other
+#[derive(Debug)]
+pub struct Node {
+    /// The squares of the move to this node as `from * 64 + to`, unused at the root
+    edge: AtomicU16,
+    /// The first child in the arena above the lowest 16 bits, which count the children.
+    /// Written once all children are, so that other threads only see complete blocks
+    children: AtomicU64,
+    /// Taken by the thread that expands the node
+    expanding: AtomicBool,
+    termination_node: AtomicBool,
+    /// The `Proof` in the lowest two bits, zero if unproven, and the plies until the game ends above
+    proof: AtomicU32,
+    total_visits: AtomicU32,
+    /// Threads currently searching below this node
+    virtual_loss: AtomicU32,
+    /// Sum of the rewards as bits of an f64
+    score: AtomicU64
+}
 
-        nodes_children.into_iter()
-            .for_each(|child| {
-                self.copy_rec_nodes(tree, child, Some(new_index));
-            });
+impl Node {
+    fn new_root() -> Node {
+        Node {
+            edge: AtomicU16::new(0),
+            children: AtomicU64::new(0),
+            expanding: AtomicBool::new(false),
+            termination_node: AtomicBool::new(false),
+            proof: AtomicU32::new(0),
+            total_visits: AtomicU32::new(0),
+            virtual_loss: AtomicU32::new(0),
+            score: AtomicU64::new(0.0f64.to_bits())
+        }
     }
 
-    pub fn refresh(&mut self, player_turn: bool) {
-        self.tree_state.lock().unwrap().nodes = vec![Node::new_root()];
-        self.tree_state.lock().unwrap().color = player_turn;
+    /// Turns the node into an unvisited leaf
+    fn reset(&self, edge: Option<&(Position, Position)>) {
+        let edge: u16 = edge.map_or(0, |(from_pos, to_pos)| from_pos.as_u8() as u16 * 64 + to_pos.as_u8() as u16);
+        self.edge.store(edge, Ordering::Relaxed);
+        self.children.store(0, Ordering::Relaxed);
+        self.expanding.store(false, Ordering::Relaxed);
+        self.termination_node.store(false, Ordering::Relaxed);
+        self.proof.store(0, Ordering::Relaxed);
+        self.total_visits.store(0, Ordering::Relaxed);
+        self.virtual_loss.store(0, Ordering::Relaxed);
+        self.score.store(0.0f64.to_bits(), Ordering::Relaxed);
     }
 
-    pub fn get_node(&self, index: usize) -> Node {
-        self.tree_state.lock().unwrap().nodes.get(index).unwrap().clone()
+    /// Takes the move and statistics of the node, but not its children
+    fn copy_from(&self, node: &Node) {
+        self.reset(None);
+        self.edge.store(node.edge.load(Ordering::Relaxed), Ordering::Relaxed);
+        self.termination_node.store(node.is_termination_node(), Ordering::Relaxed);
+        self.proof.store(node.proof.load(Ordering::Relaxed), Ordering::Relaxed);
+        self.total_visits.store(node.total_visits.load(Ordering::Relaxed), Ordering::Relaxed);
+        self.score.store(node.score.load(Ordering::Relaxed), Ordering::Relaxed);
     }
-    
-    pub fn get_node_children(&self, index: usize) -> Vec<usize> {
-        self.tree_state.lock().unwrap().nodes.get(index).unwrap().children.clone()
+
+    /// The move to this node
+    pub fn edge(&self) -> (Position, Position) {
+        let edge: usize = self.edge.load(Ordering::Relaxed) as usize;
+        (Position::from_usize(edge / 64), Position::from_usize(edge % 64))
     }
 
-    pub fn get_node_parent(&self, index: usize) -> Option<usize> {
-        self.tree_state.lock().unwrap().nodes.get(index).unwrap().parent
+    fn child_range(&self) -> Range<usize> {
+        let children: u64 = self.children.load(Ordering::Acquire);
+        let first: usize = (children >> 16) as usize;
+        first..first + (children & 0xFFFF) as usize
     }
 
-    pub fn get_node_termination_bool(&self, index: usize) -> bool {
-        self.tree_state.lock().unwrap().nodes.get(index).unwrap().termination_node
+    fn set_children(&self, first: usize, count: usize) {
+        self.children.store((first as u64) << 16 | count as u64, Ordering::Release);
     }
 
-    pub fn get_node_edge(&self, index: usize) -> Option<(Position, Position)> {
-        self.tree_state.lock().unwrap().nodes.get(index).unwrap().edge_to_this_node.clone()
+    pub fn is_termination_node(&self) -> bool {
+        self.termination_node.load(Ordering::Relaxed)
     }
 
-    pub fn modfiy_node(&mut self, index: usize, update_fn: impl FnOnce(&mut Node)) {
-        update_fn(self.tree_state.lock().unwrap().nodes.get_mut(index).unwrap())
+    /// The proven outcome and the plies from this node to the end of the game
+    pub fn proof(&self) -> Option<(Proof, usize)> {
+        let packed: u32 = self.proof.load(Ordering::Relaxed);
+        let proof: Proof = match packed & 0b11 {
+            1 => Proof::Win,
+            2 => Proof::Draw,
+            3 => Proof::Loss,
+            _ => return None
+        };
+        Some((proof, (packed >> 2) as usize))
     }
 
-    pub fn get_root_node(&self) -> Node {
-        self.get_node(0)
+    fn prove(&self, proof: Proof, plies: usize) {
+        let kind: u32 = match proof {
+            Proof::Win => 1,
+            Proof::Draw => 2,
+            Proof::Loss => 3
+        };
+        self.proof.store(kind | (plies as u32) << 2, Ordering::Relaxed);
     }
-    
-    pub fn add_new_node(&mut self, new_node: Node) -> usize {
-        let node = &mut self.tree_state.lock().unwrap().nodes;
-        node.push(new_node);
-        node.len() - 1
+
+    pub fn total_visits(&self) -> usize {
+        self.total_visits.load(Ordering::Relaxed) as usize
+    }
+
+    fn virtual_loss(&self) -> usize {
+        self.virtual_loss.load(Ordering::Relaxed) as usize
+    }
+
+    pub fn score(&self) -> f64 {
+        f64::from_bits(self.score.load(Ordering::Relaxed))
+    }
+
+    fn add_virtual_loss(&self) {
+        self.virtual_loss.fetch_add(VIRTUAL_LOSS, Ordering::Relaxed);
+    }
+
+    fn update(&self, reward: f64) {
+        let _ = self.score.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+            Some((f64::from_bits(bits) + reward).to_bits())
+        });
+        self.total_visits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn remove_virtual_loss(&self) {
+        self.virtual_loss.fetch_sub(VIRTUAL_LOSS, Ordering::Relaxed);
     }
 }
 
-#[derive(Clone, PartialEq, Debug)]
-pub struct Node {
-    pub edge_to_this_node: Option<(Position, Position)>,
-    pub parent: Option<usize>,
-    pub children: Vec<usize>,
-    pub termination_node: bool,
-    pub total_visits: usize,
-    pub score: f64
+pub struct MonteCarloAgent {
+    game: Game,
+    history: PositionHistory,
+    tree: Tree,
+    reporter: Option<InfoReporter>,
+    config: MctsConfig,
+    evaluator: Arc<dyn LeafEvaluator>
 }
 
-impl Node {
-    pub fn new(edge: (Position, Position), parent_index: usize) -> Node {
-        Node {
-            edge_to_this_node: Some(edge),
-            parent: Some(parent_index),
-            children: vec![],
-            termination_node: false,
-            total_visits: 1,
-            score: 0.0
-        }
+impl MonteCarloAgent {
+    pub fn with_config(config: MctsConfig) -> MonteCarloAgent {
+        let evaluator: Arc<dyn LeafEvaluator> = config.playout.evaluator(config.simulation_depth_limit, config.epsilon_simulation, None);
+        MonteCarloAgent::with_evaluator(config, evaluator)
     }
 
-    pub fn new_root() -> Node {
-        Node {
-            edge_to_this_node: None,
-            parent: None,
-            children: vec![],
-            termination_node: false,
-            total_visits: 1,
-            score: 0.0
+    /// Evaluates leaves with `evaluator` instead of the playout policy of the config
+    pub fn with_evaluator(config: MctsConfig, evaluator: Arc<dyn LeafEvaluator>) -> MonteCarloAgent {
+        MonteCarloAgent {
+            game: Game::new(),
+            history: PositionHistory::new(&Game::new()),
+            tree: Tree::new(config.node_budget),
+            reporter: None,
+            config,
+            evaluator
         }
     }
 }
 
-pub fn get_turn(initial_game: &Game, tree: &mut Tree, max_compute_time: f64) -> (Position, Position) {
-    if initial_game.player_turn != tree.tree_state.lock().unwrap().color || tree.get_root_node().children.is_empty() {
+impl Agent for MonteCarloAgent {
+    fn name(&self) -> String {
+        String::from("MonteCarlo")
+    }
+
+    fn new_game(&mut self, game: &Game) {
+        self.game = game.clone();
+        self.history = PositionHistory::new(game);
+        self.tree.refresh(game.player_turn);
+    }
+
+    fn notify_move(&mut self, from_pos: &Position, to_pos: &Position) {
+        self.game.perform_move(from_pos, to_pos);
+        self.history.push(zobrist::hash_game(&self.game));
+        self.tree.walk_edge_permanently(from_pos, to_pos);
+    }
+
+    fn choose_move(&mut self, limits: &SearchLimits) -> (Position, Position) {
+        get_turn(&self.game, &self.history, &mut self.tree, limits, &self.config, self.evaluator.as_ref(), self.reporter.as_ref())
+    }
+
+    fn set_reporter(&mut self, reporter: InfoReporter) {
+        self.reporter = Some(reporter);
+    }
+}
+
+/// Node limits count iterations over all threads. Depth limits end the search once a selection reaches that depth,
+/// which a tree of decided lines may never do, so they are best combined with a node or time limit
+pub fn get_turn(
+    initial_game: &Game,
+    history: &PositionHistory,
+    tree: &mut Tree,
+    limits: &SearchLimits,
+    config: &MctsConfig,
+    evaluator: &dyn LeafEvaluator,
+    report: Option<&InfoReporter>
+) -> (Position, Position) {
+    if initial_game.player_turn != tree.color || tree.children(tree.root()).is_empty() {
         tree.refresh(initial_game.player_turn);
     }
 
-    let time_for_stop: f64 = timer::time() + max_compute_time;
+    // In Syzygy positions, the root only gets the moves that keep the best outcome
+    if let Some(root_moves) = syzygy::syzygy().root_moves(initial_game) {
+        tree.refresh(initial_game.player_turn);
+        tree.expand(tree.root(), || root_moves);
+    }
 
-    let mut handles = vec![];
-    
-    for _ in 0..THREAD_COUNT {
-        let game_clone = initial_game.clone();
-        let time_stop = time_for_stop;
-        let mut tree_clone = tree.clone();
-        
-        handles.push(thread::spawn(move || {
-            monte_carlo_iteration(game_clone, &mut tree_clone, time_stop)
-        }));
+    let start_time: f64 = timer::time();
+    let iterations: AtomicUsize = AtomicUsize::new(0);
+    let tree_depth: AtomicUsize = AtomicUsize::new(0);
+    let context: SearchContext = SearchContext { limits, config, evaluator, start_time, iterations: &iterations, tree_depth: &tree_depth };
+
+    loop {
+        let shared_tree: &Tree = tree;
+        thread::scope(|scope| {
+            for _ in 0..config.threads.max(1) {
+                let context: &SearchContext = &context;
+                scope.spawn(move || monte_carlo_iteration(initial_game, history, shared_tree, context));
+            }
+        });
+
+        // The threads stop when the arena is full, so that the least visited subtrees make room
+        if !tree.arena.is_full() {
+            break
+        }
+        tree.compact(0);
     }
-    
-    for handle in handles {
-        let _ = handle.join();
+
+    let children_to_choose: &[Node] = tree.children(tree.root());
+    if children_to_choose.is_empty() {
+        // Stopped before the root was expanded
+        return random::get_turn(initial_game);
     }
 
-    let children_to_choose: &Vec<usize> = &tree.get_root_node().children;
-    let greedy_selection: usize = greedy_selection(tree, 0);
-    let greedy_node_index_in_tree: usize = *children_to_choose.get(greedy_selection).unwrap();
-    let greedy_node: Node = tree.get_node(greedy_node_index_in_tree);
+    // Once the root is proven, its outcome is played for, a win by the shortest and a loss by the longest line
+    let final_node: &Node = &children_to_choose[proven_selection(tree).unwrap_or_else(|| final_selection(tree, config))];
+
+    if let Some(report) = report {
+        // A pawn is worth 1/40 of a reward in `playout::evaluate_simulation`
+        let (score, mate) = match tree.root().proof() {
+            Some((Proof::Win, plies)) => (100_000, Some((plies as isize + 1) / 2)),
+            Some((Proof::Loss, plies)) => (-100_000, Some(-(plies as isize + 1) / 2)),
+            // A child picked before any visit, e.g. the random pick of an immediately stopped search, has no score yet
+            _ if final_node.total_visits() == 0 => (0, None),
+            _ => ((mean_reward(final_node) * 4000.0).round() as isize, None)
+        };
+        report(&SearchInfo {
+            depth: tree_depth.load(Ordering::Relaxed).max(1),
+            score,
+            mate,
+            nodes: tree.root().total_visits(),
+            time: timer::time() - start_time,
+            pv: vec![final_node.edge()]
+        });
+    }
 
-    println!("\nMonte-Carlo:\n > Execution time {:.3?}\n > best score {}\n > nodes simulated: {}",
-        timer::time() - time_for_stop + max_compute_time,
-        greedy_score(&greedy_node),
-        tree.get_root_node().total_visits
-    );
-    greedy_node.edge_to_this_node.clone().unwrap()
+    final_node.edge()
 }
 
-fn monte_carlo_iteration(initial_game: Game, tree: &mut Tree, time_for_stop: f64) {
-    let playing_for: bool = tree.tree_state.lock().unwrap().color;
+/// What the search threads share besides the tree
+struct SearchContext<'a> {
+    limits: &'a SearchLimits,
+    config: &'a MctsConfig,
+    evaluator: &'a dyn LeafEvaluator,
+    start_time: f64,
+    /// Iterations started by all threads
+    iterations: &'a AtomicUsize,
+    /// Plies of the deepest selection
+    tree_depth: &'a AtomicUsize
+}
 
-    while timer::time() < time_for_stop {
-        let mut node_index: usize = 0;
+impl SearchContext<'_> {
+    fn allows_depth(&self) -> bool {
+        self.limits.allows_depth(self.tree_depth.load(Ordering::Relaxed) + 1)
+    }
+}
+
+fn monte_carlo_iteration(initial_game: &Game, initial_history: &PositionHistory, tree: &Tree, context: &SearchContext) {
+    let SearchContext { limits, config, evaluator, start_time, iterations, tree_depth } = *context;
+    let playing_for: bool = tree.color;
+    let draw_reward: f64 = config.draw_reward();
+    let tablebases: &Tablebases = tablebase::tablebases();
+    let syzygy: &Syzygy = syzygy::syzygy();
+    let mut path: Vec<&Node> = Vec::new();
+
+    // A proven root needs no further search
+    while tree.root().proof().is_none()
+    && !tree.arena.is_full()
+    && context.allows_depth()
+    && !limits.is_exhausted(start_time, iterations.fetch_add(1, Ordering::Relaxed)) {
+        let mut node: &Node = tree.root();
         let mut simulation_game: Game = initial_game.clone();
+        let mut history: PositionHistory = initial_history.clone();
+        let mut ply: usize = 0;
+        path.clear();
+        path.push(node);
+        node.add_virtual_loss();
 
         /* Selection */
-        while !tree.get_node_children(node_index).is_empty()
-        && !tree.get_node_termination_bool(node_index) {
-            node_index = *tree.get_node_children(node_index).get(ucb_selection(tree, node_index)).unwrap();
-
-            let edge: (Position, Position) = tree.get_node_edge(node_index).unwrap();
-            simulation_game.perform_move(
-                &edge.0,
-                &edge.1
-            );
+        while !tree.children(node).is_empty() && !node.is_termination_node() && node.proof().is_none() {
+            // Rewards are those of the root player, their opponent picks the lowest
+            let perspective: f64 = if simulation_game.player_turn == playing_for { 1.0 } else { -1.0 };
+            node = &tree.children(node)[ucb_selection(tree, node, perspective, config.exploration_c)];
+            // Other threads see the node as a loss for this one until its reward is backed up
+            node.add_virtual_loss();
+            path.push(node);
+
+            let edge: (Position, Position) = node.edge();
+            simulation_game.perform_move(&edge.0, &edge.1);
+            ply += 1;
+            history.visit(ply, zobrist::hash_game(&simulation_game));
+            if history.is_draw(&simulation_game) {
+                break
+            }
         }
+        tree_depth.fetch_max(ply, Ordering::Relaxed);
 
         /* Expansion */
-        if simulation_game.get_winner().is_some() {
-            tree.modfiy_node(node_index, |node| node.termination_node = true);
+        // Repetitions depend on the path to the node and on the moves played before the root, which change
+        // when the tree is reused, so they are scored for this iteration only and never proven
+        let drawn: bool = ply != 0 && history.is_draw(&simulation_game);
+        // The root is expanded even when it is in the tablebases, so that a move is found
+        let known_reward: Option<f64> = if ply != 0 && !drawn {
+            tablebase_reward(tablebases, syzygy, &simulation_game, playing_for, draw_reward)
+        } else {
+            None
+        };
+        if simulation_game.get_winner().is_some() || known_reward.is_some() {
+            node.termination_node.store(true, Ordering::Relaxed);
+        }
+        match simulation_game.get_winner() {
+            Some(2) => node.prove(Proof::Draw, 0),
+            Some(winner) => node.prove(if (winner == 1) == playing_for { Proof::Win } else { Proof::Loss }, 0),
+            None => {}
         }
 
-        if !tree.get_node_termination_bool(node_index) {
-            let new_children: Vec<usize> = get_all_possible_moves(&simulation_game.board, simulation_game.player_turn)
-                .into_iter()
-                .map(|edge| {
-                    let new_node: Node = Node::new(edge, node_index);
-                    tree.add_new_node(new_node)
-                }).collect::<Vec<usize>>();
-            tree.modfiy_node(node_index, |node| node.children = new_children)
+        if !drawn && !node.is_termination_node() && node.proof().is_none() {
+            tree.expand(node, || get_all_possible_moves(&simulation_game.board, simulation_game.player_turn));
         }
 
         /* Simulation */
-        let reward: f64 = simulation(simulation_game, playing_for);
+        let reward: f64 = if let Some((proof, _)) = node.proof() {
+            proof.reward(draw_reward)
+        } else if drawn {
+            draw_reward
+        } else if let Some(reward) = known_reward {
+            reward
+        } else {
+            evaluator.evaluate(Leaf { game: simulation_game, history: &mut history, ply, playing_for, draw_reward })
+        };
 
         /* Backpropagation */
-        let mut propagation_node: Option<usize> = Some(node_index);
-        
-        while let Some(propagation_node_index) = propagation_node {
-            tree.modfiy_node(propagation_node_index, |node| {
-                node.score += reward;
-                node.total_visits += 1;
-            });
-            propagation_node = tree.get_node_parent(propagation_node_index);
+        for node in path.iter().rev() {
+            node.update(reward);
+            node.remove_virtual_loss();
         }
-    }
-}
 
-fn simulation(mut game: Game, playing_for: bool) -> f64 {
-    let mut current_step: usize = 0;
-    
-    while current_step < SIMULATION_DEPTH_LIMIT && game.get_winner().is_none() {
-        let (from_pos, to_pos) = get_heuristic_random_turn(&game);
-        game.perform_move(&from_pos, &to_pos);
-        current_step += 1;
+        // A new proof may decide the nodes above, up to the first one that stays open
+        if path.last().unwrap().proof().is_some() {
+            for (depth, node) in path.iter().enumerate().rev().skip(1) {
+                let perspective: f64 = if depth % 2 == 0 { 1.0 } else { -1.0 };
+                if !prove_from_children(tree, node, perspective) {
+                    break
+                }
+            }
+        }
     }
-    
-    evaluate_simulation(&game, playing_for)
 }
 
-fn get_heuristic_random_turn(game: &Game) -> (Position, Position) {
-    if rand::random::<f64>() < EPSILON_SIMULATION {
-        random::get_turn(game)
-    } else {
-        get_all_possible_moves(&game.board, game.player_turn)
-            .into_iter()
-            .filter(|(_, to_pos)|
-                game.board.get_piece_at(to_pos).piece_type() != PieceType::Empty
-            ).choose(&mut rand::rng())
-            .unwrap_or(random::get_turn(game))
+/// UCT from the perspective of the player picking among the children, who have not been visited are tried first.
+/// Threads still searching below the node count as losses for that player.
+/// Proven losses are never picked while there are other children
+fn ucb_score(node: &Node, parent_visits: usize, perspective: f64, exploration_c: f64) -> f64 {
+    if node.proof().is_some_and(|(proof, _)| perspective * proof.value() < 0.0) {
+        return f64::NEG_INFINITY
     }
+    let virtual_loss: usize = node.virtual_loss();
+    let visits: usize = node.total_visits() + virtual_loss;
+    if visits == 0 {
+        return f64::INFINITY
+    }
+    (perspective * node.score() - virtual_loss as f64) / visits as f64
+    + exploration_c * ((parent_visits.max(1) as f64).ln() / visits as f64).sqrt()
 }
 
-fn ucb_score(node: &Node, total_tree_visits: usize) -> f64 {
-    node.score / node.total_visits as f64
-    + EXPLORATION_C * ((total_tree_visits as f64).ln() / node.total_visits as f64).sqrt()
-}
-
-fn greedy_score(node: &Node) -> f64 {
-    node.score / node.total_visits as f64
+fn mean_reward(node: &Node) -> f64 {
+    let visits: usize = node.total_visits();
+    if visits == 0 {
+        return f64::NEG_INFINITY
+    }
+    node.score() / visits as f64
 }
 
-fn ucb_selection(tree: &Tree, index_of_parent: usize) -> usize {
-    let root_node_total_visits: usize = tree.get_root_node().total_visits;
-    tree.get_node(index_of_parent).children
+/// Index of the child with the highest score
+fn best_child(children: &[Node], score: impl Fn(&Node) -> f64) -> usize {
+    children
         .iter()
-        .map(|child: &usize| {
-            tree.get_node(*child)
-        }).enumerate()
-        .max_by(|(_, child1), (_, child2)| {
-            let score1: f64 = ucb_score(&child1, root_node_total_visits);
-            let score2: f64 = ucb_score(&child2, root_node_total_visits);
-
-            score1.partial_cmp(&score2).unwrap_or(std::cmp::Ordering::Equal)
+        .map(score)
+        .enumerate()
+        .max_by(|(_, score1), (_, score2)| {
+            score1.partial_cmp(score2).unwrap_or(std::cmp::Ordering::Equal)
         })
         .map(|(index, _)| index)
         .unwrap_or(0)
 }
 
-fn greedy_selection(tree: &Tree, index_of_parent: usize) -> usize {
-    tree.get_node(index_of_parent).children
+fn ucb_selection(tree: &Tree, parent: &Node, perspective: f64, exploration_c: f64) -> usize {
+    let parent_visits: usize = parent.total_visits() + parent.virtual_loss();
+    best_child(tree.children(parent), |child| ucb_score(child, parent_visits, perspective, exploration_c))
+}
+
+/// MCTS-Solver backup: the player to move wins if one child wins for them,
+/// otherwise the node is only decided once all children are, with the best of them.
+/// Returns whether the node is proven
+fn prove_from_children(tree: &Tree, node: &Node, perspective: f64) -> bool {
+    let children: &[Node] = tree.children(node);
+    // Wins are taken by the shortest line and losses delayed by the longest
+    let rank = |(proof, plies): (Proof, usize)| {
+        let value: f64 = perspective * proof.value();
+        (value, if value > 0.0 { -(plies as isize) } else { plies as isize })
+    };
+    let Some((proof, plies)) = children.iter()
+        .filter_map(Node::proof)
+        .max_by(|proof1, proof2| rank(*proof1).partial_cmp(&rank(*proof2)).unwrap_or(std::cmp::Ordering::Equal))
+    else {
+        return false
+    };
+
+    let decided: bool = perspective * proof.value() > 0.0 || children.iter().all(|child| child.proof().is_some());
+    if decided {
+        node.prove(proof, plies + 1);
+    }
+    decided
+}
+
+/// Index of the root child that keeps the proven outcome of the root
+fn proven_selection(tree: &Tree) -> Option<usize> {
+    let (root_proof, root_plies) = tree.root().proof()?;
+    let child_proof: Option<(Proof, usize)> = Some((root_proof, root_plies.checked_sub(1)?));
+    tree.children(tree.root())
         .iter()
-        .map(|child: &usize| {
-            tree.get_node(*child)
-        }).enumerate()
-        .map(|(index, child)| (index, greedy_score(&child)))
-        .max_by(|(_, score1), (_, score2)| {
-            score1.partial_cmp(score2).unwrap_or(std::cmp::Ordering::Equal)
-        })
-        .map(|(index, _)| index)
-        .unwrap_or(0)
+        .position(|child| child.proof() == child_proof)
 }
 
-fn evaluate_simulation(game: &Game, playing_for: bool) -> f64 {
-    match game.get_winner() {
-        Some(2) => 0.0,
-        Some(1) if playing_for => 1.0,
-        Some(0) if !playing_for => 1.0,
-        Some(1) => -1.0,
-        Some(0) => -1.0,
-        _ => evalutate_game(&game.board, playing_for)
+/// Index of the root child to play. Ties in visits go to the better mean reward, which stays below one visit
+fn final_selection(tree: &Tree, config: &MctsConfig) -> usize {
+    let root_children: &[Node] = tree.children(tree.root());
+    let most_visits: usize = best_child(root_children, |child| child.total_visits() as f64 + mean_reward(child).max(-1.0) / 4.0);
+    let best_mean: usize = best_child(root_children, mean_reward);
+    match config.final_move {
+        FinalMovePolicy::MostVisits => most_visits,
+        FinalMovePolicy::BestMean => best_mean,
+        FinalMovePolicy::RobustMax if most_visits == best_mean => most_visits,
+        FinalMovePolicy::RobustMax => {
+            let root_visits: f64 = tree.root().total_visits() as f64;
+            best_child(root_children, |child| {
+                mean_reward(child) - config.exploration_c * (root_visits.ln() / child.total_visits() as f64).sqrt()
+            })
+        }
     }
 }
 
-fn evalutate_game(board: &Board, playing_for: bool) -> f64 {
-    let mut score: f64 = 0.0;
-    let playing_color_layer: u64 = if playing_for {
-        board.layer_color
-    } else {
-        !board.layer_color
-    };
+#[cfg(test)]
+mod tests {
+    use crate::agent::{capture_reports, playout::{CapturePlayout, StaticEvaluation}};
 
-    score += (board.layer_pawn & playing_color_layer).count_ones() as f64;
-    score += (board.layer_knight & playing_color_layer).count_ones() as f64 * 3.0;
-    score += (board.layer_bishop & playing_color_layer).count_ones() as f64 * 3.0;
-    score += (board.layer_rook & playing_color_layer).count_ones() as f64 * 5.0;
-    score += (board.layer_queen & playing_color_layer).count_ones() as f64 * 7.0;
+    use super::*;
 
-    score -= (board.layer_pawn & !playing_color_layer).count_ones() as f64;
-    score -= (board.layer_knight & !playing_color_layer).count_ones() as f64 * 3.0;
-    score -= (board.layer_bishop & !playing_color_layer).count_ones() as f64 * 3.0;
-    score -= (board.layer_rook & !playing_color_layer).count_ones() as f64 * 5.0;
-    score -= (board.layer_queen & !playing_color_layer).count_ones() as f64 * 7.0;
+    fn nodes_in_use(tree: &Tree) -> usize {
+        tree.arena.len.load(Ordering::Relaxed).min(tree.arena.nodes.len())
+    }
 
-    let center_squares: u64 = 0x0000001818000000; // Center 4 squares
-    let center_control = (center_squares & playing_color_layer).count_ones() as f64 * 0.2;
-    score += center_control;
+    #[test]
+    fn walk_edge_permanently_keeps_the_subtree() {
+        let mut tree: Tree = Tree::new(16);
+        let edge: (Position, Position) = (Position::new(6, 4), Position::new(4, 4));
+        tree.expand(tree.root(), || vec![(Position::new(6, 3), Position::new(4, 3)), edge.clone()]);
+        let kept: &Node = &tree.children(tree.root())[1];
+        tree.expand(kept, || vec![(Position::new(1, 4), Position::new(3, 4))]);
+        kept.update(0.5);
+
+        tree.walk_edge_permanently(&edge.0, &edge.1);
+        assert_eq!(tree.root().total_visits(), 1);
+        assert_eq!(tree.children(tree.root()).len(), 1);
+        assert_eq!(tree.children(tree.root())[0].edge(), (Position::new(1, 4), Position::new(3, 4)));
+        assert_eq!(nodes_in_use(&tree), 2);
+
+        tree.walk_edge_permanently(&Position::new(0, 0), &Position::new(1, 0));
+        assert!(tree.children(tree.root()).is_empty());
+    }
 
-    score / 40.0
-}
+    #[test]
+    fn compaction_drops_the_least_visited_subtrees() {
+        let mut tree: Tree = Tree::new(12);
+        tree.expand(tree.root(), || vec![(Position::new(1, 0), Position::new(2, 0)), (Position::new(1, 1), Position::new(2, 1))]);
+        let (visited, rare) = (&tree.children(tree.root())[0], &tree.children(tree.root())[1]);
+        tree.expand(visited, || (2..5).map(|row| (Position::new(6, 0), Position::new(row, 0))).collect());
+        tree.expand(rare, || (2..4).map(|row| (Position::new(6, 1), Position::new(row, 1))).collect());
+        (0..5).for_each(|_| visited.update(1.0));
+        rare.update(-1.0);
+
+        // Half of the budget holds the root, its children and the children of the most visited one
+        tree.compact(0);
+        assert_eq!(nodes_in_use(&tree), 6);
+        let children: &[Node] = tree.children(tree.root());
+        assert_eq!(children[0].total_visits(), 5);
+        assert_eq!(tree.children(&children[0]).len(), 3);
+        assert_eq!(children[1].score(), -1.0);
+        assert!(tree.children(&children[1]).is_empty());
+    }
+
+    #[test]
+    fn search_stays_within_the_node_budget() {
+        let game: Game = Game::new();
+        let mut tree: Tree = Tree::new(300);
+        let config: MctsConfig = MctsConfig { threads: 2, ..MctsConfig::default() };
+        let (from_pos, to_pos) = get_turn(&game, &PositionHistory::new(&game), &mut tree, &SearchLimits::nodes(2000), &config, &CapturePlayout { depth_limit: 4, epsilon: 0.5 }, None);
+
+        assert!(game.valid_turn(&from_pos, &to_pos));
+        assert!(nodes_in_use(&tree) <= 300);
+        assert!(tree.root().total_visits() > 300);
+    }
+
+    #[test]
+    fn threads_back_up_every_iteration() {
+        let game: Game = Game::new();
+        let mut tree: Tree = Tree::new(100_000);
+        let config: MctsConfig = MctsConfig { threads: 4, ..MctsConfig::default() };
+        get_turn(&game, &PositionHistory::new(&game), &mut tree, &SearchLimits::nodes(400), &config, &StaticEvaluation, None);
+
+        let root: &Node = tree.root();
+        let child_visits: usize = tree.children(root).iter().map(Node::total_visits).sum();
+        // The first iteration only expands the root
+        assert_eq!(root.total_visits(), child_visits + 1);
+        assert_eq!(root.total_visits(), 400);
+        assert!(tree.children(root).iter().all(|child| child.virtual_loss() == 0));
+    }
+
+    #[test]
+    fn proves_and_plays_mate() {
+        let mut agent: MonteCarloAgent = MonteCarloAgent::with_config(MctsConfig { threads: 2, ..MctsConfig::default() });
+        agent.new_game(&Game::from_fen("7k/6pp/8/8/8/8/8/R6K w - - 0 1").unwrap());
+        let reports = capture_reports(&mut agent);
+        let best_move: (Position, Position) = agent.choose_move(&SearchLimits::nodes(1_000_000));
+
+        assert_eq!(best_move, (Position::new(0, 0), Position::new(7, 0)));
+        assert_eq!(agent.tree.root().proof(), Some((Proof::Win, 1)));
+        assert_eq!(reports.lock().unwrap().last().unwrap().mate, Some(1));
+    }
 
-// mod tests {
-//     use super::*;
-
-//     #[test]
-//     fn test_walk_edge_permanently() {
-//         // Create a tree with a known structure
-//         let mut tree = Tree::new();
-        
-//         // First, build a small tree with depth 3
-//         // Root (0) has 3 children: A (1), B (2), C (3)
-//         // A has 2 children: D (4), E (5)
-//         // B has 1 child: F (6)
-//         // D has 1 child: G (7)
-        
-//         // Add the first level
-//         let a_node = Node::new((Position::new(0, 0), Position::new(1, 0)), 0);
-//         let b_node = Node::new((Position::new(0, 1), Position::new(1, 1)), 0);
-//         let c_node = Node::new((Position::new(0, 2), Position::new(1, 2)), 0);
-        
-//         let a_index = tree.add_new_node(a_node);
-//         let b_index = tree.add_new_node(b_node);
-//         let c_index = tree.add_new_node(c_node);
-        
-//         // Update root's children
-//         tree.modfiy_node(0, |node| {
-//             node.children = vec![a_index, b_index, c_index];
-//         });
-        
-//         // Add the second level
-//         let d_node = Node::new((Position::new(1, 0), Position::new(2, 0)), a_index);
-//         let e_node = Node::new((Position::new(1, 1), Position::new(2, 1)), a_index);
-//         let f_node = Node::new((Position::new(1, 2), Position::new(2, 2)), b_index);
-        
-//         let d_index = tree.add_new_node(d_node);
-//         let e_index = tree.add_new_node(e_node);
-//         let f_index = tree.add_new_node(f_node);
-        
-//         // Update A and B's children
-//         tree.modfiy_node(a_index, |node| {
-//             node.children = vec![d_index, e_index];
-//         });
-        
-//         tree.modfiy_node(b_index, |node| {
-//             node.children = vec![f_index];
-//         });
-        
-//         // Add the third level
-//         let g_node = Node::new((Position::new(2, 0), Position::new(3, 0)), d_index);
-//         let g_index = tree.add_new_node(g_node);
-        
-//         // Update D's children
-//         tree.modfiy_node(d_index, |node| {
-//             node.children = vec![g_index];
-//         });
-        
-//         // Print initial tree state
-//         println!("Initial tree structure:");
-//         print_tree_structure(&tree);
-        
-//         // First walk: Select B's edge to keep
-//         println!("\nWalking edge B (0,1)->(1,1)");
-//         let from_pos_b = Position::new(0, 1);
-//         let to_pos_b = Position::new(1, 1);
-//         tree.walk_edge_permanently(&from_pos_b, &to_pos_b);
-        
-//         // Print tree state after first walk
-//         println!("\nTree structure after first walk:");
-//         print_tree_structure(&tree);
-        
-//         // Second walk: Select F's edge to keep
-//         println!("\nWalking edge F (1,2)->(2,2)");
-//         let from_pos_f = Position::new(1, 2);
-//         let to_pos_f = Position::new(2, 2);
-//         tree.walk_edge_permanently(&from_pos_f, &to_pos_f);
-        
-//         // Print final tree state
-//         println!("\nFinal tree structure:");
-//         print_tree_structure(&tree);
-        
-//         // Verify the structure is as expected
-//         assert_eq!(tree.tree_state.lock().unwrap().nodes.len(), 2, 
-//             "Tree should have 2 nodes: root and F");
-            
-//         // Check that the remaining child has the correct edge (F)
-//         let root_node = tree.get_root_node();
-//         assert_eq!(root_node.children.len(), 1, "Root should have 1 child");
-//         let f_idx = root_node.children[0];
-//         let f_node = tree.get_node(f_idx);
-//         assert_eq!(f_node.edge_to_this_node, Some((Position::new(1, 2), Position::new(2, 2))),
-//             "Remaining node should have F's edge");
-//     }
-
-//     // Helper function to print the tree structure for debugging
-//     fn print_tree_structure(tree: &Tree) {
-//         let nodes = tree.tree_state.lock().unwrap().nodes.clone();
-//         println!("Tree has {} nodes", nodes.len());
-        
-//         for (i, node) in nodes.iter().enumerate() {
-//             let edge_str = match &node.edge_to_this_node {
-//                 Some((from, to)) => format!("({},{}) -> ({},{})", from.row, from.column, to.row, to.column),
-//                 None => "ROOT".to_string()
-//             };
-            
-//             let parent_str = match node.parent {
-//                 Some(p) => format!("{}", p),
-//                 None => "None".to_string()
-//             };
-            
-//             println!("Node {}: Edge: {}, Parent: {}, Children: {:?}, Visits: {}, Score: {}",
-//                 i, edge_str, parent_str, node.children, node.total_visits, node.score);
-//         }
-//     }
-// }
+    #[test]
+    fn reports_unvisited_moves_as_even() {
+        let mut agent: MonteCarloAgent = MonteCarloAgent::with_config(MctsConfig { threads: 1, ..MctsConfig::default() });
+        let reports = capture_reports(&mut agent);
+        // The only iteration expands the root, so no child gets a visit
+        agent.choose_move(&SearchLimits::nodes(1));
+
+        let scores: Vec<isize> = reports.lock().unwrap().iter().map(|info| info.score).collect();
+        assert_eq!(scores, vec![0]);
+    }
+
+    #[test]
+    fn reused_trees_hold_no_repetition_draws() {
+        // Bare kings shuffle back and forth, so the search is full of repetitions but no game ends
+        let mut agent: MonteCarloAgent = MonteCarloAgent::with_config(MctsConfig { threads: 1, ..MctsConfig::default() });
+        agent.new_game(&Game::from_fen("k7/8/8/8/8/8/8/7K w - - 0 1").unwrap());
+        let draw_proofs = |tree: &Tree| tree.arena.nodes[..nodes_in_use(tree)].iter()
+            .filter(|node| node.proof().is_some_and(|(proof, _)| proof == Proof::Draw) || node.is_termination_node())
+            .count();
+
+        // The played moves return to the start, which then occurred twice
+        for (from_pos, to_pos) in [((0, 7), (0, 6)), ((7, 0), (7, 1)), ((0, 6), (0, 7)), ((7, 1), (7, 0))] {
+            agent.choose_move(&SearchLimits::nodes(3000));
+            assert_eq!(draw_proofs(&agent.tree), 0);
+            agent.notify_move(&Position::new(from_pos.0, from_pos.1), &Position::new(to_pos.0, to_pos.1));
+        }
+        let (from_pos, to_pos) = agent.choose_move(&SearchLimits::nodes(3000));
+        assert!(agent.game.valid_turn(&from_pos, &to_pos));
+        assert_eq!(agent.tree.root().proof(), None);
+        assert_eq!(draw_proofs(&agent.tree), 0);
+    }
+
+    #[test]
+    fn proven_losses_lift_the_parent() {
+        let moves = || vec![(Position::new(0, 0), Position::new(1, 0)), (Position::new(0, 1), Position::new(1, 1))];
+        let tree: Tree = Tree::new(8);
+        tree.expand(tree.root(), moves);
+        tree.children(tree.root())[0].prove(Proof::Loss, 2);
+        // The opponent picks among the children, so one proven loss for the root player decides the node
+        assert!(prove_from_children(&tree, tree.root(), -1.0));
+        assert_eq!(tree.root().proof(), Some((Proof::Loss, 3)));
+
+        let tree: Tree = Tree::new(8);
+        tree.expand(tree.root(), moves);
+        tree.children(tree.root())[0].prove(Proof::Loss, 2);
+        assert!(!prove_from_children(&tree, tree.root(), 1.0));
+        tree.children(tree.root())[1].prove(Proof::Draw, 0);
+        assert!(prove_from_children(&tree, tree.root(), 1.0));
+        assert_eq!(tree.root().proof(), Some((Proof::Draw, 1)));
+    }
+}
@@ -0,0 +1,110 @@
+use crate::core::{board::Board, piece::PieceType, position::Position};
+
+type Move = (Position, Position);
+
+const HASH_MOVE_SCORE: isize = 1_000_000;
+const CAPTURE_SCORE: isize = 500_000;
+const KILLER_SCORES: [isize; 2] = [400_000, 390_000];
+/// Keeps history scores of quiet moves below the killer moves
+const HISTORY_LIMIT: isize = 300_000;
+/// Captures that lose material are searched after all quiet moves
+const LOSING_CAPTURE_SCORE: isize = -100_000;
+
+/// Staged move ordering: hash move, captures that do not lose material by MVV-LVA, killer moves,
+/// quiet moves by history, then losing captures by static exchange evaluation
+pub struct MoveOrdering {
+    killers: Vec<[Option<Move>; 2]>,
+    history: Box<[[isize; 64]; 64]>
+}
+
+impl MoveOrdering {
+    pub fn new() -> MoveOrdering {
+        MoveOrdering { killers: vec![], history: Box::new([[0; 64]; 64]) }
+    }
+
+    /// Sorts `moves` so the most promising ones are searched first
+    pub fn order(&self, board: &Board, moves: &mut [Move], hash_move: Option<&Move>, ply: usize) {
+        moves.sort_by_cached_key(|mv| -self.score(board, mv, hash_move, ply));
+    }
+
+    fn score(&self, board: &Board, mv: &Move, hash_move: Option<&Move>, ply: usize) -> isize {
+        if hash_move == Some(mv) {
+            return HASH_MOVE_SCORE;
+        }
+
+        let (from_pos, to_pos) = mv;
+        let victim: PieceType = board.get_piece_at(to_pos).piece_type();
+        if victim != PieceType::Empty {
+            let exchange: isize = board.static_exchange(from_pos, to_pos);
+            if exchange < 0 {
+                return LOSING_CAPTURE_SCORE + exchange;
+            }
+            let attacker: PieceType = board.get_piece_at(from_pos).piece_type();
+            return CAPTURE_SCORE + victim.value() * 10 - attacker.value() / 10;
+        }
+
+        if let Some(killer_index) = self.killers.get(ply)
+            .and_then(|killers| killers.iter().position(|killer| killer.as_ref() == Some(mv))) {
+            return KILLER_SCORES[killer_index];
+        }
+
+        self.history[from_pos.as_u8() as usize][to_pos.as_u8() as usize]
+    }
+
+    /// Remembers a quiet move that caused a beta cutoff `depth` plies above the horizon
+    pub fn record_cutoff(&mut self, board: &Board, mv: &Move, ply: usize, depth: usize) {
+        let (from_pos, to_pos) = mv;
+        if board.get_piece_at(to_pos).piece_type() != PieceType::Empty {
+            return;
+        }
+
+        if self.killers.len() <= ply {
+            self.killers.resize(ply + 1, [None, None]);
+        }
+        let killers: &mut [Option<Move>; 2] = &mut self.killers[ply];
+        if killers[0].as_ref() != Some(mv) {
+            killers[1] = killers[0].take();
+            killers[0] = Some(mv.clone());
+        }
+
+        let history: &mut isize = &mut self.history[from_pos.as_u8() as usize][to_pos.as_u8() as usize];
+        *history += (depth * depth) as isize;
+        if *history > HISTORY_LIMIT {
+            self.history.iter_mut()
+                .flat_map(|row| row.iter_mut())
+                .for_each(|score| *score /= 2);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::move_generator::get_all_possible_moves;
+
+    #[test]
+    fn orders_by_stage() {
+        let board: Board = Board::from_fen("4k3/8/8/3q1r2/4P3/8/8/4K1N1", "-").unwrap();
+        let mut moves: Vec<Move> = get_all_possible_moves(&board, true);
+        let hash_move: Move = (Position::new(0, 4), Position::new(1, 4));
+        let killer: Move = (Position::new(0, 6), Position::new(2, 7));
+
+        let mut ordering: MoveOrdering = MoveOrdering::new();
+        ordering.record_cutoff(&board, &killer, 3, 2);
+        ordering.order(&board, &mut moves, Some(&hash_move), 3);
+
+        assert_eq!(moves[0], hash_move);
+        assert_eq!(moves[1], (Position::new(3, 4), Position::new(4, 3)));
+        assert_eq!(moves[2], (Position::new(3, 4), Position::new(4, 5)));
+        assert_eq!(moves[3], killer);
+    }
+
+    #[test]
+    fn searches_losing_captures_last() {
+        let board: Board = Board::from_fen("4k3/8/2p5/3p4/8/8/3Q4/4K3", "-").unwrap();
+        let mut moves: Vec<Move> = get_all_possible_moves(&board, true);
+        MoveOrdering::new().order(&board, &mut moves, None, 0);
+
+        assert_eq!(moves.last(), Some(&(Position::new(1, 3), Position::new(4, 3))));
+    }
+}
@@ -6,13 +6,15 @@ use rand::Rng;
 use serde::{Serialize, Deserialize};
 use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
 
-use crate::core::board::Board;
 use crate::core::game::Game;
 use crate::core::move_generator::get_all_possible_moves;
 use crate::core::piece::PieceType;
 use crate::core::position::Position;
 
-use super::minimax;
+use super::eval;
+use super::minimax::{MinimaxAgent, MinimaxConfig};
+use super::transposition::SELF_PLAY_HASH_SIZE_MB;
+use super::{play_match, syzygy, Agent, InfoReporter, SearchInfo, SearchLimits};
 
 const INPUT_NODE_COUNT: usize = 64;
 const OUTPUT_NODE_COUNT: usize = 1;
@@ -218,19 +220,16 @@ impl Network {
 
     /// Return true if network a wins
     fn run_simulation(network_a: &Network, network_b: &Network, a_is_white: bool) -> bool {
-        let mut game: Game = Game::new();
+        let mut agent_a: Box<dyn Agent> = Box::new(NeuralNetworkAgent::new(network_a.clone()));
+        let mut agent_b: Box<dyn Agent> = Box::new(NeuralNetworkAgent::new(network_b.clone()));
 
-        while game.get_winner().is_none() && game.step_counter < 50 {
-            if game.player_turn ^ a_is_white {
-                let b_turn: (Position, Position) = get_turn(&game, &network_b, true);
-                game.perform_move(&b_turn.0, &b_turn.1);
-            } else {
-                let a_turn: (Position, Position) = get_turn(&game, &network_a, true);
-                game.perform_move(&a_turn.0, &a_turn.1);
-            }
-        }
+        let (game, _) = if a_is_white {
+            play_match(agent_a.as_mut(), agent_b.as_mut(), &SearchLimits::time(0.0), 50)
+        } else {
+            play_match(agent_b.as_mut(), agent_a.as_mut(), &SearchLimits::time(0.0), 50)
+        };
 
-        match game.get_winner() {
+        match game.get_winner().or_else(|| syzygy::adjudicate(&game)) {
             Some(1) => a_is_white,
             Some(0) => !a_is_white,
             _ => evaluate_simulation(&game) > 0
@@ -239,17 +238,17 @@ impl Network {
 
     /// Return true if network a wins
     fn run_simulation_minimax(time_for_minimax: f64, network: &Network, net_is_white: bool) -> isize {
-        let mut game: Game = Game::new();
-
-        while game.get_winner().is_none() && game.step_counter < 50 {
-            if game.player_turn ^ net_is_white {
-                let turn: (Position, Position) = minimax::get_turn(&game, time_for_minimax, true);
-                game.perform_move(&turn.0, &turn.1);
-            } else {
-                let turn: (Position, Position) = get_turn(&game, &network, true);
-                game.perform_move(&turn.0, &turn.1);
-            }
-        }
+        let mut network_agent: Box<dyn Agent> = Box::new(NeuralNetworkAgent::new(network.clone()));
+        let mut minimax_agent: Box<dyn Agent> = Box::new(MinimaxAgent::with_config(
+            MinimaxConfig { hash_size_mb: SELF_PLAY_HASH_SIZE_MB, ..MinimaxConfig::default() }
+        ));
+        let limits: SearchLimits = SearchLimits::time(time_for_minimax);
+
+        let (game, _) = if net_is_white {
+            play_match(network_agent.as_mut(), minimax_agent.as_mut(), &limits, 50)
+        } else {
+            play_match(minimax_agent.as_mut(), network_agent.as_mut(), &limits, 50)
+        };
 
         evaluate_simulation(&game)
     }
@@ -289,11 +288,51 @@ pub fn has_correct_format(network: &Network) -> bool {
     node_count == OUTPUT_NODE_COUNT
 }
 
-pub fn get_turn(initial_game: &Game, network: &Network, silent: bool) -> (Position, Position) {
+pub struct NeuralNetworkAgent {
+    game: Game,
+    network: Network,
+    reporter: Option<InfoReporter>
+}
+
+impl NeuralNetworkAgent {
+    pub fn new(network: Network) -> NeuralNetworkAgent {
+        NeuralNetworkAgent { game: Game::new(), network, reporter: None }
+    }
+}
+
+impl Agent for NeuralNetworkAgent {
+    fn name(&self) -> String {
+        String::from("NeuralNetwork")
+    }
+
+    fn new_game(&mut self, game: &Game) {
+        self.game = game.clone();
+    }
+
+    fn notify_move(&mut self, from_pos: &Position, to_pos: &Position) {
+        self.game.perform_move(from_pos, to_pos);
+    }
+
+    /// The network only looks one move ahead, so the limits are not needed
+    fn choose_move(&mut self, _limits: &SearchLimits) -> (Position, Position) {
+        get_turn(&self.game, &self.network, self.reporter.as_ref())
+    }
+
+    fn set_reporter(&mut self, reporter: InfoReporter) {
+        self.reporter = Some(reporter);
+    }
+}
+
+pub fn get_turn(
+    initial_game: &Game,
+    network: &Network,
+    report: Option<&InfoReporter>
+) -> (Position, Position) {
     let start_time: f64 = timer::time();
-    let inital_game_score: f64 = evaluate_game(&initial_game, network);
+    let possible_moves: Vec<(Position, Position)> = get_all_possible_moves(&initial_game.board, initial_game.player_turn);
+    let nodes: usize = possible_moves.len();
 
-    let best_move: (Position, Position) = get_all_possible_moves(&initial_game.board, initial_game.player_turn)
+    let best_move: (Position, Position) = possible_moves
         .into_iter()
         .max_by(|(from_pos_a, to_pos_a), (from_pos_b, to_pos_b)| {
             let mut future_game_a: Game = initial_game.clone();
@@ -314,18 +353,22 @@ pub fn get_turn(initial_game: &Game, network: &Network, silent: bool) -> (Positi
         evaluate_game(&future_game, network)
     };
     
-    if !silent {
-        println!("\nNeural Network:\n > Execution time {:.3?}\n > initial score {}\n > best score after move: {}",
-            timer::time() - start_time,
-            inital_game_score,
-            best_move_score
-        );
+    if let Some(report) = report {
+        report(&SearchInfo {
+            depth: 1,
+            score: win_probability_to_centipawns(best_move_score),
+            mate: None,
+            nodes,
+            time: timer::time() - start_time,
+            pv: vec![best_move.clone()]
+        });
     }
 
     best_move
 }
 
-fn evaluate_game(game: &Game, network: &Network) -> f64 {
+/// Rating of a position between 0 and 1, which `get_turn` maximizes over the moves of the player to move
+pub fn evaluate_game(game: &Game, network: &Network) -> f64 {
     let mut values: Vec<f64> = game.board.iterator_pieces()
         .map(|piece| {
             let mut piece_value: f64 = if piece.get_color() {
@@ -377,36 +420,21 @@ fn evaluate_game(game: &Game, network: &Network) -> f64 {
     *values.first().unwrap()
 }
 
+/// Inverse of the usual logistic mapping between centipawns and winning chances
+fn win_probability_to_centipawns(probability: f64) -> isize {
+    let probability: f64 = probability.clamp(0.001, 0.999);
+    (400.0 * (probability / (1.0 - probability)).log10()).round() as isize
+}
+
 /// Based on sigmoid function
 fn activator(x: f64) -> f64 {
     1.0 / (1.0 + E.powf(-x))
 }
 
 fn evaluate_simulation(game: &Game) -> isize {
-    let board: &Board = &game.board;
-    let mut score: isize = 0;
-
-    score += (board.layer_pawn & board.layer_color).count_ones() as isize * 10;
-    score += (board.layer_knight & board.layer_color).count_ones() as isize * 30;
-    score += (board.layer_bishop & board.layer_color).count_ones() as isize * 30;
-    score += (board.layer_rook & board.layer_color).count_ones() as isize * 50;
-    score += (board.layer_queen & board.layer_color).count_ones() as isize * 70;
-
-    score -= (board.layer_pawn & !board.layer_color).count_ones() as isize * 10;
-    score -= (board.layer_knight & !board.layer_color).count_ones() as isize * 30;
-    score -= (board.layer_bishop & !board.layer_color).count_ones() as isize * 30;
-    score -= (board.layer_rook & !board.layer_color).count_ones() as isize * 50;
-    score -= (board.layer_queen & !board.layer_color).count_ones() as isize * 70;
-
-    let center_control_white =
-        (0b1100000011000000000000000000000000000 & board.layer_color).count_ones() as isize;
-    let center_control_black =
-        (0b1100000011000000000000000000000000000 & !board.layer_color).count_ones() as isize;
-    
-    score += center_control_white;
-    score -= center_control_black;
+    let mut score: isize = eval::evaluate(&game.board);
 
-    let winner: Option<u8> = game.get_winner();
+    let winner: Option<u8> = game.get_winner().or_else(|| syzygy::adjudicate(game));
     if winner.is_some() && winner.unwrap() == 1 {
         score += 1000000 - 1000 * game.step_counter as isize;
     } else if winner.is_some() && winner.unwrap() == 0 {
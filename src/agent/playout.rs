@@ -0,0 +1,335 @@
+use std::sync::Arc;
+use rand::{seq::{IteratorRandom, SliceRandom}, Rng};
+
+use crate::{agent::{eval, history::PositionHistory, neural_network::{self, Network}, random, syzygy::{self, Syzygy}, tablebase::{self, Tablebases, Wdl}}, core::{game::Game, move_generator::get_all_possible_moves, piece::PieceType, position::Position, zobrist}};
+
+type Move = (Position, Position);
+
+/// Reward of wins known from Syzygy tables, whose distance to mate is unknown
+const SYZYGY_WIN_REWARD: f64 = 0.9;
+/// Beyond every evaluation, so that the shallow searches of playouts take mates
+const MATE_SCORE: isize = 1_000_000;
+
+/// A leaf of the MCTS tree, with the positions that lead to it
+pub struct Leaf<'a> {
+    pub game: Game,
+    /// The positions of the game and the tree up to the leaf, which playouts continue
+    pub history: &'a mut PositionHistory,
+    /// Plies from the root to the leaf
+    pub ply: usize,
+    /// The root player, whom the rewards are for
+    pub playing_for: bool,
+    pub draw_reward: f64
+}
+
+/// Estimates the reward of a leaf between -1 and 1, either by playing the game on or by evaluating the leaf itself
+pub trait LeafEvaluator: Send + Sync {
+    fn evaluate(&self, leaf: Leaf) -> f64;
+}
+
+/// How the leaves of an MCTS agent are evaluated, as built from its options
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlayoutPolicy {
+    /// Playouts with good captures by static exchange evaluation, and random moves otherwise
+    Capture,
+    Random,
+    /// Playouts with the best moves of a two ply search
+    Minimax,
+    /// No playout, the static evaluation of the leaf
+    Static,
+    /// No playout, the evaluation of the neural network
+    Neural,
+    /// Capture playouts and static evaluations in equal shares
+    Mixed
+}
+
+impl PlayoutPolicy {
+    pub const NAMES: [&'static str; 6] = ["Capture", "Random", "Minimax", "Static", "Neural", "Mixed"];
+
+    pub fn from_name(name: &str) -> Option<PlayoutPolicy> {
+        match name.to_ascii_lowercase().as_str() {
+            "capture" => Some(PlayoutPolicy::Capture),
+            "random" => Some(PlayoutPolicy::Random),
+            "minimax" => Some(PlayoutPolicy::Minimax),
+            "static" => Some(PlayoutPolicy::Static),
+            "neural" | "neuralnetwork" => Some(PlayoutPolicy::Neural),
+            "mixed" => Some(PlayoutPolicy::Mixed),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PlayoutPolicy::Capture => PlayoutPolicy::NAMES[0],
+            PlayoutPolicy::Random => PlayoutPolicy::NAMES[1],
+            PlayoutPolicy::Minimax => PlayoutPolicy::NAMES[2],
+            PlayoutPolicy::Static => PlayoutPolicy::NAMES[3],
+            PlayoutPolicy::Neural => PlayoutPolicy::NAMES[4],
+            PlayoutPolicy::Mixed => PlayoutPolicy::NAMES[5]
+        }
+    }
+
+    /// Playouts last `depth_limit` plies. Neural evaluations need a network,
+    /// without one the leaves are evaluated statically
+    pub fn evaluator(&self, depth_limit: usize, epsilon: f64, network: Option<Network>) -> Arc<dyn LeafEvaluator> {
+        match (self, network) {
+            (PlayoutPolicy::Capture, _) => Arc::new(CapturePlayout { depth_limit, epsilon }),
+            (PlayoutPolicy::Random, _) => Arc::new(RandomPlayout { depth_limit }),
+            (PlayoutPolicy::Minimax, _) => Arc::new(MinimaxPlayout { depth_limit, search_depth: 2 }),
+            (PlayoutPolicy::Neural, Some(network)) => Arc::new(NeuralEvaluation { network }),
+            (PlayoutPolicy::Static | PlayoutPolicy::Neural, _) => Arc::new(StaticEvaluation),
+            (PlayoutPolicy::Mixed, _) => Arc::new(MixedEvaluation::new(vec![
+                (1.0, Arc::new(CapturePlayout { depth_limit, epsilon })),
+                (1.0, Arc::new(StaticEvaluation))
+            ]))
+        }
+    }
+}
+
+pub struct RandomPlayout {
+    pub depth_limit: usize
+}
+
+impl LeafEvaluator for RandomPlayout {
+    fn evaluate(&self, leaf: Leaf) -> f64 {
+        playout(leaf, self.depth_limit, random::get_turn)
+    }
+}
+
+pub struct CapturePlayout {
+    pub depth_limit: usize,
+    /// Share of random moves, the others are good captures where possible
+    pub epsilon: f64
+}
+
+impl LeafEvaluator for CapturePlayout {
+    fn evaluate(&self, leaf: Leaf) -> f64 {
+        playout(leaf, self.depth_limit, |game| get_heuristic_random_turn(game, self.epsilon))
+    }
+}
+
+pub struct MinimaxPlayout {
+    pub depth_limit: usize,
+    /// Plies each move of the playout is searched
+    pub search_depth: usize
+}
+
+impl LeafEvaluator for MinimaxPlayout {
+    fn evaluate(&self, leaf: Leaf) -> f64 {
+        playout(leaf, self.depth_limit, |game| minimax_turn(game, self.search_depth))
+    }
+}
+
+pub struct StaticEvaluation;
+
+impl LeafEvaluator for StaticEvaluation {
+    fn evaluate(&self, leaf: Leaf) -> f64 {
+        evaluate_simulation(&leaf.game, leaf.playing_for, leaf.draw_reward)
+    }
+}
+
+pub struct NeuralEvaluation {
+    pub network: Network
+}
+
+impl LeafEvaluator for NeuralEvaluation {
+    /// Like `neural_network::get_turn`, the rating is taken as the winning chance of the player who just moved
+    fn evaluate(&self, leaf: Leaf) -> f64 {
+        if leaf.game.get_winner().is_some() {
+            return evaluate_simulation(&leaf.game, leaf.playing_for, leaf.draw_reward)
+        }
+        let reward: f64 = 2.0 * neural_network::evaluate_game(&leaf.game, &self.network) - 1.0;
+        if leaf.game.player_turn != leaf.playing_for { reward } else { -reward }
+    }
+}
+
+/// Evaluates each leaf with one of its evaluators, picked by weight
+pub struct MixedEvaluation {
+    evaluators: Vec<(f64, Arc<dyn LeafEvaluator>)>
+}
+
+impl MixedEvaluation {
+    pub fn new(evaluators: Vec<(f64, Arc<dyn LeafEvaluator>)>) -> MixedEvaluation {
+        MixedEvaluation { evaluators }
+    }
+}
+
+impl LeafEvaluator for MixedEvaluation {
+    fn evaluate(&self, leaf: Leaf) -> f64 {
+        let total_weight: f64 = self.evaluators.iter().map(|(weight, _)| weight).sum();
+        let mut pick: f64 = rand::rng().random::<f64>() * total_weight;
+        for (weight, evaluator) in &self.evaluators {
+            pick -= weight;
+            if pick < 0.0 {
+                return evaluator.evaluate(leaf)
+            }
+        }
+        match self.evaluators.last() {
+            Some((_, evaluator)) => evaluator.evaluate(leaf),
+            None => evaluate_simulation(&leaf.game, leaf.playing_for, leaf.draw_reward)
+        }
+    }
+}
+
+/// Plays up to `depth_limit` plies with the moves of `choose_move` and evaluates the end statically.
+/// Tablebase positions and draws end the playout early with their exact reward
+fn playout(leaf: Leaf, depth_limit: usize, choose_move: impl Fn(&Game) -> Move) -> f64 {
+    let Leaf { mut game, history, ply, playing_for, draw_reward } = leaf;
+    let tablebases: &Tablebases = tablebase::tablebases();
+    let syzygy: &Syzygy = syzygy::syzygy();
+    let mut current_step: usize = 0;
+
+    while current_step < depth_limit && game.get_winner().is_none() {
+        if let Some(reward) = tablebase_reward(tablebases, syzygy, &game, playing_for, draw_reward) {
+            return reward
+        }
+        let (from_pos, to_pos) = choose_move(&game);
+        game.perform_move(&from_pos, &to_pos);
+        current_step += 1;
+
+        history.visit(ply + current_step, zobrist::hash_game(&game));
+        if game.get_winner().is_none() && history.is_draw(&game) {
+            return draw_reward
+        }
+    }
+
+    evaluate_simulation(&game, playing_for, draw_reward)
+}
+
+/// Exact rewards of positions in the tablebases, where faster wins and slower losses are preferred
+pub fn tablebase_reward(tablebases: &Tablebases, syzygy: &Syzygy, game: &Game, playing_for: bool, draw_reward: f64) -> Option<f64> {
+    let (wdl, reward) = match tablebases.probe(game) {
+        Some(probe) => (probe.wdl, 1.0 - probe.dtm as f64 * 0.001),
+        None => (syzygy.probe_wdl(game)?.wdl(), SYZYGY_WIN_REWARD)
+    };
+    let reward: f64 = match wdl {
+        Wdl::Win => reward,
+        Wdl::Draw => return Some(draw_reward),
+        Wdl::Loss => -reward
+    };
+    Some(if game.player_turn == playing_for { reward } else { -reward })
+}
+
+fn get_heuristic_random_turn(game: &Game, epsilon_simulation: f64) -> Move {
+    if rand::random::<f64>() < epsilon_simulation {
+        random::get_turn(game)
+    } else {
+        // Captures that do not lose material by static exchange evaluation
+        get_all_possible_moves(&game.board, game.player_turn)
+            .into_iter()
+            .filter(|(from_pos, to_pos)|
+                game.board.get_piece_at(to_pos).piece_type() != PieceType::Empty
+                && game.board.static_exchange(from_pos, to_pos) >= 0
+            ).choose(&mut rand::rng())
+            .unwrap_or(random::get_turn(game))
+    }
+}
+
+/// The best move of an alpha-beta search over the static evaluation, equally good moves are picked at random
+fn minimax_turn(game: &Game, depth: usize) -> Move {
+    let mut moves: Vec<Move> = get_all_possible_moves(&game.board, game.player_turn);
+    moves.shuffle(&mut rand::rng());
+
+    let mut best: Option<(isize, Move)> = None;
+    for (from_pos, to_pos) in moves {
+        let mut next_game: Game = game.clone();
+        next_game.perform_move(&from_pos, &to_pos);
+        let alpha: isize = best.as_ref().map_or(-MATE_SCORE, |(score, _)| *score);
+        let score: isize = -negamax(&next_game, depth.saturating_sub(1), -MATE_SCORE, -alpha);
+        if best.as_ref().is_none_or(|(best_score, _)| score > *best_score) {
+            best = Some((score, (from_pos, to_pos)));
+        }
+    }
+
+    best.map_or_else(|| random::get_turn(game), |(_, best_move)| best_move)
+}
+
+/// Score for the player to move
+fn negamax(game: &Game, depth: usize, mut alpha: isize, beta: isize) -> isize {
+    let sign: isize = if game.player_turn { 1 } else { -1 };
+    match game.get_winner() {
+        Some(2) => return 0,
+        Some(winner) => return if (winner == 1) == game.player_turn { MATE_SCORE } else { -MATE_SCORE },
+        None => {}
+    }
+    if depth == 0 {
+        return sign * eval::evaluate(&game.board)
+    }
+
+    for (from_pos, to_pos) in get_all_possible_moves(&game.board, game.player_turn) {
+        let mut next_game: Game = game.clone();
+        next_game.perform_move(&from_pos, &to_pos);
+        alpha = alpha.max(-negamax(&next_game, depth - 1, -beta, -alpha));
+        if alpha >= beta {
+            break
+        }
+    }
+    alpha
+}
+
+pub fn evaluate_simulation(game: &Game, playing_for: bool, draw_reward: f64) -> f64 {
+    match game.get_winner() {
+        Some(2) => draw_reward,
+        Some(1) if playing_for => 1.0,
+        Some(0) if !playing_for => 1.0,
+        Some(1) => -1.0,
+        Some(0) => -1.0,
+        _ => {
+            // A pawn is worth 1/40 of a reward
+            let reward: f64 = eval::evaluate(&game.board) as f64 / 4000.0;
+            (if playing_for { reward } else { -reward }).clamp(-1.0, 1.0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    struct Constant {
+        reward: f64,
+        calls: AtomicUsize
+    }
+
+    impl LeafEvaluator for Constant {
+        fn evaluate(&self, _leaf: Leaf) -> f64 {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            self.reward
+        }
+    }
+
+    #[test]
+    fn mixed_evaluation_uses_every_evaluator() {
+        let (win, loss) = (
+            Arc::new(Constant { reward: 1.0, calls: AtomicUsize::new(0) }),
+            Arc::new(Constant { reward: -1.0, calls: AtomicUsize::new(0) })
+        );
+        let mixed: MixedEvaluation = MixedEvaluation::new(vec![(3.0, win.clone()), (1.0, loss.clone())]);
+
+        let game: Game = Game::new();
+        let mut history: PositionHistory = PositionHistory::new(&game);
+        for _ in 0..400 {
+            mixed.evaluate(Leaf { game: game.clone(), history: &mut history, ply: 0, playing_for: true, draw_reward: 0.0 });
+        }
+
+        let wins: usize = win.calls.load(Ordering::Relaxed);
+        assert_eq!(wins + loss.calls.load(Ordering::Relaxed), 400);
+        assert!((200..400).contains(&wins));
+    }
+
+    #[test]
+    fn minimax_playouts_take_hanging_pieces() {
+        let game: Game = Game::from_fen("4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1").unwrap();
+        assert_eq!(minimax_turn(&game, 2), (Position::new(1, 3), Position::new(4, 3)));
+    }
+
+    #[test]
+    fn static_evaluation_rewards_material() {
+        let game: Game = Game::from_fen("4k3/8/8/8/8/8/3Q4/4K3 b - - 0 1").unwrap();
+        let mut history: PositionHistory = PositionHistory::new(&game);
+        let reward: f64 = StaticEvaluation.evaluate(Leaf { game, history: &mut history, ply: 0, playing_for: false, draw_reward: 0.0 });
+        assert!(reward < -0.1);
+    }
+}
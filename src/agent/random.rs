@@ -1,8 +1,36 @@
 use rand::seq::IndexedRandom;
 
-use crate::core::{game::Game, move_generator::get_all_possible_moves, position::Position};
+use crate::{agent::{Agent, SearchLimits}, core::{game::Game, move_generator::get_all_possible_moves, position::Position}};
+
+pub struct RandomAgent {
+    game: Game
+}
+
+impl RandomAgent {
+    pub fn new() -> RandomAgent {
+        RandomAgent { game: Game::new() }
+    }
+}
+
+impl Agent for RandomAgent {
+    fn name(&self) -> String {
+        String::from("Random")
+    }
+
+    fn new_game(&mut self, game: &Game) {
+        self.game = game.clone();
+    }
+
+    fn notify_move(&mut self, from_pos: &Position, to_pos: &Position) {
+        self.game.perform_move(from_pos, to_pos);
+    }
+
+    fn choose_move(&mut self, _limits: &SearchLimits) -> (Position, Position) {
+        get_turn(&self.game)
+    }
+}
 
 pub fn get_turn(game: &Game) -> (Position, Position) {
     let all_moves: Vec<(Position, Position)> = get_all_possible_moves(&game.board, game.player_turn);
     all_moves.choose(&mut rand::rng()).unwrap().clone()
-}
\ No newline at end of file
+}
@@ -0,0 +1,1030 @@
+use std::{collections::HashMap, error::Error, fs, path::{Path, PathBuf}, sync::OnceLock};
+
+use crate::{agent::tablebase::{castling_possible, Material, Wdl}, core::{board::Board, game::Game, move_generator::get_all_possible_moves, move_validator::is_check, piece::PieceType, position::Position}};
+
+type Move = (Position, Position);
+
+pub const WDL_EXTENSION: &str = "rtbw";
+pub const DTZ_EXTENSION: &str = "rtbz";
+/// Largest number of pieces on the board, kings included, that tables are read for
+pub const MAX_PIECES: usize = 5;
+
+const WDL_MAGIC: u32 = 0x5d23e871;
+const DTZ_MAGIC: u32 = 0xa50c66d7;
+
+/// Flags in the first byte of a file
+const SPLIT: u8 = 1;
+const HAS_PAWNS: u8 = 2;
+
+/// Flags of a compressed side of a table
+const STM: u8 = 1;
+const MAPPED: u8 = 2;
+const WIN_PLIES: u8 = 4;
+const LOSS_PLIES: u8 = 8;
+const WIDE: u8 = 16;
+const SINGLE_VALUE: u8 = 128;
+
+/// Ways to place the leading group of a table: both kings, or three unique pieces
+const KINGS_PLACEMENTS: u64 = 462;
+const UNIQUE_PLACEMENTS: u64 = 31332;
+
+/// Outcome for the player to move, where cursed wins and blessed losses are drawn by the fifty-move rule
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SyzygyWdl {
+    Loss,
+    BlessedLoss,
+    Draw,
+    CursedWin,
+    Win
+}
+
+impl SyzygyWdl {
+    fn from_value(value: i32) -> Option<SyzygyWdl> {
+        match value {
+            -2 => Some(SyzygyWdl::Loss),
+            -1 => Some(SyzygyWdl::BlessedLoss),
+            0 => Some(SyzygyWdl::Draw),
+            1 => Some(SyzygyWdl::CursedWin),
+            2 => Some(SyzygyWdl::Win),
+            _ => None
+        }
+    }
+
+    fn value(self) -> i32 {
+        self as i32 - 2
+    }
+
+    /// The outcome for the other player
+    fn opposite(self) -> SyzygyWdl {
+        SyzygyWdl::from_value(-self.value()).unwrap()
+    }
+
+    /// `Game` applies the fifty-move rule, so only clear wins are won
+    pub fn wdl(self) -> Wdl {
+        match self {
+            SyzygyWdl::Win => Wdl::Win,
+            SyzygyWdl::Loss => Wdl::Loss,
+            _ => Wdl::Draw
+        }
+    }
+
+    /// Distance to zeroing of a position right before a capture into this outcome
+    fn dtz_before_zeroing(self) -> i32 {
+        match self {
+            SyzygyWdl::Win => 1,
+            SyzygyWdl::CursedWin => 101,
+            SyzygyWdl::Draw => 0,
+            SyzygyWdl::BlessedLoss => -101,
+            SyzygyWdl::Loss => -1
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TableType {
+    Wdl,
+    Dtz
+}
+
+/// Squares of the leading pieces are encoded with these maps, which follow the Syzygy generator
+struct Encoding {
+    /// Squares below the a1-h8 diagonal to 0..28
+    map_b1h1h7: [u16; 64],
+    /// Squares of the a1-d1-d4 triangle to 0..10, the diagonal last
+    map_a1d1d4: [u16; 64],
+    /// Legal placements of both kings, the first one in the triangle, to 0..462
+    map_kk: [[u16; 64]; 10],
+    binomial: [[u64; 64]; 6]
+}
+
+fn off_diagonal(square: u8) -> i32 {
+    (square / 8) as i32 - (square % 8) as i32
+}
+
+fn encoding() -> &'static Encoding {
+    static ENCODING: OnceLock<Encoding> = OnceLock::new();
+    ENCODING.get_or_init(|| {
+        let mut encoding: Encoding = Encoding {
+            map_b1h1h7: [0; 64],
+            map_a1d1d4: [0; 64],
+            map_kk: [[0; 64]; 10],
+            binomial: [[0; 64]; 6]
+        };
+
+        let mut code: u16 = 0;
+        for square in 0..64 {
+            if off_diagonal(square) < 0 {
+                encoding.map_b1h1h7[square as usize] = code;
+                code += 1;
+            }
+        }
+
+        let triangle: Vec<u8> = (0..32).filter(|square| square % 8 <= 3).collect();
+        code = 0;
+        for square in triangle.iter().filter(|square| off_diagonal(**square) < 0) {
+            encoding.map_a1d1d4[*square as usize] = code;
+            code += 1;
+        }
+        for square in triangle.iter().filter(|square| off_diagonal(**square) == 0) {
+            encoding.map_a1d1d4[*square as usize] = code;
+            code += 1;
+        }
+
+        let mut both_on_diagonal: Vec<(usize, usize)> = vec![];
+        code = 0;
+        for index in 0..10 {
+            // b1 is mapped to 0 like all squares outside of the triangle
+            let Some(first) = (0..28u8).find(|square| encoding.map_a1d1d4[*square as usize] as usize == index && (index > 0 || *square == 1)) else {
+                continue;
+            };
+            for second in 0..64u8 {
+                // The second king may not stand above the diagonal if the first one is on it
+                if kings_adjacent(first, second) || off_diagonal(first) == 0 && off_diagonal(second) > 0 {
+                    continue;
+                } else if off_diagonal(first) == 0 && off_diagonal(second) == 0 {
+                    both_on_diagonal.push((index, second as usize));
+                } else {
+                    encoding.map_kk[index][second as usize] = code;
+                    code += 1;
+                }
+            }
+        }
+        for (index, second) in both_on_diagonal {
+            encoding.map_kk[index][second] = code;
+            code += 1;
+        }
+
+        encoding.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..6.min(n + 1) {
+                encoding.binomial[k][n] = if k > 0 { encoding.binomial[k - 1][n - 1] } else { 0 }
+                    + if k < n { encoding.binomial[k][n - 1] } else { 0 };
+            }
+        }
+
+        encoding
+    })
+}
+
+/// Also true for the same square
+fn kings_adjacent(first: u8, second: u8) -> bool {
+    (first / 8).abs_diff(second / 8) <= 1 && (first % 8).abs_diff(second % 8) <= 1
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(bytes.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn read_u32_big_endian(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+/// One compressed side of a table. Offsets point into the bytes of the file
+#[derive(Default)]
+struct PairsData {
+    flags: u8,
+    /// Piece codes in the order of the encoding, 1 to 6 for white pawns to kings and 8 more for black
+    pieces: Vec<u8>,
+    /// Sizes of the groups of pieces that are encoded together
+    group_len: Vec<usize>,
+    /// Factor of each group in the index, the last entry is the size of the side
+    group_idx: Vec<u64>,
+    size_of_block: u64,
+    span: u64,
+    sparse_index: usize,
+    block_length: usize,
+    block_length_size: usize,
+    data: usize,
+    blocks: u64,
+    /// The stored value of single value sides
+    min_sym_len: u8,
+    lowest_sym: usize,
+    base64: Vec<u64>,
+    /// Number of values each symbol expands to, minus one
+    symlen: Vec<u8>,
+    btree: usize,
+    /// Start of the four value maps of DTZ tables, for wins, losses, cursed wins and blessed losses
+    map_idx: [usize; 4]
+}
+
+impl PairsData {
+    fn new(pieces: Vec<u8>, order: u8, unique_pieces: bool) -> PairsData {
+        // Pieces of the leading group and other equal pieces in a row are encoded together
+        let mut group_len: Vec<usize> = vec![1];
+        let mut first_len: i32 = if unique_pieces { 3 } else { 2 };
+        for index in 1..pieces.len() {
+            first_len -= 1;
+            if first_len > 0 || pieces[index] == pieces[index - 1] {
+                *group_len.last_mut().unwrap() += 1;
+            } else {
+                group_len.push(1);
+            }
+        }
+
+        // The leading group is at position `order` of the index, the others follow their squares
+        let binomial = &encoding().binomial;
+        let mut group_idx: Vec<u64> = vec![0; group_len.len() + 1];
+        let mut free_squares: usize = 64 - group_len[0];
+        let mut factor: u64 = 1;
+        let mut next: usize = 1;
+        let mut position: u8 = 0;
+        while next < group_len.len() || position == order {
+            if position == order {
+                group_idx[0] = factor;
+                factor *= if unique_pieces { UNIQUE_PLACEMENTS } else { KINGS_PLACEMENTS };
+            } else {
+                group_idx[next] = factor;
+                factor *= binomial[group_len[next]][free_squares];
+                free_squares -= group_len[next];
+                next += 1;
+            }
+            position += 1;
+        }
+        group_idx[group_len.len()] = factor;
+
+        PairsData { pieces, group_len, group_idx, ..PairsData::default() }
+    }
+
+    /// Reads the compression parameters at the offset and returns the offset behind them
+    fn read_sizes(&mut self, bytes: &[u8], mut offset: usize) -> Option<usize> {
+        self.flags = *bytes.get(offset)?;
+        if self.flags & SINGLE_VALUE != 0 {
+            self.min_sym_len = *bytes.get(offset + 1)?;
+            return Some(offset + 2)
+        }
+
+        let size: u64 = *self.group_idx.last()?;
+        self.size_of_block = 1u64.checked_shl(u32::from(*bytes.get(offset + 1)?))?;
+        self.span = 1u64.checked_shl(u32::from(*bytes.get(offset + 2)?))?;
+        let padding: usize = *bytes.get(offset + 3)? as usize;
+        self.blocks = read_u32(bytes, offset + 4)? as u64;
+        self.block_length_size = self.blocks as usize + padding;
+        let max_sym_len: u8 = *bytes.get(offset + 8)?;
+        self.min_sym_len = *bytes.get(offset + 9)?;
+        if size == 0 || self.min_sym_len == 0 || max_sym_len < self.min_sym_len || max_sym_len > 32 {
+            return None
+        }
+        offset += 10;
+        self.lowest_sym = offset;
+
+        // Canonical Huffman codes: longer codes have lower values, base64 holds the lowest code
+        // of each length left aligned in 64 bits
+        let lengths: usize = (max_sym_len - self.min_sym_len) as usize + 1;
+        self.base64 = vec![0; lengths];
+        for index in (0..lengths - 1).rev() {
+            let lowest: u64 = read_u16(bytes, self.lowest_sym + 2 * index)? as u64;
+            let next_lowest: u64 = read_u16(bytes, self.lowest_sym + 2 * index + 2)? as u64;
+            self.base64[index] = (self.base64[index + 1] + lowest).wrapping_sub(next_lowest) / 2;
+        }
+        for (index, base) in self.base64.iter_mut().enumerate() {
+            *base = base.checked_shl(64 - index as u32 - self.min_sym_len as u32).unwrap_or(0);
+        }
+        offset += 2 * lengths;
+
+        // Symbols stand for pairs of symbols, the tree leaves hold the values
+        let symbols: usize = read_u16(bytes, offset)? as usize;
+        self.btree = offset + 2;
+        bytes.get(self.btree..self.btree + 3 * symbols)?;
+        self.symlen = vec![0; symbols];
+        let mut visited: Vec<bool> = vec![false; symbols];
+        for symbol in 0..symbols {
+            if !visited[symbol] {
+                self.symlen[symbol] = self.expanded_length(bytes, symbol, &mut visited)?;
+            }
+        }
+
+        Some(self.btree + 3 * symbols + (symbols & 1))
+    }
+
+    fn expanded_length(&mut self, bytes: &[u8], symbol: usize, visited: &mut [bool]) -> Option<u8> {
+        visited[symbol] = true;
+        let (left, right) = self.children(bytes, symbol);
+        if right == 0xFFF {
+            return Some(0)
+        }
+        for child in [left, right] {
+            if !*visited.get(child)? {
+                self.symlen[child] = self.expanded_length(bytes, child, visited)?;
+            }
+        }
+        Some(self.symlen[left].wrapping_add(self.symlen[right]).wrapping_add(1))
+    }
+
+    /// The two symbols a symbol stands for, leaves hold their value on the left
+    fn children(&self, bytes: &[u8], symbol: usize) -> (usize, usize) {
+        let node: &[u8] = &bytes[self.btree + 3 * symbol..self.btree + 3 * symbol + 3];
+        (
+            (((node[1] & 0xF) as usize) << 8) | node[0] as usize,
+            ((node[2] as usize) << 4) | (node[1] >> 4) as usize
+        )
+    }
+
+    /// The value stored at the index
+    fn decompress(&self, bytes: &[u8], index: u64) -> Option<u16> {
+        if self.flags & SINGLE_VALUE != 0 {
+            return Some(self.min_sym_len as u16)
+        }
+
+        // The sparse index points into the blocks every `span` values, the block lengths lead the rest of the way
+        let entry: usize = self.sparse_index + 6 * (index / self.span) as usize;
+        let mut block: usize = read_u32(bytes, entry)? as usize;
+        let mut offset: i64 = read_u16(bytes, entry + 4)? as i64
+            + (index % self.span) as i64 - (self.span / 2) as i64;
+        while offset < 0 {
+            block = block.checked_sub(1)?;
+            offset += read_u16(bytes, self.block_length + 2 * block)? as i64 + 1;
+        }
+        loop {
+            let block_length: i64 = read_u16(bytes, self.block_length + 2 * block)? as i64;
+            if offset <= block_length {
+                break;
+            }
+            offset -= block_length + 1;
+            block += 1;
+        }
+
+        let mut position: usize = self.data + block * self.size_of_block as usize;
+        let mut buffer: u64 = ((read_u32_big_endian(bytes, position)? as u64) << 32) | read_u32_big_endian(bytes, position + 4)? as u64;
+        position += 8;
+        let mut buffer_size: u32 = 64;
+        let mut symbol: usize = loop {
+            let mut length: usize = 0;
+            while buffer < *self.base64.get(length)? {
+                length += 1;
+            }
+            let code_length: u32 = length as u32 + self.min_sym_len as u32;
+            let symbol: usize = ((buffer - self.base64[length]) >> (64 - code_length)) as usize
+                + read_u16(bytes, self.lowest_sym + 2 * length)? as usize;
+            let expanded: i64 = *self.symlen.get(symbol)? as i64 + 1;
+            if offset < expanded {
+                break symbol;
+            }
+
+            offset -= expanded;
+            buffer <<= code_length;
+            buffer_size -= code_length;
+            if buffer_size <= 32 {
+                buffer_size += 32;
+                buffer |= (read_u32_big_endian(bytes, position)? as u64) << (64 - buffer_size);
+                position += 4;
+            }
+        };
+
+        // Expand the pairs down to the leaf holding the value
+        while *self.symlen.get(symbol)? != 0 {
+            let (left, right) = self.children(bytes, symbol);
+            let left_length: i64 = *self.symlen.get(left)? as i64 + 1;
+            if offset < left_length {
+                symbol = left;
+            } else {
+                offset -= left_length;
+                symbol = right;
+            }
+        }
+        Some(self.children(bytes, symbol).0 as u16)
+    }
+}
+
+/// A WDL or DTZ file of a material without pawns
+struct SyzygyTable {
+    table_type: TableType,
+    bytes: Vec<u8>,
+    /// Both colors have the same pieces, so only white to move is stored
+    symmetric: bool,
+    unique_pieces: bool,
+    /// White and black to move, DTZ tables only store one of them
+    sides: Vec<PairsData>
+}
+
+impl SyzygyTable {
+    fn read_from_file(file_path: &Path, material: &Material, table_type: TableType) -> Result<SyzygyTable, Box<dyn Error>> {
+        let bytes: Vec<u8> = fs::read(file_path)?;
+        SyzygyTable::from_bytes(bytes, material, table_type)
+            .ok_or_else(|| format!("{} is no valid Syzygy table", file_path.display()).into())
+    }
+
+    fn from_bytes(bytes: Vec<u8>, material: &Material, table_type: TableType) -> Option<SyzygyTable> {
+        let magic: u32 = if table_type == TableType::Wdl { WDL_MAGIC } else { DTZ_MAGIC };
+        let flags: u8 = *bytes.get(4)?;
+        if read_u32(&bytes, 0)? != magic || flags & HAS_PAWNS != 0 {
+            return None
+        }
+
+        let piece_count: usize = material.piece_count();
+        let unique_pieces: bool = [&material.white, &material.black].iter()
+            .any(|pieces| pieces.iter().any(|piece_type| pieces.iter().filter(|other| *other == piece_type).count() == 1));
+        let side_count: usize = if table_type == TableType::Wdl && flags & SPLIT != 0 { 2 } else { 1 };
+
+        let order: u8 = *bytes.get(5)?;
+        let pieces: &[u8] = bytes.get(6..6 + piece_count)?;
+        let mut sides: Vec<PairsData> = (0..side_count)
+            .map(|side| {
+                let pieces: Vec<u8> = pieces.iter().map(|piece| if side == 0 { piece & 0xF } else { piece >> 4 }).collect();
+                let order: u8 = if side == 0 { order & 0xF } else { order >> 4 };
+                PairsData::new(pieces, order, unique_pieces)
+            })
+            .collect();
+
+        let mut offset: usize = 6 + piece_count;
+        offset += offset & 1;
+        for side in sides.iter_mut() {
+            offset = side.read_sizes(&bytes, offset)?;
+        }
+
+        if table_type == TableType::Dtz {
+            let side: &mut PairsData = &mut sides[0];
+            if side.flags & MAPPED != 0 {
+                if side.flags & WIDE != 0 {
+                    offset += offset & 1;
+                    for map_idx in side.map_idx.iter_mut() {
+                        *map_idx = offset + 2;
+                        offset += 2 * read_u16(&bytes, offset)? as usize + 2;
+                    }
+                } else {
+                    for map_idx in side.map_idx.iter_mut() {
+                        *map_idx = offset + 1;
+                        offset += *bytes.get(offset)? as usize + 1;
+                    }
+                }
+            }
+            offset += offset & 1;
+        }
+
+        for side in sides.iter_mut() {
+            side.sparse_index = offset;
+            let sparse_index_size: u64 = if side.flags & SINGLE_VALUE != 0 { 0 } else { side.group_idx.last()?.div_ceil(side.span) };
+            offset += 6 * sparse_index_size as usize;
+        }
+        for side in sides.iter_mut() {
+            side.block_length = offset;
+            offset += 2 * side.block_length_size;
+        }
+        for side in sides.iter_mut() {
+            offset = offset.next_multiple_of(64);
+            side.data = offset;
+            offset = offset.checked_add(side.blocks.checked_mul(side.size_of_block)? as usize)?;
+            if side.blocks > 0 && offset > bytes.len() {
+                return None
+            }
+        }
+
+        Some(SyzygyTable { table_type, bytes, symmetric: material.white == material.black, unique_pieces, sides })
+    }
+
+    /// Positions are stored with the stronger pieces for white, and for white to move if both have the same pieces.
+    /// Returns if the colors are swapped and if black is to move in the table
+    fn orientation(&self, board: &Board, white_turn: bool) -> (bool, bool) {
+        let flip: bool = Material::from_board(board).is_flipped() || (self.symmetric && !white_turn);
+        (flip, flip == white_turn)
+    }
+
+    fn side(&self, black_to_move: bool) -> &PairsData {
+        &self.sides[if self.sides.len() == 2 && black_to_move { 1 } else { 0 }]
+    }
+
+    /// DTZ tables only store one player to move, unless both have the same pieces
+    fn stores(&self, board: &Board, white_turn: bool) -> bool {
+        let (_, black_to_move) = self.orientation(board, white_turn);
+        self.table_type == TableType::Wdl || self.symmetric || (self.side(black_to_move).flags & STM != 0) == black_to_move
+    }
+
+    /// The raw value of a valid position of this material
+    fn probe(&self, board: &Board, white_turn: bool) -> Option<u16> {
+        let (flip, black_to_move) = self.orientation(board, white_turn);
+        let side: &PairsData = self.side(black_to_move);
+
+        // Pieces with the colors of the table, in the order of its encoding
+        let mut squares: Vec<u8> = vec![];
+        let mut pieces: Vec<u8> = vec![];
+        let mut occupied: u64 = !board.get_empty_layer();
+        while occupied != 0 {
+            let square: u8 = occupied.trailing_zeros() as u8;
+            occupied &= occupied - 1;
+            let piece = board.get_piece_at(&Position::new(square / 8, square % 8));
+            let white: bool = piece.get_color() != flip;
+            squares.push(if flip { square ^ 56 } else { square });
+            pieces.push(piece_code(piece.piece_type()) + if white { 0 } else { 8 });
+        }
+        for index in 0..pieces.len().saturating_sub(1) {
+            if let Some(found) = (index + 1..pieces.len()).find(|other| pieces[*other] == side.pieces[index]) {
+                pieces.swap(index, found);
+                squares.swap(index, found);
+            }
+        }
+        if pieces != side.pieces {
+            return None
+        }
+
+        // Mirror the leading piece into the a1-d1-d4 triangle, and the first one off the diagonal below it
+        if squares[0] % 8 > 3 {
+            squares.iter_mut().for_each(|square| *square ^= 7);
+        }
+        if squares[0] / 8 > 3 {
+            squares.iter_mut().for_each(|square| *square ^= 56);
+        }
+        if let Some(first) = (0..side.group_len[0]).find(|index| off_diagonal(squares[*index]) != 0) {
+            if off_diagonal(squares[first]) > 0 {
+                squares[first..].iter_mut().for_each(|square| *square = ((*square >> 3) | (*square << 3)) & 63);
+            }
+        }
+
+        let index: u64 = self.encode(side, &mut squares)?;
+        let value: u16 = side.decompress(&self.bytes, index)?;
+        Some(value)
+    }
+
+    fn encode(&self, side: &PairsData, squares: &mut [u8]) -> Option<u64> {
+        let encoding: &Encoding = encoding();
+        let rank = |square: u8| (square / 8) as u64;
+
+        let mut index: u64 = if self.unique_pieces {
+            let adjust1: u64 = u64::from(squares[1] > squares[0]);
+            let adjust2: u64 = u64::from(squares[2] > squares[0]) + u64::from(squares[2] > squares[1]);
+            if off_diagonal(squares[0]) != 0 {
+                (encoding.map_a1d1d4[squares[0] as usize] as u64 * 63 + (squares[1] as u64 - adjust1)) * 62
+                    + squares[2] as u64 - adjust2
+            } else if off_diagonal(squares[1]) != 0 {
+                (6 * 63 + rank(squares[0]) * 28 + encoding.map_b1h1h7[squares[1] as usize] as u64) * 62
+                    + squares[2] as u64 - adjust2
+            } else if off_diagonal(squares[2]) != 0 {
+                6 * 63 * 62 + 4 * 28 * 62
+                    + rank(squares[0]) * 7 * 28
+                    + (rank(squares[1]) - adjust1) * 28
+                    + encoding.map_b1h1h7[squares[2] as usize] as u64
+            } else {
+                6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28
+                    + rank(squares[0]) * 7 * 6
+                    + (rank(squares[1]) - adjust1) * 6
+                    + (rank(squares[2]) - adjust2)
+            }
+        } else {
+            if kings_adjacent(squares[0], squares[1]) {
+                return None
+            }
+            encoding.map_kk[encoding.map_a1d1d4[squares[0] as usize] as usize][squares[1] as usize] as u64
+        };
+        index *= side.group_idx[0];
+
+        // The other groups are combinations of the remaining squares
+        let mut group_start: usize = side.group_len[0];
+        for (group, group_len) in side.group_len.iter().enumerate().skip(1) {
+            let (previous, rest) = squares.split_at_mut(group_start);
+            let group_squares: &mut [u8] = &mut rest[..*group_len];
+            group_squares.sort_unstable();
+            let combination: u64 = group_squares.iter().enumerate()
+                .map(|(position, square)| {
+                    let adjust: usize = previous.iter().filter(|other| square > other).count();
+                    encoding.binomial[position + 1][*square as usize - adjust]
+                })
+                .sum();
+            index += combination * side.group_idx[group];
+            group_start += group_len;
+        }
+
+        Some(index)
+    }
+
+    /// Converts the stored value of a DTZ table into plies
+    fn dtz_plies(&self, value: u16, wdl: SyzygyWdl) -> Option<i32> {
+        let side: &PairsData = &self.sides[0];
+        let mut value: i32 = value as i32;
+        if side.flags & MAPPED != 0 {
+            let map: usize = side.map_idx[match wdl {
+                SyzygyWdl::Win => 0,
+                SyzygyWdl::Loss => 1,
+                SyzygyWdl::CursedWin => 2,
+                SyzygyWdl::BlessedLoss => 3,
+                SyzygyWdl::Draw => return Some(0)
+            }];
+            value = if side.flags & WIDE != 0 {
+                read_u16(&self.bytes, map + 2 * value as usize)? as i32
+            } else {
+                *self.bytes.get(map + value as usize)? as i32
+            };
+        }
+
+        let in_moves: bool = match wdl {
+            SyzygyWdl::Win => side.flags & WIN_PLIES == 0,
+            SyzygyWdl::Loss => side.flags & LOSS_PLIES == 0,
+            _ => true
+        };
+        Some(if in_moves { 2 * value + 1 } else { value + 1 })
+    }
+}
+
+fn piece_code(piece_type: PieceType) -> u8 {
+    match piece_type {
+        PieceType::Pawn => 1,
+        PieceType::Knight => 2,
+        PieceType::Bishop => 3,
+        PieceType::Rook => 4,
+        PieceType::Queen => 5,
+        PieceType::King => 6,
+        PieceType::Empty => 0
+    }
+}
+
+/// The files of one material, read on their first probe
+struct TableFiles {
+    material: Material,
+    wdl_path: Option<PathBuf>,
+    dtz_path: Option<PathBuf>,
+    wdl: OnceLock<Option<SyzygyTable>>,
+    dtz: OnceLock<Option<SyzygyTable>>
+}
+
+impl TableFiles {
+    fn table(&self, table_type: TableType) -> Option<&SyzygyTable> {
+        let (path, table) = match table_type {
+            TableType::Wdl => (&self.wdl_path, &self.wdl),
+            TableType::Dtz => (&self.dtz_path, &self.dtz)
+        };
+        table.get_or_init(|| {
+            let path: &PathBuf = path.as_ref()?;
+            SyzygyTable::read_from_file(path, &self.material, table_type)
+                .inspect_err(|error| eprintln!("Skipping Syzygy table: {}", error))
+                .ok()
+        }).as_ref()
+    }
+}
+
+/// Syzygy tables for standard chess. Only tables without pawns are read,
+/// and only positions that are legal in standard chess are probed
+#[derive(Default)]
+pub struct Syzygy {
+    tables: HashMap<String, TableFiles>,
+    max_pieces: usize
+}
+
+impl Syzygy {
+    /// Finds the tables in the directory, they are read once they are probed
+    pub fn open(directory: &str) -> Result<Syzygy, Box<dyn Error>> {
+        let mut syzygy: Syzygy = Syzygy::default();
+        for entry in fs::read_dir(directory)? {
+            let file_path: PathBuf = entry?.path();
+            let extension: Option<&str> = file_path.extension().and_then(|extension| extension.to_str());
+            if extension != Some(WDL_EXTENSION) && extension != Some(DTZ_EXTENSION) {
+                continue;
+            }
+            let Some(material) = file_path.file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| Material::from_name(stem).ok())
+                .filter(|material| !material.has_pawns() && !material.is_flipped() && material.piece_count() <= MAX_PIECES) else {
+                continue;
+            };
+
+            syzygy.max_pieces = syzygy.max_pieces.max(material.piece_count());
+            let files: &mut TableFiles = syzygy.tables.entry(material.name()).or_insert_with(|| TableFiles {
+                material,
+                wdl_path: None,
+                dtz_path: None,
+                wdl: OnceLock::new(),
+                dtz: OnceLock::new()
+            });
+            if extension == Some(WDL_EXTENSION) {
+                files.wdl_path = Some(file_path);
+            } else {
+                files.dtz_path = Some(file_path);
+            }
+        }
+        Ok(syzygy)
+    }
+
+    /// Number of materials with tables
+    pub fn len(&self) -> usize {
+        self.tables.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    /// Outcome of an ongoing game for the player to move, regardless of the fifty-move counter
+    pub fn probe_wdl(&self, game: &Game) -> Option<SyzygyWdl> {
+        if !self.covers(game) {
+            return None
+        }
+        self.search(&game.board, game.player_turn).map(|(wdl, _)| wdl)
+    }
+
+    /// The moves that keep the best outcome. A win has to be reached within the fifty-move rule,
+    /// so only the wins closest to a capture or mate remain, and losses are dragged out as far as possible
+    pub fn root_moves(&self, game: &Game) -> Option<Vec<Move>> {
+        if !self.covers(game) {
+            return None
+        }
+
+        let mut ranked_moves: Vec<(i32, Move)> = vec![];
+        for (from_pos, to_pos) in legal_moves(&game.board, game.player_turn) {
+            let capture: bool = game.board.get_piece_at(&to_pos).piece_type() != PieceType::Empty;
+            let mut board: Board = game.board.clone();
+            board.move_from_to(&from_pos, &to_pos);
+
+            let mut dtz: i32 = if capture {
+                self.search(&board, !game.player_turn)?.0.opposite().dtz_before_zeroing()
+            } else {
+                let dtz: i32 = -self.dtz(&board, !game.player_turn)?;
+                dtz + dtz.signum()
+            };
+            if dtz == 2 && is_mate(&board, !game.player_turn) {
+                dtz = 1;
+            }
+            let halfmove_clock: i32 = if capture { 0 } else { game.halfmove_clock as i32 };
+            let rank: i32 = if dtz > 0 && dtz + halfmove_clock <= 100 {
+                1000 - dtz
+            } else if dtz < 0 && -dtz + halfmove_clock <= 100 {
+                -1000 - dtz
+            } else {
+                0
+            };
+            ranked_moves.push((rank, (from_pos, to_pos)));
+        }
+
+        let best_rank: i32 = ranked_moves.iter().map(|(rank, _)| *rank).max()?;
+        Some(ranked_moves.into_iter().filter(|(rank, _)| *rank == best_rank).map(|(_, mv)| mv).collect())
+    }
+
+    fn covers(&self, game: &Game) -> bool {
+        let board: &Board = &game.board;
+        let piece_count: usize = (!board.get_empty_layer()).count_ones() as usize;
+        !self.is_empty()
+            && piece_count <= self.max_pieces
+            && board.layer_pawn == 0
+            && !castling_possible(board)
+            && game.get_winner().is_none()
+            && is_legal(board, game.player_turn)
+    }
+
+    fn table(&self, board: &Board, table_type: TableType) -> Option<&SyzygyTable> {
+        self.tables.get(&Material::from_board(board).canonical().name())?.table(table_type)
+    }
+
+    /// The best capture is not always stored in the tables, so captures are searched first.
+    /// Also tells if a capture or having no moves decides the outcome
+    fn search(&self, board: &Board, white_turn: bool) -> Option<(SyzygyWdl, bool)> {
+        // Two bare kings have no table
+        if (!board.get_empty_layer()).count_ones() == 2 {
+            return Some((SyzygyWdl::Draw, false))
+        }
+
+        let moves: Vec<Move> = legal_moves(board, white_turn);
+        let mut best: SyzygyWdl = SyzygyWdl::Loss;
+        let mut captures: usize = 0;
+        for (from_pos, to_pos) in moves.iter() {
+            if board.get_piece_at(to_pos).piece_type() == PieceType::Empty {
+                continue;
+            }
+            captures += 1;
+            let mut captured_board: Board = board.clone();
+            captured_board.move_from_to(from_pos, to_pos);
+            let wdl: SyzygyWdl = self.search(&captured_board, !white_turn)?.0.opposite();
+            if wdl > best {
+                best = wdl;
+                if wdl == SyzygyWdl::Win {
+                    return Some((wdl, true))
+                }
+            }
+        }
+
+        let only_captures: bool = captures > 0 && captures == moves.len();
+        let wdl: SyzygyWdl = if only_captures {
+            best
+        } else {
+            let value: u16 = self.table(board, TableType::Wdl)?.probe(board, white_turn)?;
+            SyzygyWdl::from_value(value as i32 - 2)?
+        };
+
+        if captures > 0 && best >= wdl {
+            Some((best, best > SyzygyWdl::Draw || only_captures))
+        } else {
+            Some((wdl, false))
+        }
+    }
+
+    /// Plies to the next capture or mate that keeps the outcome, positive if the player to move wins
+    /// and 0 for draws. Cursed wins and blessed losses are 100 plies further
+    fn dtz(&self, board: &Board, white_turn: bool) -> Option<i32> {
+        let (wdl, decided_by_capture) = self.search(board, white_turn)?;
+        if wdl == SyzygyWdl::Draw {
+            return Some(0)
+        }
+        if decided_by_capture {
+            return Some(wdl.dtz_before_zeroing())
+        }
+
+        let table: &SyzygyTable = self.table(board, TableType::Dtz)?;
+        if table.stores(board, white_turn) {
+            let dtz: i32 = table.dtz_plies(table.probe(board, white_turn)?, wdl)?;
+            let cursed: bool = matches!(wdl, SyzygyWdl::CursedWin | SyzygyWdl::BlessedLoss);
+            return Some((dtz + if cursed { 100 } else { 0 }) * wdl.value().signum())
+        }
+
+        // The table only stores the other player to move, so the best move is searched
+        let mut best: Option<i32> = None;
+        for (from_pos, to_pos) in legal_moves(board, white_turn) {
+            let capture: bool = board.get_piece_at(&to_pos).piece_type() != PieceType::Empty;
+            let mut next_board: Board = board.clone();
+            next_board.move_from_to(&from_pos, &to_pos);
+
+            let mut dtz: i32 = if capture {
+                -self.search(&next_board, !white_turn)?.0.dtz_before_zeroing()
+            } else {
+                -self.dtz(&next_board, !white_turn)?
+            };
+            if dtz == 1 && is_mate(&next_board, !white_turn) {
+                best = Some(1);
+            }
+            if !capture {
+                dtz += dtz.signum();
+            }
+            if dtz.signum() == wdl.value().signum() && best.is_none_or(|best| dtz < best) {
+                best = Some(dtz);
+            }
+        }
+        Some(best.unwrap_or(-1))
+    }
+}
+
+/// Legal in standard chess: the kings do not touch and the player who just moved is not in check
+fn is_legal(board: &Board, white_turn: bool) -> bool {
+    let white_king: u64 = board.layer_king & board.layer_color;
+    let black_king: u64 = board.layer_king & !board.layer_color;
+    white_king.count_ones() == 1
+        && black_king.count_ones() == 1
+        && !kings_adjacent(white_king.trailing_zeros() as u8, black_king.trailing_zeros() as u8)
+        && !is_check(board, white_turn)
+}
+
+fn is_mate(board: &Board, white_turn: bool) -> bool {
+    is_check(board, !white_turn) && legal_moves(board, white_turn).is_empty()
+}
+
+/// Moves of `Game` without those that put the kings next to each other
+fn legal_moves(board: &Board, white_turn: bool) -> Vec<Move> {
+    let mut moves: Vec<Move> = get_all_possible_moves(board, white_turn);
+    moves.dedup();
+    moves.into_iter()
+        .filter(|(from_pos, to_pos)| {
+            let mut next_board: Board = board.clone();
+            next_board.move_from_to(from_pos, to_pos);
+            is_legal(&next_board, !white_turn)
+        })
+        .collect()
+}
+
+static SYZYGY: OnceLock<Syzygy> = OnceLock::new();
+
+/// Sets the directory all agents probe Syzygy tables from, before their first probe
+pub fn init(directory: &str) -> Result<usize, Box<dyn Error>> {
+    let syzygy: Syzygy = Syzygy::open(directory)?;
+    let count: usize = syzygy.len();
+    SYZYGY.set(syzygy).map_err(|_| "Syzygy tables were already set")?;
+    Ok(count)
+}
+
+/// Without `init`, no tables are probed
+pub fn syzygy() -> &'static Syzygy {
+    SYZYGY.get_or_init(Syzygy::default)
+}
+
+/// Result of a game decided by the tables, in the encoding of `Game::get_winner`
+pub fn adjudicate(game: &Game) -> Option<u8> {
+    let wdl: Wdl = syzygy().probe_wdl(game)?.wdl();
+    Some(match wdl {
+        Wdl::Draw => 2,
+        Wdl::Win => u8::from(game.player_turn),
+        Wdl::Loss => u8::from(!game.player_turn)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A KQvK WDL file: white to move either stores a single value or wins at every third index, black to move always loses
+    fn wdl_file(single_value: Option<u8>) -> Vec<u8> {
+        let mut bytes: Vec<u8> = WDL_MAGIC.to_le_bytes().to_vec();
+        // Split, the leading group comes first, then white king, queen and black king for both sides
+        bytes.extend([SPLIT, 0x00, 0x66, 0x55, 0xEE, 0x00]);
+        match single_value {
+            Some(value) => bytes.extend([SINGLE_VALUE, value]),
+            None => {
+                // Blocks of 4096 bytes, one sparse index entry per 32768 values, one block, codes of one bit
+                bytes.extend([0, 12, 15, 0]);
+                bytes.extend(1u32.to_le_bytes());
+                bytes.extend([1, 1]);
+                bytes.extend(0u16.to_le_bytes());
+                // Two leaves holding the values of draws and wins
+                bytes.extend(2u16.to_le_bytes());
+                bytes.extend([0x02, 0xF0, 0xFF, 0x04, 0xF0, 0xFF]);
+            }
+        }
+        bytes.extend([SINGLE_VALUE, 0]);
+
+        if single_value.is_none() {
+            bytes.extend(0u32.to_le_bytes());
+            bytes.extend(16384u16.to_le_bytes());
+            bytes.extend((UNIQUE_PLACEMENTS as u16 - 1).to_le_bytes());
+            bytes.resize(bytes.len().next_multiple_of(64), 0);
+            let mut block: Vec<u8> = vec![0; 4096];
+            for index in (0..UNIQUE_PLACEMENTS as usize).step_by(3) {
+                block[index / 8] |= 0x80 >> (index % 8);
+            }
+            bytes.extend(block);
+        }
+        bytes
+    }
+
+    /// A KQvK DTZ file for white to move, where every win is 7 plies from zeroing
+    fn dtz_file() -> Vec<u8> {
+        let mut bytes: Vec<u8> = DTZ_MAGIC.to_le_bytes().to_vec();
+        bytes.extend([0, 0x00, 0x06, 0x05, 0x0E, 0x00, SINGLE_VALUE, 3]);
+        bytes
+    }
+
+    fn syzygy_in(directory_name: &str, wdl: Vec<u8>) -> Syzygy {
+        let directory: PathBuf = std::env::temp_dir().join(directory_name);
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("KQvK.rtbw"), wdl).unwrap();
+        fs::write(directory.join("KQvK.rtbz"), dtz_file()).unwrap();
+        // Tables with pawns are skipped
+        fs::write(directory.join("KPvK.rtbw"), [0]).unwrap();
+        Syzygy::open(directory.to_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn builds_index_maps() {
+        let encoding: &Encoding = encoding();
+        let kings: Vec<u16> = encoding.map_kk.iter().flatten().copied().filter(|code| *code != 0).collect();
+        assert_eq!(kings.len() as u64, KINGS_PLACEMENTS - 1);
+        assert_eq!(kings.iter().max().copied(), Some(KINGS_PLACEMENTS as u16 - 1));
+
+        // a1, b2, c3 and d4 come last in the triangle
+        assert_eq!(encoding.map_a1d1d4[1], 0);
+        assert_eq!(encoding.map_a1d1d4[27], 9);
+        assert_eq!(encoding.binomial[2][62], 1891);
+
+        // Every placement of the pieces in KQvK stays below the size of the table
+        let syzygy: Syzygy = syzygy_in("chess-ai-syzygy-maps", wdl_file(Some(4)));
+        let table: &SyzygyTable = syzygy.tables["KQvK"].table(TableType::Wdl).unwrap();
+        for (white_king, queen, black_king) in [(0, 1, 63), (27, 36, 0), (4, 60, 13), (18, 0, 45)] {
+            let mut squares: Vec<u8> = vec![white_king, queen, black_king];
+            let index: u64 = table.encode(&table.sides[0], &mut squares).unwrap();
+            assert!(index < UNIQUE_PLACEMENTS);
+        }
+    }
+
+    #[test]
+    fn decompresses_huffman_blocks() {
+        let syzygy: Syzygy = syzygy_in("chess-ai-syzygy-blocks", wdl_file(None));
+        let table: &SyzygyTable = syzygy.tables["KQvK"].table(TableType::Wdl).unwrap();
+        for index in (0..200).chain([UNIQUE_PLACEMENTS - 2, UNIQUE_PLACEMENTS - 1]) {
+            let expected: u16 = if index % 3 == 0 { 4 } else { 2 };
+            assert_eq!(table.sides[0].decompress(&table.bytes, index), Some(expected));
+        }
+        assert_eq!(table.sides[1].decompress(&table.bytes, 5), Some(0));
+    }
+
+    #[test]
+    fn rejects_oversized_blocks() {
+        // Blocks of 2^200 bytes do not fit into the shift, so the table is unusable instead of panicking
+        let mut wdl: Vec<u8> = wdl_file(None);
+        assert_eq!(wdl[11], 12);
+        wdl[11] = 200;
+        let syzygy: Syzygy = syzygy_in("chess-ai-syzygy-oversized", wdl);
+        assert_eq!(syzygy.probe_wdl(&Game::from_fen("8/8/8/3k4/8/8/8/Q6K w - - 0 1").unwrap()), None);
+    }
+
+    #[test]
+    fn probes_tables_without_pawns() {
+        let syzygy: Syzygy = syzygy_in("chess-ai-syzygy-probes", wdl_file(Some(4)));
+        assert_eq!(syzygy.len(), 1);
+
+        let probe = |fen: &str| syzygy.probe_wdl(&Game::from_fen(fen).unwrap());
+        assert_eq!(probe("8/8/8/3k4/8/8/8/Q6K w - - 0 1"), Some(SyzygyWdl::Win));
+        assert_eq!(probe("8/8/8/3k4/8/8/8/Q6K b - - 0 1"), Some(SyzygyWdl::Loss));
+        assert_eq!(probe("8/8/8/3K4/8/8/8/q6k b - - 0 1"), Some(SyzygyWdl::Win));
+        // The queen can be captured
+        assert_eq!(probe("8/8/8/8/8/8/1k6/Q6K b - - 0 1"), Some(SyzygyWdl::Draw));
+        // Touching kings and a check for the player who just moved are not covered
+        assert_eq!(probe("8/8/8/8/8/8/6kQ/7K w - - 0 1"), None);
+        assert_eq!(probe("3k4/8/8/8/8/8/8/3Q3K w - - 0 1"), None);
+        assert_eq!(probe("8/8/8/3k4/8/8/8/Q3K3 w - - 0 1"), Some(SyzygyWdl::Win));
+        // Without a table
+        assert_eq!(probe("8/8/8/3k4/8/8/8/R3K3 w - - 0 1"), None);
+
+        // Black to move is not stored, so the distance comes from the best reply
+        let game: Game = Game::from_fen("8/8/8/3k4/8/8/8/Q6K w - - 0 1").unwrap();
+        assert_eq!(syzygy.dtz(&game.board, true), Some(7));
+        assert_eq!(syzygy.dtz(&game.board, false), Some(-8));
+
+        // Moves next to the black king lose the queen
+        let root_moves: Vec<Move> = syzygy.root_moves(&game).unwrap();
+        assert!(root_moves.contains(&(Position::new(0, 0), Position::new(4, 0))));
+        assert!(!root_moves.contains(&(Position::new(0, 0), Position::new(3, 3))));
+    }
+}
@@ -0,0 +1,487 @@
+use std::{collections::HashMap, error::Error, fs, path::Path, sync::OnceLock};
+
+use crate::core::{board::Board, game::Game, piece::PieceType, zobrist::CASTLING_SQUARES};
+
+/// Tables are written here by `chess-ai tablebase` and probed by all agents, if the directory exists
+pub const TABLEBASE_DIR: &str = "data/tablebases";
+pub const TABLE_EXTENSION: &str = "tb";
+/// Largest number of pieces on the board, kings included, that tables are generated for
+pub const MAX_PIECES: usize = 4;
+
+const MAGIC: &[u8; 4] = b"CATB";
+/// Order of the pieces in the names of the tables, strongest first
+const PIECE_ORDER: [PieceType; 5] = [PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight, PieceType::Pawn];
+const PIECE_LETTERS: [char; 5] = ['Q', 'R', 'B', 'N', 'P'];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Wdl {
+    Win,
+    Draw,
+    Loss
+}
+
+/// Outcome of a position with perfect play, for the player to move.
+/// The rules are those of `Game`, so positions that only draw by promotion or by the fifty-move rule elsewhere may be decided
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Probe {
+    pub wdl: Wdl,
+    /// Plies until mate, 0 for draws
+    pub dtm: usize
+}
+
+impl Probe {
+    pub const DRAW: Probe = Probe { wdl: Wdl::Draw, dtm: 0 };
+
+    /// Tables store 0 for draws and the distance to mate plus 1 otherwise.
+    /// Wins take an odd number of plies, as the winner makes the last move
+    pub fn from_value(value: u8) -> Probe {
+        match value {
+            0 => Probe::DRAW,
+            value => {
+                let dtm: usize = value as usize - 1;
+                Probe { wdl: if dtm % 2 == 1 { Wdl::Win } else { Wdl::Loss }, dtm }
+            }
+        }
+    }
+
+    pub fn to_value(self) -> u8 {
+        match self.wdl {
+            Wdl::Draw => 0,
+            _ => (self.dtm + 1) as u8
+        }
+    }
+
+    /// The outcome for the player who moves into this position
+    pub fn parent(self) -> Probe {
+        match self.wdl {
+            Wdl::Win => Probe { wdl: Wdl::Loss, dtm: self.dtm + 1 },
+            Wdl::Draw => Probe::DRAW,
+            Wdl::Loss => Probe { wdl: Wdl::Win, dtm: self.dtm + 1 }
+        }
+    }
+}
+
+/// The pieces besides the kings, each color's in `PIECE_ORDER`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Material {
+    pub white: Vec<PieceType>,
+    pub black: Vec<PieceType>
+}
+
+impl Material {
+    /// Names like `KBNvK`, the `v` may be left out
+    pub fn from_name(name: &str) -> Result<Material, String> {
+        let name: String = name.to_ascii_uppercase().replace('V', "");
+        let Some(rest) = name.strip_prefix('K') else {
+            return Err(format!("Material '{}' does not start with a king", name));
+        };
+        let Some((white, black)) = rest.split_once('K') else {
+            return Err(format!("Material '{}' needs two kings", name));
+        };
+
+        let parse = |letters: &str| -> Result<Vec<PieceType>, String> {
+            let mut pieces: Vec<PieceType> = letters.chars()
+                .map(|letter| PIECE_LETTERS.iter().position(|piece_letter| *piece_letter == letter)
+                    .map(|order| PIECE_ORDER[order])
+                    .ok_or(format!("Unknown piece '{}' in material '{}'", letter, name)))
+                .collect::<Result<_, _>>()?;
+            pieces.sort_by_key(piece_order);
+            Ok(pieces)
+        };
+        Ok(Material { white: parse(white)?, black: parse(black)? })
+    }
+
+    pub fn from_board(board: &Board) -> Material {
+        let pieces = |color_layer: u64| -> Vec<PieceType> {
+            PIECE_ORDER.iter()
+                .flat_map(|piece_type| {
+                    let count: u32 = (piece_layer(board, *piece_type) & color_layer).count_ones();
+                    (0..count).map(|_| *piece_type)
+                })
+                .collect()
+        };
+        Material { white: pieces(board.layer_color), black: pieces(!board.layer_color) }
+    }
+
+    pub fn name(&self) -> String {
+        let letters = |pieces: &[PieceType]| -> String {
+            pieces.iter().map(|piece_type| PIECE_LETTERS[piece_order(piece_type)]).collect()
+        };
+        format!("K{}vK{}", letters(&self.white), letters(&self.black))
+    }
+
+    pub fn piece_count(&self) -> usize {
+        2 + self.white.len() + self.black.len()
+    }
+
+    pub fn has_pawns(&self) -> bool {
+        self.white.contains(&PieceType::Pawn) || self.black.contains(&PieceType::Pawn)
+    }
+
+    /// Tables are stored with the stronger pieces for white, other positions are probed with the colors swapped
+    pub fn is_flipped(&self) -> bool {
+        let strength = |pieces: &[PieceType]| -> (usize, Vec<usize>) {
+            (pieces.len(), pieces.iter().map(|piece_type| PIECE_ORDER.len() - piece_order(piece_type)).collect())
+        };
+        strength(&self.black) > strength(&self.white)
+    }
+
+    pub fn canonical(&self) -> Material {
+        if self.is_flipped() {
+            Material { white: self.black.clone(), black: self.white.clone() }
+        } else {
+            self.clone()
+        }
+    }
+
+    /// Materials after one of the pieces was captured, as stored in their tables
+    pub fn captures(&self) -> Vec<Material> {
+        let mut materials: Vec<Material> = vec![];
+        for white in [true, false] {
+            let pieces: &Vec<PieceType> = if white { &self.white } else { &self.black };
+            for index in 0..pieces.len() {
+                let mut material: Material = self.clone();
+                if white { material.white.remove(index); } else { material.black.remove(index); }
+                let material: Material = material.canonical();
+                if !materials.contains(&material) {
+                    materials.push(material);
+                }
+            }
+        }
+        materials
+    }
+
+    /// All tables with at most `max_pieces` pieces, kings included, the smaller ones first
+    pub fn all(max_pieces: usize) -> Vec<Material> {
+        let mut materials: Vec<Material> = vec![Material { white: vec![], black: vec![] }];
+        let mut index: usize = 0;
+        while index < materials.len() {
+            let material: Material = materials[index].clone();
+            index += 1;
+            if material.piece_count() >= max_pieces {
+                continue;
+            }
+
+            for piece_type in PIECE_ORDER {
+                for white in [true, false] {
+                    let mut larger: Material = material.clone();
+                    let pieces: &mut Vec<PieceType> = if white { &mut larger.white } else { &mut larger.black };
+                    pieces.push(piece_type);
+                    pieces.sort_by_key(piece_order);
+                    let larger: Material = larger.canonical();
+                    if !materials.contains(&larger) {
+                        materials.push(larger);
+                    }
+                }
+            }
+        }
+        materials
+    }
+}
+
+fn piece_order(piece_type: &PieceType) -> usize {
+    PIECE_ORDER.iter().position(|order_type| order_type == piece_type).unwrap_or(PIECE_ORDER.len())
+}
+
+fn piece_layer(board: &Board, piece_type: PieceType) -> u64 {
+    match piece_type {
+        PieceType::Pawn => board.layer_pawn,
+        PieceType::Knight => board.layer_knight,
+        PieceType::Bishop => board.layer_bishop,
+        PieceType::Rook => board.layer_rook,
+        PieceType::Queen => board.layer_queen,
+        PieceType::King => board.layer_king,
+        PieceType::Empty => board.get_empty_layer()
+    }
+}
+
+/// Tables only hold positions without castling rights
+pub fn castling_possible(board: &Board) -> bool {
+    CASTLING_SQUARES.iter().any(|(king_square, rook_square)| {
+        let castling_mask: u64 = (0b1 << king_square) | (0b1 << rook_square);
+        board.layer_not_moved & (board.layer_king | board.layer_rook) & castling_mask == castling_mask
+    })
+}
+
+/// Mirrors the board vertically and swaps the colors, which the rules of `Game` are symmetric to
+pub fn flip_colors(board: &Board) -> Board {
+    let occupied: u64 = !board.get_empty_layer();
+    let mut layers: [u64; 8] = board.export().map(u64::swap_bytes);
+    layers[0] = !layers[0] & occupied.swap_bytes();
+    Board::import(layers)
+}
+
+/// Square after one of the 8 symmetries of the board: a transposition followed by mirroring the files and rows
+fn transform(square: u8, symmetry: usize) -> u8 {
+    let (mut row, mut column) = (square / 8, square % 8);
+    if symmetry & 0b100 != 0 {
+        (row, column) = (column, row);
+    }
+    if symmetry & 0b001 != 0 {
+        column = 7 - column;
+    }
+    if symmetry & 0b010 != 0 {
+        row = 7 - row;
+    }
+    row * 8 + column
+}
+
+/// Solved positions of one material. The white king is moved into a1-d1-d4 by the symmetries of the board,
+/// or into the files a to d if pawns forbid mirroring the rows. Castling rights are never part of a table
+pub struct Table {
+    pub material: Material,
+    /// Pieces besides the kings with their color, in the order of their squares in an index
+    pieces: Vec<(PieceType, bool)>,
+    symmetries: usize,
+    /// Index of every square the white king can be moved to by the symmetries
+    king_slots: [Option<usize>; 64],
+    king_slot_count: usize,
+    values: Vec<u8>
+}
+
+impl Table {
+    /// A table with all positions drawn
+    pub fn new(material: Material) -> Table {
+        let pieces: Vec<(PieceType, bool)> = material.white.iter().map(|piece_type| (*piece_type, true))
+            .chain(material.black.iter().map(|piece_type| (*piece_type, false)))
+            .collect();
+        let has_pawns: bool = material.has_pawns();
+
+        let mut king_slots: [Option<usize>; 64] = [None; 64];
+        let mut king_slot_count: usize = 0;
+        for (square, slot) in king_slots.iter_mut().enumerate() {
+            let (row, column) = (square / 8, square % 8);
+            if column <= 3 && (has_pawns || row <= column) {
+                *slot = Some(king_slot_count);
+                king_slot_count += 1;
+            }
+        }
+
+        let size: usize = 2 * king_slot_count * 64usize.pow(pieces.len() as u32 + 1);
+        Table {
+            material,
+            pieces,
+            symmetries: if has_pawns { 2 } else { 8 },
+            king_slots,
+            king_slot_count,
+            values: vec![0; size]
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn value(&self, index: usize) -> u8 {
+        self.values[index]
+    }
+
+    pub fn set_value(&mut self, index: usize, value: u8) {
+        self.values[index] = value;
+    }
+
+    pub fn values(&self) -> &[u8] {
+        &self.values
+    }
+
+    /// Index of the position, which is the same for all of its mirror images.
+    /// The board has to hold exactly the material of the table
+    pub fn index(&self, board: &Board, white_turn: bool) -> Option<usize> {
+        let squares: Vec<u8> = self.squares(board)?;
+
+        (0..self.symmetries)
+            .filter_map(|symmetry| {
+                let mut transformed: Vec<u8> = squares.iter().map(|square| transform(*square, symmetry)).collect();
+                let king_slot: usize = self.king_slots[transformed[0] as usize]?;
+
+                // Equal pieces are interchangeable, so their squares are sorted
+                let mut run_start: usize = 0;
+                while run_start < self.pieces.len() {
+                    let run_end: usize = (run_start..self.pieces.len())
+                        .find(|index| self.pieces[*index] != self.pieces[run_start])
+                        .unwrap_or(self.pieces.len());
+                    transformed[2 + run_start..2 + run_end].sort_unstable();
+                    run_start = run_end;
+                }
+
+                let index: usize = transformed[1..].iter()
+                    .fold(usize::from(white_turn) * self.king_slot_count + king_slot, |index, square| index * 64 + *square as usize);
+                Some(index)
+            })
+            .min()
+    }
+
+    /// The position behind an index, None for indices of impossible positions and for those
+    /// that are stored under another index of their mirror images
+    pub fn position(&self, index: usize) -> Option<(Board, bool)> {
+        let mut remainder: usize = index;
+        let mut squares: Vec<u8> = vec![0; self.pieces.len() + 2];
+        for square in squares[1..].iter_mut().rev() {
+            *square = (remainder % 64) as u8;
+            remainder /= 64;
+        }
+        let king_slot: usize = remainder % self.king_slot_count;
+        let white_turn: bool = remainder / self.king_slot_count == 1;
+        squares[0] = self.king_slots.iter().position(|slot| *slot == Some(king_slot))? as u8;
+
+        let occupied: u64 = squares.iter().fold(0, |layer, square| layer | 0b1 << square);
+        if occupied.count_ones() as usize != squares.len() {
+            return None
+        }
+
+        let mut layers: [u64; 8] = [0; 8];
+        layers[0] = 0b1 << squares[0];
+        layers[7] = (0b1 << squares[0]) | (0b1 << squares[1]);
+        for ((piece_type, white), square) in self.pieces.iter().zip(&squares[2..]) {
+            // Pawns never stand on their own first row
+            let row: u8 = square / 8;
+            if *piece_type == PieceType::Pawn && (*white && row == 0 || !*white && row == 7) {
+                return None
+            }
+            let layer_index: usize = match piece_type {
+                PieceType::Pawn => 2,
+                PieceType::Knight => 3,
+                PieceType::Bishop => 4,
+                PieceType::Rook => 5,
+                PieceType::Queen => 6,
+                _ => 7
+            };
+            layers[layer_index] |= 0b1 << square;
+            if *white {
+                layers[0] |= 0b1 << square;
+            }
+        }
+
+        let board: Board = Board::import(layers);
+        (self.index(&board, white_turn) == Some(index)).then_some((board, white_turn))
+    }
+
+    pub fn probe(&self, board: &Board, white_turn: bool) -> Option<Probe> {
+        self.index(board, white_turn).map(|index| Probe::from_value(self.values[index]))
+    }
+
+    /// Squares of the white king, the black king and the other pieces in table order
+    fn squares(&self, board: &Board) -> Option<Vec<u8>> {
+        let white_king: u64 = board.layer_king & board.layer_color;
+        let black_king: u64 = board.layer_king & !board.layer_color;
+        if white_king.count_ones() != 1 || black_king.count_ones() != 1 {
+            return None
+        }
+
+        let mut squares: Vec<u8> = vec![white_king.trailing_zeros() as u8, black_king.trailing_zeros() as u8];
+        let mut previous: Option<(PieceType, bool)> = None;
+        for (piece_type, white) in &self.pieces {
+            if previous == Some((*piece_type, *white)) {
+                continue;
+            }
+            previous = Some((*piece_type, *white));
+            let color_layer: u64 = if *white { board.layer_color } else { !board.layer_color };
+            let mut layer: u64 = piece_layer(board, *piece_type) & color_layer;
+            while layer != 0 {
+                squares.push(layer.trailing_zeros() as u8);
+                layer &= layer - 1;
+            }
+        }
+
+        let occupied: u64 = !board.get_empty_layer();
+        (squares.len() == occupied.count_ones() as usize && squares.len() == self.pieces.len() + 2).then_some(squares)
+    }
+
+    pub fn read_from_file(file_path: &str) -> Result<Table, Box<dyn Error>> {
+        let file_name: &str = Path::new(file_path).file_stem().and_then(|stem| stem.to_str()).unwrap_or_default();
+        let mut table: Table = Table::new(Material::from_name(file_name)?);
+
+        let bytes: Vec<u8> = fs::read(file_path)?;
+        let Some(values) = bytes.strip_prefix(MAGIC.as_slice()) else {
+            return Err(format!("{} is no tablebase file", file_path).into());
+        };
+        if values.len() != table.size() {
+            return Err(format!("{} holds {} positions instead of {}", file_path, values.len(), table.size()).into());
+        }
+        table.values.copy_from_slice(values);
+        Ok(table)
+    }
+
+    /// Written to `<directory>/<name>.tb`
+    pub fn write_to_directory(&self, directory: &str) -> Result<String, Box<dyn Error>> {
+        fs::create_dir_all(directory)?;
+        let file_path: String = Path::new(directory)
+            .join(format!("{}.{}", self.material.name(), TABLE_EXTENSION))
+            .to_string_lossy()
+            .into_owned();
+        fs::write(&file_path, [MAGIC.as_slice(), &self.values].concat())?;
+        Ok(file_path)
+    }
+}
+
+/// All tables available to the agents
+#[derive(Default)]
+pub struct Tablebases {
+    tables: HashMap<String, Table>
+}
+
+impl Tablebases {
+    /// Reads every table in the directory
+    pub fn load(directory: &str) -> Result<Tablebases, Box<dyn Error>> {
+        let mut tablebases: Tablebases = Tablebases::default();
+        for entry in fs::read_dir(directory)? {
+            let file_path = entry?.path();
+            if file_path.extension().is_some_and(|extension| extension == TABLE_EXTENSION) {
+                tablebases.insert(Table::read_from_file(&file_path.to_string_lossy())?);
+            }
+        }
+        Ok(tablebases)
+    }
+
+    pub fn insert(&mut self, table: Table) {
+        self.tables.insert(table.material.name(), table);
+    }
+
+    pub fn contains(&self, material: &Material) -> bool {
+        self.tables.contains_key(&material.name())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    /// Outcome of an ongoing game for the player to move. Positions with castling rights are not covered
+    pub fn probe(&self, game: &Game) -> Option<Probe> {
+        if self.is_empty() {
+            return None
+        }
+        if castling_possible(&game.board) || game.get_winner().is_some() {
+            return None
+        }
+        self.probe_board(&game.board, game.player_turn)
+    }
+
+    pub fn probe_board(&self, board: &Board, white_turn: bool) -> Option<Probe> {
+        if self.tables.is_empty() || (!board.get_empty_layer()).count_ones() as usize > MAX_PIECES {
+            return None
+        }
+
+        let material: Material = Material::from_board(board);
+        if material.is_flipped() {
+            let table: &Table = self.tables.get(&material.canonical().name())?;
+            table.probe(&flip_colors(board), !white_turn)
+        } else {
+            self.tables.get(&material.name())?.probe(board, white_turn)
+        }
+    }
+}
+
+static TABLEBASES: OnceLock<Tablebases> = OnceLock::new();
+
+/// The tables all agents probe, read from `TABLEBASE_DIR` on first use if it exists
+pub fn tablebases() -> &'static Tablebases {
+    TABLEBASES.get_or_init(|| {
+        if !Path::new(TABLEBASE_DIR).is_dir() {
+            return Tablebases::default()
+        }
+        Tablebases::load(TABLEBASE_DIR).unwrap_or_else(|error| {
+            eprintln!("Playing without tablebases, as {} could not be read: {}", TABLEBASE_DIR, error);
+            Tablebases::default()
+        })
+    })
+}
@@ -0,0 +1,163 @@
+use std::{mem::size_of, sync::atomic::{AtomicU64, Ordering}};
+
+use crate::core::position::Position;
+
+type Move = (Position, Position);
+
+pub const DEFAULT_HASH_SIZE_MB: usize = 16;
+/// For agents of self-play games, which are many and only think briefly
+pub const SELF_PLAY_HASH_SIZE_MB: usize = 4;
+
+/// How the stored score relates to the true value of the position
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Bound {
+    Exact,
+    /// The search failed high, the true value is at least the score
+    Lower,
+    /// The search failed low, the true value is at most the score
+    Upper
+}
+
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub depth: usize,
+    pub bound: Bound,
+    pub score: isize,
+    pub best_move: Option<Move>
+}
+
+/// Fixed-size hash table of searched positions, indexed by the Zobrist hash.
+/// Search threads share it without locks: a slot holds its packed entry and the key XOR that entry,
+/// so an entry torn by two threads writing at once no longer matches its key and is ignored
+pub struct TranspositionTable {
+    slots: Vec<Slot>
+}
+
+#[derive(Default)]
+struct Slot {
+    checked_key: AtomicU64,
+    data: AtomicU64
+}
+
+const DEPTH_SHIFT: u32 = 32;
+const BOUND_SHIFT: u32 = 40;
+const MOVE_SHIFT: u32 = 42;
+const HAS_MOVE: u64 = 1 << 54;
+/// Set in every stored entry, so that empty slots never match
+const OCCUPIED: u64 = 1 << 63;
+
+impl Entry {
+    /// Score in bits 0 to 31, depth up to 255 in bits 32 to 39, bound in bits 40 and 41,
+    /// origin and target square of the best move in bits 42 to 53
+    fn pack(&self) -> u64 {
+        let score: u64 = self.score.clamp(i32::MIN as isize, i32::MAX as isize) as i32 as u32 as u64;
+        let depth: u64 = self.depth.min(u8::MAX as usize) as u64;
+        let bound: u64 = match self.bound {
+            Bound::Exact => 0,
+            Bound::Lower => 1,
+            Bound::Upper => 2
+        };
+        let best_move: u64 = self.best_move.as_ref().map_or(0, |(from_pos, to_pos)| {
+            let square = |position: &Position| (position.row * 8 + position.column) as u64;
+            HAS_MOVE | (square(from_pos) << MOVE_SHIFT) | (square(to_pos) << (MOVE_SHIFT + 6))
+        });
+        OCCUPIED | score | (depth << DEPTH_SHIFT) | (bound << BOUND_SHIFT) | best_move
+    }
+
+    fn unpack(data: u64) -> Option<Entry> {
+        if data & OCCUPIED == 0 {
+            return None
+        }
+        let square = |bits: u64| Position::new(((bits >> 3) & 0b111) as u8, (bits & 0b111) as u8);
+        Some(Entry {
+            depth: ((data >> DEPTH_SHIFT) & 0xFF) as usize,
+            bound: match (data >> BOUND_SHIFT) & 0b11 {
+                0 => Bound::Exact,
+                1 => Bound::Lower,
+                _ => Bound::Upper
+            },
+            score: data as u32 as i32 as isize,
+            best_move: (data & HAS_MOVE != 0).then(|| (square(data >> MOVE_SHIFT), square(data >> (MOVE_SHIFT + 6))))
+        })
+    }
+}
+
+impl TranspositionTable {
+    pub fn new(size_mb: usize) -> TranspositionTable {
+        let slot_count: usize = (size_mb.max(1) * 1024 * 1024 / size_of::<Slot>()).max(1);
+        TranspositionTable { slots: (0..slot_count).map(|_| Slot::default()).collect() }
+    }
+
+    pub fn clear(&mut self) {
+        self.slots.iter_mut().for_each(|slot| *slot = Slot::default());
+    }
+
+    pub fn probe(&self, key: u64) -> Option<Entry> {
+        let slot: &Slot = &self.slots[self.index(key)];
+        let data: u64 = slot.data.load(Ordering::Relaxed);
+        if slot.checked_key.load(Ordering::Relaxed) ^ data != key {
+            return None
+        }
+        Entry::unpack(data)
+    }
+
+    /// Replaces entries of other positions and shallower searches of the same position
+    pub fn store(&self, key: u64, depth: usize, bound: Bound, score: isize, best_move: Option<Move>) {
+        let entry: Option<Entry> = self.probe(key);
+        if entry.as_ref().is_some_and(|entry| entry.depth > depth) {
+            return;
+        }
+
+        // Keep the known best move if this search did not find one
+        let best_move: Option<Move> = best_move.or_else(|| entry.and_then(|entry| entry.best_move));
+        let data: u64 = Entry { depth, bound, score, best_move }.pack();
+        let slot: &Slot = &self.slots[self.index(key)];
+        slot.data.store(data, Ordering::Relaxed);
+        slot.checked_key.store(key ^ data, Ordering::Relaxed);
+    }
+
+    fn index(&self, key: u64) -> usize {
+        (key % self.slots.len() as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_deeper_entries() {
+        let table: TranspositionTable = TranspositionTable::new(1);
+        let best_move: Move = (Position::new(1, 4), Position::new(3, 4));
+
+        table.store(42, 3, Bound::Exact, 10, Some(best_move.clone()));
+        table.store(42, 1, Bound::Lower, 50, None);
+        assert_eq!(table.probe(42).unwrap().score, 10);
+
+        table.store(42, 4, Bound::Upper, -5, None);
+        let entry: Entry = table.probe(42).unwrap();
+        assert_eq!((entry.depth, entry.bound, entry.best_move.clone()), (4, Bound::Upper, Some(best_move)));
+        assert!(table.probe(43).is_none());
+    }
+
+    #[test]
+    fn packs_every_field() {
+        let table: TranspositionTable = TranspositionTable::new(1);
+        let best_move: Move = (Position::new(7, 6), Position::new(5, 5));
+
+        table.store(7, 300, Bound::Lower, -999_990, Some(best_move.clone()));
+        let entry: Entry = table.probe(7).unwrap();
+        assert_eq!((entry.depth, entry.bound, entry.score, entry.best_move), (255, Bound::Lower, -999_990, Some(best_move)));
+    }
+
+    #[test]
+    fn ignores_torn_entries() {
+        let table: TranspositionTable = TranspositionTable::new(1);
+        table.store(42, 3, Bound::Exact, 10, None);
+
+        // Another thread wrote its data, but not yet its key
+        let slot: &Slot = &table.slots[table.index(42)];
+        slot.data.store(Entry { depth: 5, bound: Bound::Upper, score: -20, best_move: None }.pack(), Ordering::Relaxed);
+        assert!(table.probe(42).is_none());
+    }
+}
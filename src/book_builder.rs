@@ -0,0 +1,357 @@
+use std::{collections::BTreeMap, error::Error, fs};
+
+use crate::{
+    agent::{self, book::{self, BookEntry, BookFormat, PolyglotKeys}, syzygy, transposition::SELF_PLAY_HASH_SIZE_MB, Agent, SearchLimits},
+    core::{game::Game, position::Position},
+    protocol::{self, AgentOptions}
+};
+
+type Move = (Position, Position);
+
+const USAGE: &str = "Usage: chess-ai book <output file> [PGN files...] [--selfplay <games>] [--agent <name>] \
+    [--movetime <ms>] [--plies <plies>] [--min-count <games>] [--hash <MB>]";
+/// Moves played less often are left out of the book
+const DEFAULT_MIN_COUNT: usize = 2;
+const DEFAULT_MOVE_TIME_MS: u64 = 100;
+/// Self-play games still running after this many plies are scored as draws
+const MAX_SELF_PLAY_STEPS: usize = 300;
+
+/// Games played so far with a move, counted for the player who made it
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MoveStats {
+    pub count: usize,
+    pub wins: usize,
+    pub draws: usize,
+    pub losses: usize
+}
+
+impl MoveStats {
+    /// `result` is 1 for a win of the moving player, 0.5 for a draw and 0 for a loss
+    fn add(&mut self, result: f64) {
+        self.count += 1;
+        match result {
+            result if result > 0.5 => self.wins += 1,
+            result if result < 0.5 => self.losses += 1,
+            _ => self.draws += 1
+        }
+    }
+
+    /// Points per game for the moving player
+    pub fn score(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.count.max(1) as f64
+    }
+
+    /// Half points, like the books Polyglot builds
+    fn weight(&self) -> usize {
+        2 * self.wins + self.draws
+    }
+}
+
+/// A game from a PGN file, replayed as far as `Game` supports its moves
+pub struct PgnGame {
+    pub start: Game,
+    pub moves: Vec<Move>,
+    /// 1 if white won, 0.5 for a draw and 0 if black won, None for unfinished games
+    pub result: Option<f64>
+}
+
+/// Collects the moves of many games per position and turns them into book entries
+pub struct BookBuilder {
+    format: BookFormat,
+    keys: Option<PolyglotKeys>,
+    /// Moves after this many plies from the start of the game are not collected
+    plies: usize,
+    stats: BTreeMap<(u64, u16), MoveStats>,
+    games: usize
+}
+
+impl BookBuilder {
+    /// Polyglot books need the keys, native books are hashed without them
+    pub fn new(keys: Option<PolyglotKeys>, plies: usize) -> BookBuilder {
+        let format: BookFormat = if keys.is_some() { BookFormat::Polyglot } else { BookFormat::Native };
+        BookBuilder { format, keys, plies, stats: BTreeMap::new(), games: 0 }
+    }
+
+    /// `result` is 1 if white won, 0.5 for a draw and 0 if black won
+    pub fn add_game(&mut self, start: &Game, moves: &[Move], result: f64) {
+        let mut game: Game = start.clone();
+        for game_move in moves {
+            if game.step_counter >= self.plies || game.get_winner().is_some() || !game.valid_turn(&game_move.0, &game_move.1) {
+                break;
+            }
+
+            let key: u64 = book::book_key(self.keys.as_ref(), &game);
+            let raw_move: u16 = book::encode_move(&game, game_move, self.format);
+            let player_result: f64 = if game.player_turn { result } else { 1.0 - result };
+            self.stats.entry((key, raw_move)).or_default().add(player_result);
+
+            game.perform_move(&game_move.0, &game_move.1);
+        }
+        self.games += 1;
+    }
+
+    pub fn stats(&self, game: &Game) -> Vec<(u16, &MoveStats)> {
+        let key: u64 = book::book_key(self.keys.as_ref(), game);
+        self.stats.range((key, 0)..=(key, u16::MAX))
+            .map(|((_, raw_move), move_stats)| (*raw_move, move_stats))
+            .collect()
+    }
+
+    /// Moves played at least `min_count` times, sorted by key and weight. Weights are scaled down
+    /// proportionally if the most successful move does not fit into the entries
+    pub fn entries(&self, min_count: usize) -> Vec<BookEntry> {
+        let frequent_stats = || self.stats.iter().filter(|(_, move_stats)| move_stats.count >= min_count);
+        let max_weight: usize = frequent_stats().map(|(_, move_stats)| move_stats.weight()).max().unwrap_or(0);
+        let divisor: usize = max_weight.div_ceil(u16::MAX as usize).max(1);
+
+        let mut entries: Vec<BookEntry> = frequent_stats()
+            .map(|((key, raw_move), move_stats)| BookEntry {
+                key: *key,
+                raw_move: *raw_move,
+                weight: (move_stats.weight() / divisor) as u16,
+                learn: 0
+            })
+            .collect();
+        entries.sort_by(|first, second| first.key.cmp(&second.key).then(second.weight.cmp(&first.weight)));
+        entries
+    }
+}
+
+/// Builds a book with `chess-ai book <output file> [PGN files...] [options]` from the games of the
+/// PGN files and from `--selfplay` games of the `--agent`. Books ending in `.bin` are written as
+/// Polyglot books, all others as native books
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let Some(output_file) = args.first() else {
+        return Err(USAGE.into());
+    };
+
+    let mut pgn_files: Vec<&str> = vec![];
+    let mut self_play_games: usize = 0;
+    let mut agent_name: &str = "Minimax";
+    let mut move_time: u64 = DEFAULT_MOVE_TIME_MS;
+    let mut plies: usize = 2 * book::DEFAULT_BOOK_DEPTH;
+    let mut min_count: usize = DEFAULT_MIN_COUNT;
+    let mut options: AgentOptions = AgentOptions::default();
+    options.minimax.hash_size_mb = SELF_PLAY_HASH_SIZE_MB;
+
+    let mut arguments = args[1..].iter();
+    while let Some(argument) = arguments.next() {
+        let mut value = || arguments.next().map(|value| value.as_str()).ok_or(USAGE);
+        match argument.as_str() {
+            "--selfplay" => self_play_games = value()?.parse()?,
+            "--agent" => agent_name = value()?,
+            "--movetime" => move_time = value()?.parse()?,
+            "--plies" => plies = value()?.parse()?,
+            "--min-count" => min_count = value()?.parse()?,
+            "--hash" => options.minimax.hash_size_mb = value()?.parse()?,
+            option if option.starts_with("--") => return Err(format!("Unknown option '{}'\n{}", option, USAGE).into()),
+            pgn_file => pgn_files.push(pgn_file)
+        }
+    }
+    if pgn_files.is_empty() && self_play_games == 0 {
+        return Err(USAGE.into());
+    }
+
+    let keys: Option<PolyglotKeys> = match BookFormat::from_path(output_file) {
+        BookFormat::Polyglot => Some(PolyglotKeys::default()),
+        BookFormat::Native => None
+    };
+    let mut builder: BookBuilder = BookBuilder::new(keys, plies);
+
+    for pgn_file in pgn_files {
+        let games: Vec<PgnGame> = parse_pgn(&fs::read_to_string(pgn_file)?);
+        let finished_games: Vec<&PgnGame> = games.iter().filter(|game| game.result.is_some()).collect();
+        println!("Read {} finished games from {}", finished_games.len(), pgn_file);
+        for pgn_game in finished_games {
+            builder.add_game(&pgn_game.start, &pgn_game.moves, pgn_game.result.unwrap());
+        }
+    }
+
+    let limits: SearchLimits = SearchLimits::time(move_time as f64 / 1000.0);
+    for game_index in 0..self_play_games {
+        let mut white_agent: Box<dyn Agent> = protocol::create_agent(agent_name, &options)?;
+        let mut black_agent: Box<dyn Agent> = protocol::create_agent(agent_name, &options)?;
+        let (game, moves) = agent::play_match(white_agent.as_mut(), black_agent.as_mut(), &limits, MAX_SELF_PLAY_STEPS);
+        let result: f64 = game_result(&game);
+        println!("Self-play game {}: {} plies, result {}", game_index + 1, moves.len(), result);
+        builder.add_game(&Game::new(), &moves, result);
+    }
+
+    let entries: Vec<BookEntry> = builder.entries(min_count);
+    book::write_book(output_file, &entries)?;
+    println!("Wrote {} moves from {} games to {}", entries.len(), builder.games, output_file);
+    let start: Game = Game::new();
+    for (raw_move, move_stats) in builder.stats(&start) {
+        println!(
+            "Start position {}: {} games, score {:.2} (+{} ={} -{})",
+            protocol::format_move(&book::decode_move(&start, raw_move)), move_stats.count, move_stats.score(), move_stats.wins, move_stats.draws, move_stats.losses
+        );
+    }
+    Ok(())
+}
+
+/// 1 if white won, 0 if black won and 0.5 for draws, including games the Syzygy tables decide as draws
+fn game_result(game: &Game) -> f64 {
+    match game.get_winner().or_else(|| syzygy::adjudicate(game)) {
+        Some(1) => 1.0,
+        Some(0) => 0.0,
+        _ => 0.5
+    }
+}
+
+/// Splits PGN text into games. Comments, variations and annotations are skipped, and each game
+/// is replayed up to its first move that `Game` cannot play, e.g. a promotion
+pub fn parse_pgn(content: &str) -> Vec<PgnGame> {
+    let mut games: Vec<PgnGame> = vec![];
+    let mut tags: Vec<(String, String)> = vec![];
+    let mut movetext: String = String::new();
+
+    for line in content.lines() {
+        let line: &str = line.trim();
+        if let Some(tag) = line.strip_prefix('[').and_then(|tag| tag.strip_suffix(']')) {
+            if !movetext.trim().is_empty() {
+                games.extend(parse_pgn_game(&tags, &movetext));
+                tags.clear();
+                movetext.clear();
+            }
+            if let Some((name, value)) = tag.split_once(char::is_whitespace) {
+                tags.push((name.to_string(), value.trim().trim_matches('"').to_string()));
+            }
+        } else {
+            movetext.push_str(line);
+            movetext.push('\n');
+        }
+    }
+    if !movetext.trim().is_empty() || !tags.is_empty() {
+        games.extend(parse_pgn_game(&tags, &movetext));
+    }
+
+    games
+}
+
+fn parse_pgn_game(tags: &[(String, String)], movetext: &str) -> Option<PgnGame> {
+    let tag = |name: &str| tags.iter().find(|(tag_name, _)| tag_name == name).map(|(_, value)| value.as_str());
+    let start: Game = match tag("FEN") {
+        Some(fen) => Game::from_fen(fen).ok()?,
+        None => Game::new()
+    };
+    let mut result: Option<f64> = tag("Result").and_then(parse_result);
+
+    let mut game: Game = start.clone();
+    let mut moves: Vec<Move> = vec![];
+    let mut replaying: bool = true;
+    for token in strip_annotations(movetext).split_whitespace() {
+        if let Some(token_result) = parse_result(token) {
+            result = Some(token_result);
+            continue;
+        }
+
+        let notation: &str = token.trim_start_matches(|symbol: char| symbol.is_ascii_digit() || symbol == '.');
+        if !replaying || notation.is_empty() || notation.starts_with('$') {
+            continue;
+        }
+        match protocol::parse_san(&game, notation) {
+            Some((from_pos, to_pos)) => {
+                game.perform_move(&from_pos, &to_pos);
+                moves.push((from_pos, to_pos));
+            },
+            None => replaying = false
+        }
+    }
+
+    Some(PgnGame { start, moves, result })
+}
+
+fn parse_result(token: &str) -> Option<f64> {
+    match token {
+        "1-0" => Some(1.0),
+        "0-1" => Some(0.0),
+        "1/2-1/2" => Some(0.5),
+        _ => None
+    }
+}
+
+/// Removes `{comments}`, `; comments` and `(variations)`, which may be nested
+fn strip_annotations(movetext: &str) -> String {
+    let mut stripped: String = String::with_capacity(movetext.len());
+    let mut variation_depth: usize = 0;
+    let mut in_comment: bool = false;
+    let mut in_line_comment: bool = false;
+
+    for symbol in movetext.chars() {
+        match symbol {
+            '\n' if in_line_comment => in_line_comment = false,
+            _ if in_line_comment => {},
+            '}' if in_comment => in_comment = false,
+            _ if in_comment => {},
+            '{' => in_comment = true,
+            ';' => in_line_comment = true,
+            '(' => variation_depth += 1,
+            ')' => variation_depth = variation_depth.saturating_sub(1),
+            _ if variation_depth > 0 => {},
+            symbol => {
+                stripped.push(symbol);
+                continue;
+            }
+        }
+        stripped.push(' ');
+    }
+
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::book::OpeningBook;
+
+    const PGN: &str = r#"[Event "Test"]
+[Result "1-0"]
+
+1. e4 {best by test} e5 2. Nf3 (2. f4 exf4) Nc6 3. Bb5 a6 $1 4. Ba4 Nf6 5. O-O Be7 ; main line
+6. Re1 b5 7. Bb3 O-O 8. c3 d6 1-0
+
+[Event "Test"]
+[Result "1/2-1/2"]
+
+1. e4 e5 2. Nf3 Nf6 3. Nxe5 d6 4. Nf3 Nxe4 5. Nc3 Nxc3 6. dxc3 1/2-1/2
+
+[Event "Unfinished"]
+
+1. d4 d5 *
+"#;
+
+    #[test]
+    fn parses_pgn_games() {
+        let games: Vec<PgnGame> = parse_pgn(PGN);
+        assert_eq!(games.len(), 3);
+        assert_eq!(games.iter().map(|game| game.result).collect::<Vec<_>>(), vec![Some(1.0), Some(0.5), None]);
+        assert_eq!(games[0].moves.len(), 16);
+        assert_eq!(games[1].moves.len(), 11);
+
+        // Castling and disambiguated captures
+        assert_eq!(games[0].moves[8], (Position::new(0, 4), Position::new(0, 6)));
+        assert_eq!(games[1].moves[10], (Position::new(1, 3), Position::new(2, 2)));
+    }
+
+    #[test]
+    fn builds_weighted_books() {
+        let mut builder: BookBuilder = BookBuilder::new(None, 4);
+        for pgn_game in parse_pgn(PGN).iter().filter(|game| game.result.is_some()) {
+            builder.add_game(&pgn_game.start, &pgn_game.moves, pgn_game.result.unwrap());
+        }
+
+        let start: Game = Game::new();
+        assert_eq!(builder.stats(&start).len(), 1);
+        assert_eq!(builder.stats(&start)[0].1, &MoveStats { count: 2, wins: 1, draws: 1, losses: 0 });
+
+        // Only the first four plies were played in both games
+        let entries: Vec<BookEntry> = builder.entries(2);
+        assert_eq!(entries.len(), 3);
+        assert!(entries.windows(2).all(|pair| pair[0].key <= pair[1].key));
+
+        let bytes: Vec<u8> = entries.iter().flat_map(|entry| entry.to_bytes()).collect();
+        let book: OpeningBook = OpeningBook::from_bytes(&bytes, None).unwrap();
+        assert_eq!(book.moves(&start), vec![((Position::new(1, 4), Position::new(3, 4)), 3)]);
+    }
+}
@@ -1,7 +1,7 @@
 use crate::core::{piece::Piece, position::Position};
 
 use super::{move_validator, piece::PieceType};
-use std::fmt;
+use std::{cmp::max, fmt};
 
 #[derive(Clone)]
 pub struct Board {
@@ -16,7 +16,6 @@ pub struct Board {
 }
 
 impl Board {
-    #[allow(dead_code)]
     fn zero() -> Board {
         Board {
             layer_color: 0b0,
@@ -50,6 +49,70 @@ impl Board {
         }
     }
 
+    /// Builds a board from the piece placement and castling fields of a FEN string
+    pub fn from_fen(placement: &str, castling: &str) -> Result<Board, String> {
+        let mut board: Board = Board::zero();
+        let ranks: Vec<&str> = placement.split('/').collect();
+        if ranks.len() != 8 {
+            return Err(format!("Expected 8 ranks in FEN placement, found {}", ranks.len()));
+        }
+
+        for (rank_index, rank) in ranks.into_iter().enumerate() {
+            let row: u8 = 7 - rank_index as u8;
+            let mut column: u8 = 0;
+
+            for symbol in rank.chars() {
+                if let Some(empty_squares) = symbol.to_digit(10) {
+                    if empty_squares == 0 || u32::from(column) + empty_squares > 8 {
+                        return Err(format!("Too many squares in FEN rank '{}'", rank));
+                    }
+                    column += empty_squares as u8;
+                    continue;
+                }
+
+                let piece_bit: u64 = match symbol.to_ascii_lowercase() {
+                    'p' => 0b0100000,
+                    'n' => 0b0010000,
+                    'b' => 0b0001000,
+                    'r' => 0b0000100,
+                    'q' => 0b0000010,
+                    'k' => 0b0000001,
+                    _ => return Err(format!("Unknown piece '{}' in FEN placement", symbol))
+                };
+                if column > 7 {
+                    return Err(format!("Too many squares in FEN rank '{}'", rank));
+                }
+
+                let color_bit: u64 = if symbol.is_ascii_uppercase() { 0b10000000 } else { 0b0 };
+                let home_pawn: bool = piece_bit == 0b0100000
+                    && (symbol.is_ascii_uppercase() && row == 1 || symbol.is_ascii_lowercase() && row == 6);
+                let not_moved_bit: u64 = if home_pawn { 0b1000000 } else { 0b0 };
+
+                board.set_position_binary(&Position::new(row, column), color_bit | not_moved_bit | piece_bit);
+                column += 1;
+            }
+
+            if column != 8 {
+                return Err(format!("FEN rank '{}' does not cover 8 squares", rank));
+            }
+        }
+
+        for (symbol, king_square, rook_square) in [('K', 4, 7), ('Q', 4, 0), ('k', 60, 63), ('q', 60, 56)] {
+            if !castling.contains(symbol) {
+                continue;
+            }
+            let king_mask: u64 = 0b1 << king_square;
+            let rook_mask: u64 = 0b1 << rook_square;
+            let color_mask: u64 = if symbol.is_ascii_uppercase() { board.layer_color } else { !board.layer_color };
+
+            if board.layer_king & color_mask & king_mask != 0 && board.layer_rook & color_mask & rook_mask != 0 {
+                board.layer_not_moved |= king_mask | rook_mask;
+            }
+        }
+
+        Ok(board)
+    }
+
     pub fn import(layers: [u64; 8]) -> Board {
         Board {
             layer_color: layers[0],
@@ -182,6 +245,109 @@ impl Board {
         self.set_position_binary(from, 0b0);
         self.set_position_binary(to, binary_piece);
     }
+
+    /// Static exchange evaluation: the material the player moving from `from` wins in centipawns,
+    /// if both players keep recapturing on `to` with their least valuable piece as long as it pays off.
+    /// Pieces behind the capturing ones join in, pins and checks are ignored
+    pub fn static_exchange(&self, from: &Position, to: &Position) -> isize {
+        let target: u32 = to.as_u8() as u32;
+        let mut occupied: u64 = !self.get_empty_layer();
+        let mut attacker_square: u32 = from.as_u8() as u32;
+        let mut attacker: PieceType = self.get_piece_at(from).piece_type();
+        let mut player: bool = self.get_piece_at(from).get_color();
+
+        // gains[n] is the material won by the player making capture n, if the opponent stops there
+        let mut gains: Vec<isize> = vec![self.get_piece_at(to).piece_type().value()];
+        loop {
+            occupied &= !(0b1 << attacker_square);
+            player = !player;
+            let player_layer: u64 = if player { self.layer_color } else { !self.layer_color };
+            let Some((square, piece_type)) = self.least_valuable_attacker(target, occupied & player_layer, occupied) else {
+                break;
+            };
+
+            gains.push(attacker.value() - gains[gains.len() - 1]);
+            attacker_square = square;
+            attacker = piece_type;
+        }
+
+        // A player only recaptures if that does not lose material
+        while gains.len() > 1 {
+            let gain: isize = gains.pop().unwrap();
+            let last_index: usize = gains.len() - 1;
+            gains[last_index] = -max(-gains[last_index], gain);
+        }
+        gains[0]
+    }
+
+    /// Square and type of the cheapest piece in `candidates` attacking `target` through `occupied`
+    fn least_valuable_attacker(&self, target: u32, candidates: u64, occupied: u64) -> Option<(u32, PieceType)> {
+        let (row, column) = ((target / 8) as i32, (target % 8) as i32);
+        let straight: u64 = piece_attacks(PieceType::Rook, target, occupied);
+        let diagonal: u64 = piece_attacks(PieceType::Bishop, target, occupied);
+
+        // Pawns attack the target from the row behind them
+        let white_pawns: u64 = step_attacks(row, column, &[(-1, -1), (-1, 1)]) & self.layer_color;
+        let black_pawns: u64 = step_attacks(row, column, &[(1, -1), (1, 1)]) & !self.layer_color;
+
+        [
+            (self.layer_pawn & (white_pawns | black_pawns), PieceType::Pawn),
+            (self.layer_knight & piece_attacks(PieceType::Knight, target, occupied), PieceType::Knight),
+            (self.layer_bishop & diagonal, PieceType::Bishop),
+            (self.layer_rook & straight, PieceType::Rook),
+            (self.layer_queen & (straight | diagonal), PieceType::Queen),
+            (self.layer_king & piece_attacks(PieceType::King, target, occupied), PieceType::King)
+        ].into_iter()
+            .map(|(attackers, piece_type)| (attackers & candidates, piece_type))
+            .find(|(attackers, _)| *attackers != 0)
+            .map(|(attackers, piece_type)| (attackers.trailing_zeros(), piece_type))
+    }
+}
+
+const KNIGHT_STEPS: [(i32, i32); 8] = [(1, 2), (2, 1), (2, -1), (1, -2), (-1, -2), (-2, -1), (-2, 1), (-1, 2)];
+const KING_STEPS: [(i32, i32); 8] = [(1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1), (0, -1), (1, -1)];
+const STRAIGHT_DIRECTIONS: [(i32, i32); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+const DIAGONAL_DIRECTIONS: [(i32, i32); 4] = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+
+/// Squares attacked by a knight, bishop, rook, queen or king on `square`, sliding pieces
+/// are stopped by the first piece in `occupied`. Pawns are left out, as their attacks depend on their color
+pub fn piece_attacks(piece_type: PieceType, square: u32, occupied: u64) -> u64 {
+    let (row, column) = ((square / 8) as i32, (square % 8) as i32);
+    match piece_type {
+        PieceType::Knight => step_attacks(row, column, &KNIGHT_STEPS),
+        PieceType::Bishop => sliding_attacks(row, column, &DIAGONAL_DIRECTIONS, occupied),
+        PieceType::Rook => sliding_attacks(row, column, &STRAIGHT_DIRECTIONS, occupied),
+        PieceType::Queen => sliding_attacks(row, column, &DIAGONAL_DIRECTIONS, occupied)
+            | sliding_attacks(row, column, &STRAIGHT_DIRECTIONS, occupied),
+        PieceType::King => step_attacks(row, column, &KING_STEPS),
+        PieceType::Pawn | PieceType::Empty => 0
+    }
+}
+
+/// Squares one step away in the given directions
+fn step_attacks(row: i32, column: i32, steps: &[(i32, i32)]) -> u64 {
+    steps.iter()
+        .map(|(row_step, column_step)| (row + row_step, column + column_step))
+        .filter(|(row, column)| (0..8).contains(row) && (0..8).contains(column))
+        .fold(0, |layer, (row, column)| layer | 0b1 << (row * 8 + column))
+}
+
+/// Squares along the given directions up to and including the first occupied one
+fn sliding_attacks(row: i32, column: i32, directions: &[(i32, i32)], occupied: u64) -> u64 {
+    let mut layer: u64 = 0;
+    for (row_step, column_step) in directions {
+        let (mut row, mut column) = (row + row_step, column + column_step);
+        while (0..8).contains(&row) && (0..8).contains(&column) {
+            let mask: u64 = 0b1 << (row * 8 + column);
+            layer |= mask;
+            if occupied & mask != 0 {
+                break;
+            }
+            row += row_step;
+            column += column_step;
+        }
+    }
+    layer
 }
 
 impl fmt::Display for Board {
@@ -210,3 +376,32 @@ impl fmt::Display for Board {
         write!(f, "{}", result_string)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exchanges_on_a_square() {
+        // The pawn on d5 is defended by the pawn on e6, the rook behind the other rook joins the exchange
+        let board: Board = Board::from_fen("4k3/8/4p3/3p4/8/8/3R4/3RK3", "-").unwrap();
+        assert_eq!(board.static_exchange(&Position::new(1, 3), &Position::new(4, 3)), 100 - 500 + 100);
+
+        let board: Board = Board::from_fen("4k3/8/8/3p4/8/8/3R4/3RK3", "-").unwrap();
+        assert_eq!(board.static_exchange(&Position::new(1, 3), &Position::new(4, 3)), 100);
+
+        // Pawn takes knight and is recaptured by the queen. Pushing the pawn instead is safe,
+        // as the rook behind it would recapture the queen
+        let board: Board = Board::from_fen("4k3/3q4/4n3/3P4/8/8/8/3RK3", "-").unwrap();
+        assert_eq!(board.static_exchange(&Position::new(4, 3), &Position::new(5, 4)), 300 - 100);
+        assert_eq!(board.static_exchange(&Position::new(4, 3), &Position::new(5, 3)), 0);
+    }
+
+    #[test]
+    fn rejects_overlong_fen_ranks() {
+        for placement in ["4k3/8/8/8/8/8/8/4K4", "4k3/8/8/8/8/8/8/9", "4k3/8/8/8/8/8/8/44444444K", "4k3/8/8/8/8/8/8/4K03"] {
+            assert!(Board::from_fen(placement, "-").is_err(), "{}", placement);
+        }
+        assert!(Board::from_fen("4k3/8/8/8/8/8/8/4K3", "-").is_ok());
+    }
+}
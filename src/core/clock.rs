@@ -0,0 +1,169 @@
+/// Times are given in seconds
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimeControl {
+    /// `base_time` for the whole game, `increment` is added after every move
+    Increment { base_time: f64, increment: f64 },
+    /// `base_time` is added again after every `moves` moves of a player
+    Session { moves: usize, base_time: f64, increment: f64 },
+    /// Every move has to be made within `move_time`, unused time is lost
+    PerMove { move_time: f64 }
+}
+
+impl TimeControl {
+    /// Reads `300+3` for increments, `40/600+0` for sessions of 40 moves and `1/move` for a fixed time per move
+    pub fn from_notation(notation: &str) -> Option<TimeControl> {
+        let seconds = |text: &str| text.trim().parse::<f64>().ok().filter(|seconds| seconds.is_finite() && *seconds >= 0.0);
+        if let Some(move_time) = notation.strip_suffix("/move") {
+            return seconds(move_time).filter(|move_time| *move_time > 0.0).map(|move_time| TimeControl::PerMove { move_time });
+        }
+
+        let (time, increment) = match notation.split_once('+') {
+            Some((time, increment)) => (time, seconds(increment)?),
+            None => (notation, 0.0)
+        };
+        match time.split_once('/') {
+            Some((moves, base_time)) => Some(TimeControl::Session {
+                moves: moves.trim().parse().ok().filter(|moves| *moves > 0)?,
+                base_time: seconds(base_time).filter(|base_time| *base_time > 0.0)?,
+                increment
+            }),
+            None => seconds(time).filter(|base_time| *base_time > 0.0).map(|base_time| TimeControl::Increment { base_time, increment })
+        }
+    }
+}
+
+/// Chess clock of both players, which is started and pressed with the current time
+#[derive(Clone, Debug)]
+pub struct Clock {
+    pub time_control: TimeControl,
+    remaining: [f64; 2],
+    moves_made: [usize; 2],
+    running_since: Option<f64>
+}
+
+impl Clock {
+    pub fn new(time_control: TimeControl) -> Clock {
+        let start_time: f64 = match time_control {
+            TimeControl::Increment { base_time, .. } => base_time,
+            TimeControl::Session { base_time, .. } => base_time,
+            TimeControl::PerMove { move_time } => move_time
+        };
+
+        Clock {
+            time_control,
+            remaining: [start_time; 2],
+            moves_made: [0; 2],
+            running_since: None
+        }
+    }
+
+    pub fn start(&mut self, now: f64) {
+        self.running_since = Some(now);
+    }
+
+    /// Ends the turn of `player`, the clock of the other player runs from `now` on
+    pub fn press(&mut self, player: bool, now: f64) {
+        let index: usize = usize::from(player);
+        let elapsed: f64 = self.running_since.map_or(0.0, |since| now - since);
+        self.remaining[index] -= elapsed;
+        self.moves_made[index] += 1;
+
+        match self.time_control {
+            TimeControl::Increment { increment, .. } => self.remaining[index] += increment,
+            TimeControl::Session { moves, base_time, increment } => {
+                self.remaining[index] += increment;
+                if moves > 0 && self.moves_made[index].is_multiple_of(moves) {
+                    self.remaining[index] += base_time;
+                }
+            },
+            TimeControl::PerMove { move_time } => self.remaining[index] = move_time
+        }
+
+        self.running_since = Some(now);
+    }
+
+    /// Time left for `player`, counting the running turn if it is theirs
+    pub fn remaining(&self, player: bool, player_turn: bool, now: f64) -> f64 {
+        let mut remaining: f64 = self.remaining[usize::from(player)];
+        if let (true, Some(since)) = (player == player_turn, self.running_since) {
+            remaining -= now - since;
+        }
+        remaining.max(0.0)
+    }
+
+    pub fn increment(&self) -> f64 {
+        match self.time_control {
+            TimeControl::Increment { increment, .. } => increment,
+            TimeControl::Session { increment, .. } => increment,
+            TimeControl::PerMove { .. } => 0.0
+        }
+    }
+
+    /// Moves `player` has to make until time is added again, unknown for increments
+    pub fn moves_to_go(&self, player: bool) -> Option<usize> {
+        match self.time_control {
+            TimeControl::Session { moves, .. } if moves > 0 =>
+                Some(moves - self.moves_made[usize::from(player)] % moves),
+            TimeControl::PerMove { .. } => Some(1),
+            _ => None
+        }
+    }
+}
+
+/// Formats seconds as `m:ss`, with tenths of a second below 10 seconds
+pub fn format_time(seconds: f64) -> String {
+    let seconds: f64 = seconds.max(0.0);
+    if seconds < 10.0 {
+        format!("0:0{:.1}", seconds)
+    } else {
+        let seconds: u64 = seconds.floor() as u64;
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn increments_and_sessions() {
+        let mut clock: Clock = Clock::new(TimeControl::Session { moves: 2, base_time: 60.0, increment: 1.0 });
+        clock.start(0.0);
+        clock.press(true, 10.0);
+        assert_eq!(clock.remaining(true, false, 15.0), 51.0);
+        assert_eq!(clock.remaining(false, false, 15.0), 55.0);
+        assert_eq!(clock.moves_to_go(true), Some(1));
+
+        clock.press(false, 20.0);
+        clock.press(true, 30.0);
+        assert_eq!(clock.remaining(true, false, 30.0), 102.0);
+        assert_eq!(clock.moves_to_go(true), Some(2));
+    }
+
+    #[test]
+    fn fixed_time_per_move() {
+        let mut clock: Clock = Clock::new(TimeControl::PerMove { move_time: 5.0 });
+        clock.start(0.0);
+        assert_eq!(clock.remaining(true, true, 6.0), 0.0);
+        clock.press(true, 3.0);
+        assert_eq!(clock.remaining(true, false, 10.0), 5.0);
+    }
+
+    #[test]
+    fn parses_time_controls() {
+        assert_eq!(TimeControl::from_notation("300+3"), Some(TimeControl::Increment { base_time: 300.0, increment: 3.0 }));
+        assert_eq!(TimeControl::from_notation("60"), Some(TimeControl::Increment { base_time: 60.0, increment: 0.0 }));
+        assert_eq!(TimeControl::from_notation("40/600"), Some(TimeControl::Session { moves: 40, base_time: 600.0, increment: 0.0 }));
+        assert_eq!(TimeControl::from_notation("40/600+2.5"), Some(TimeControl::Session { moves: 40, base_time: 600.0, increment: 2.5 }));
+        assert_eq!(TimeControl::from_notation("1/move"), Some(TimeControl::PerMove { move_time: 1.0 }));
+        for notation in ["", "0", "0/600", "40/0", "300+", "-5", "0/move", "fast"] {
+            assert_eq!(TimeControl::from_notation(notation), None);
+        }
+    }
+
+    #[test]
+    fn formats_times() {
+        assert_eq!(format_time(305.4), "5:05");
+        assert_eq!(format_time(9.34), "0:09.3");
+    }
+}
@@ -1,10 +1,11 @@
-use crate::core::{board::Board, piece::PieceType};
+use crate::core::{board::Board, clock::{Clock, TimeControl}, piece::PieceType};
 
 use super::{
     move_validator::{is_check, is_checkmate, is_remis},
     position::Position,
 };
 
+/// A game of chess. Pawns that reach the last rank are not promoted and en passant captures are not played
 #[derive(Clone)]
 pub struct Game {
     pub board: Board,
@@ -13,8 +14,12 @@ pub struct Game {
     pub check: bool,
     checkmate: bool,
     remis: bool,
+    flag_fall: bool,
 
-    pub step_counter: usize
+    pub step_counter: usize,
+    /// Plies since the last capture or pawn move, for the fifty-move rule
+    pub halfmove_clock: usize,
+    pub clock: Option<Clock>
 }
 
 impl Game {
@@ -25,10 +30,63 @@ impl Game {
             check: false,
             checkmate: false,
             remis: false,
-            step_counter: 0
+            flag_fall: false,
+            step_counter: 0,
+            halfmove_clock: 0,
+            clock: None
         }
     }
 
+    pub fn with_time_control(time_control: TimeControl) -> Game {
+        Game { clock: Some(Clock::new(time_control)), ..Game::new() }
+    }
+
+    /// En passant targets are ignored, as the rule is not supported
+    pub fn from_fen(fen: &str) -> Result<Game, String> {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        if fields.len() < 2 {
+            return Err(format!("FEN '{}' needs at least placement and side to move", fen));
+        }
+
+        let board: Board = Board::from_fen(fields[0], fields.get(2).unwrap_or(&"-"))?;
+        let player_turn: bool = match fields[1] {
+            "w" => true,
+            "b" => false,
+            side => return Err(format!("Unknown side to move '{}' in FEN", side))
+        };
+        let halfmove_clock: usize = fields.get(4)
+            .and_then(|field| field.parse::<usize>().ok())
+            .unwrap_or(0);
+        let full_moves: usize = fields.get(5)
+            .and_then(|field| field.parse::<usize>().ok())
+            .unwrap_or(1)
+            .max(1);
+
+        let mut game: Game = Game {
+            board,
+            player_turn,
+            check: false,
+            checkmate: false,
+            remis: false,
+            flag_fall: false,
+            step_counter: (full_moves - 1) * 2 + usize::from(!player_turn),
+            halfmove_clock,
+            clock: None
+        };
+
+        let last_mover: bool = !player_turn;
+        game.check = is_check(&game.board, last_mover);
+        if !game.check && is_remis(&game.board, last_mover) {
+            game.remis = true;
+            game.player_turn = last_mover;
+        } else if game.check && is_checkmate(&game.board, last_mover) {
+            game.checkmate = true;
+            game.player_turn = last_mover;
+        }
+
+        Ok(game)
+    }
+
     pub fn valid_turn(&self, from: &Position, to: &Position) -> bool {
         self.board.is_move_valid(self.player_turn, from, to)
     }
@@ -43,6 +101,10 @@ impl Game {
             return;
         }
 
+        let irreversible: bool = self.board.get_piece_at(from).piece_type() == PieceType::Pawn
+            || self.board.get_piece_at(to).piece_type() != PieceType::Empty;
+        self.halfmove_clock = if irreversible { 0 } else { self.halfmove_clock + 1 };
+
         if self.is_castleing_move(from, to) && from.column < to.column {
             self.board.move_from_to(from, to);
             self.board.move_from_to(
@@ -70,6 +132,48 @@ impl Game {
         self.next_player();
     }
 
+    /// Like `perform_move`, but also presses the clock of the moving player.
+    /// Unless the clock was started before, it starts running with the first move
+    pub fn perform_timed_move(&mut self, from: &Position, to: &Position, now: f64) {
+        if self.update_clock(now) || !self.valid_turn(from, to) {
+            return;
+        }
+
+        let player: bool = self.player_turn;
+        self.perform_move(from, to);
+        if let Some(clock) = &mut self.clock {
+            clock.press(player, now);
+        }
+    }
+
+    /// Ends the game once the player to move runs out of time.
+    /// Returns true if the game was lost or drawn on time
+    pub fn update_clock(&mut self, now: f64) -> bool {
+        let Some(clock) = &self.clock else {
+            return false
+        };
+        if self.get_winner().is_some() {
+            return self.flag_fall;
+        }
+
+        if clock.remaining(self.player_turn, self.player_turn, now) <= 0.0 {
+            self.flag_fall = true;
+            // Without material to mate, the opponent only gets a draw
+            let opponent_layer: u64 = if self.player_turn {
+                !self.board.layer_color
+            } else {
+                self.board.layer_color
+            };
+            self.remis = (!self.board.get_empty_layer() & !self.board.layer_king & opponent_layer) == 0;
+        }
+        self.flag_fall
+    }
+
+    /// Time left for `player` or None for untimed games
+    pub fn remaining_time(&self, player: bool, now: f64) -> Option<f64> {
+        self.clock.as_ref().map(|clock| clock.remaining(player, self.player_turn, now))
+    }
+
     pub fn next_player(&mut self) {
         self.player_turn = !self.player_turn
     }
@@ -81,8 +185,29 @@ impl Game {
             Some(0)
         } else if self.remis {
             Some(2)
+        } else if self.flag_fall && self.player_turn {
+            Some(0)
+        } else if self.flag_fall {
+            Some(1)
         } else {
             None
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flag_fall_ends_the_game() {
+        let mut game: Game = Game::with_time_control(TimeControl::Increment { base_time: 10.0, increment: 0.0 });
+        game.perform_timed_move(&Position::new(1, 4), &Position::new(3, 4), 0.0);
+        assert!(!game.update_clock(5.0));
+        assert!(game.update_clock(11.0));
+        assert_eq!(game.get_winner(), Some(1));
+
+        game.perform_timed_move(&Position::new(6, 4), &Position::new(4, 4), 12.0);
+        assert_eq!(game.step_counter, 1);
+    }
+}
@@ -1,7 +1,9 @@
 pub mod board;
+pub mod clock;
 pub mod game;
 pub mod move_generator;
 pub mod move_validator;
 pub mod piece;
 pub mod position;
 pub mod snapshot;
+pub mod zobrist;
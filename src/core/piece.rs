@@ -21,7 +21,7 @@
 //      0b______1_ = queen
 //      0b_______1 = king
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum PieceType {
     Empty,
     Pawn,
@@ -32,6 +32,21 @@ pub enum PieceType {
     King,
 }
 
+impl PieceType {
+    /// Material value in centipawns, the king outweighs all other material
+    pub fn value(&self) -> isize {
+        match self {
+            PieceType::Empty => 0,
+            PieceType::Pawn => 100,
+            PieceType::Knight => 300,
+            PieceType::Bishop => 300,
+            PieceType::Rook => 500,
+            PieceType::Queen => 900,
+            PieceType::King => 10000
+        }
+    }
+}
+
 #[derive(Clone, PartialEq)]
 pub struct Piece {
     color: bool, // 0b0 black, 0b1 white
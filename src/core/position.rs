@@ -22,6 +22,23 @@ impl Position {
         self.row * 8 + self.column
     }
 
+    /// Parses a square in algebraic notation like `e4`
+    pub fn from_notation(notation: &str) -> Option<Position> {
+        let mut chars = notation.chars();
+        let file: char = chars.next()?;
+        let rank: char = chars.next()?;
+
+        if chars.next().is_some() || !('a'..='h').contains(&file) || !('1'..='8').contains(&rank) {
+            return None
+        }
+
+        Some(Position::new(rank as u8 - b'1', file as u8 - b'a'))
+    }
+
+    pub fn to_notation(&self) -> String {
+        format!("{}{}", (b'a' + self.column) as char, self.row + 1)
+    }
+
     pub fn move_towards(&mut self, new_position: &Position) {
         if self.row < new_position.row {
             self.row += 1;
@@ -0,0 +1,97 @@
+use crate::core::{board::Board, game::Game};
+
+const PIECE_KEYS: [[u64; 64]; 12] = generate_piece_keys();
+const CASTLING_KEYS: [u64; 4] = generate_keys::<4>(0x0C45_7A5E);
+const WHITE_TO_MOVE_KEY: u64 = generate_keys::<1>(0x7E5D_0F1E)[0];
+
+/// King and rook squares of the castlings, white short, white long, black short, black long
+pub const CASTLING_SQUARES: [(u64, u64); 4] = [(4, 7), (4, 0), (60, 63), (60, 56)];
+
+/// Zobrist hash of the pieces, castling rights and the player to move
+pub fn hash_game(game: &Game) -> u64 {
+    hash(&game.board, game.player_turn)
+}
+
+pub fn hash(board: &Board, player_turn: bool) -> u64 {
+    let mut hash: u64 = 0;
+
+    let piece_layers: [u64; 6] = [
+        board.layer_pawn,
+        board.layer_knight,
+        board.layer_bishop,
+        board.layer_rook,
+        board.layer_queen,
+        board.layer_king
+    ];
+    for (piece_index, piece_layer) in piece_layers.into_iter().enumerate() {
+        for (color_index, color_layer) in [board.layer_color, !board.layer_color].into_iter().enumerate() {
+            let mut layer: u64 = piece_layer & color_layer;
+            while layer != 0 {
+                let square: usize = layer.trailing_zeros() as usize;
+                hash ^= PIECE_KEYS[piece_index * 2 + color_index][square];
+                layer &= layer - 1;
+            }
+        }
+    }
+
+    for (castling_index, (king_square, rook_square)) in CASTLING_SQUARES.into_iter().enumerate() {
+        let castling_mask: u64 = (0b1 << king_square) | (0b1 << rook_square);
+        if board.layer_not_moved & (board.layer_king | board.layer_rook) & castling_mask == castling_mask {
+            hash ^= CASTLING_KEYS[castling_index];
+        }
+    }
+
+    if player_turn {
+        hash ^= WHITE_TO_MOVE_KEY;
+    }
+
+    hash
+}
+
+const fn generate_piece_keys() -> [[u64; 64]; 12] {
+    let mut keys: [[u64; 64]; 12] = [[0; 64]; 12];
+    let mut piece_index: usize = 0;
+    while piece_index < 12 {
+        keys[piece_index] = generate_keys::<64>(piece_index as u64 + 1);
+        piece_index += 1;
+    }
+    keys
+}
+
+/// SplitMix64, so the keys are the same in every build
+const fn generate_keys<const N: usize>(seed: u64) -> [u64; N] {
+    let mut keys: [u64; N] = [0; N];
+    let mut state: u64 = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    let mut index: usize = 0;
+    while index < N {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut key: u64 = state;
+        key = (key ^ (key >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        key = (key ^ (key >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        keys[index] = key ^ (key >> 31);
+        index += 1;
+    }
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::position::Position;
+
+    #[test]
+    fn transpositions_share_a_hash() {
+        let mut game: Game = Game::new();
+        let mut transposed: Game = Game::new();
+        for (from_pos, to_pos) in [((0, 6), (2, 5)), ((7, 6), (5, 5)), ((0, 1), (2, 2)), ((7, 1), (5, 2))] {
+            game.perform_move(&Position::new(from_pos.0, from_pos.1), &Position::new(to_pos.0, to_pos.1));
+        }
+        for (from_pos, to_pos) in [((0, 1), (2, 2)), ((7, 1), (5, 2)), ((0, 6), (2, 5)), ((7, 6), (5, 5))] {
+            transposed.perform_move(&Position::new(from_pos.0, from_pos.1), &Position::new(to_pos.0, to_pos.1));
+        }
+
+        assert_eq!(hash_game(&game), hash_game(&transposed));
+        assert_ne!(hash_game(&game), hash_game(&Game::new()));
+        assert_ne!(hash(&game.board, true), hash(&game.board, false));
+    }
+}
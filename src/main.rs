@@ -1,45 +1,93 @@
 mod agent;
+mod book_builder;
 mod core;
+mod protocol;
+mod tablebase_generator;
+mod tuner;
 mod ui;
 
 use agent::neural_network::Network;
 use agent::Agent;
 use ggez::conf::Conf;
-use ggez::GameResult;
+use ggez::{GameError, GameResult};
 use good_web_game as ggez;
 use ui::Engine;
 
-use crate::agent::monte_carlo::Tree;
-use crate::agent::{neural_network, AgentType};
+use crate::agent::neural_network::NeuralNetworkAgent;
+use crate::agent::{neural_network, syzygy, SearchLimits};
+use crate::core::clock::TimeControl;
+
+const DEFAULT_TIME_CONTROL: TimeControl = TimeControl::Increment { base_time: 300.0, increment: 3.0 };
 
 fn main() {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    init_syzygy(&mut args);
+
     #[cfg(feature = "train")]
     neural_network_training().expect("Something went wrong");
     #[cfg(not(feature = "train"))]
-    run_game().expect("Something went wrong");
+    match args.first().map(String::as_str) {
+        Some("uci") => protocol::uci::run().expect("Something went wrong"),
+        Some("xboard") => protocol::xboard::run().expect("Something went wrong"),
+        Some("book") => book_builder::run(&args[1..]).expect("Something went wrong"),
+        Some("tablebase") => tablebase_generator::run(&args[1..]).expect("Something went wrong"),
+        Some("tune") => tuner::run(&args[1..]).expect("Something went wrong"),
+        _ => run_game(&args).expect("Something went wrong")
+    }
 }
 
-fn run_game() -> GameResult {
+/// Takes `--syzygy <directory>` out of the arguments, every mode probes the tables in there
+fn init_syzygy(args: &mut Vec<String>) {
+    let Some(index) = args.iter().position(|arg| arg == "--syzygy") else {
+        return;
+    };
+    args.remove(index);
+    if index >= args.len() {
+        return eprintln!("--syzygy needs a directory");
+    }
+
+    let directory: String = args.remove(index);
+    match syzygy::init(&directory) {
+        // Standard output belongs to the UCI and xboard protocols
+        Ok(count) => eprintln!("Found Syzygy tables for {} materials in {}", count, directory),
+        Err(error) => eprintln!("Playing without Syzygy tables, as {} could not be read: {}", directory, error)
+    }
+}
+
+/// Opens the board, `--time <control>` sets the clock as read by `TimeControl::from_notation`,
+/// `--time none` plays without clocks
+fn run_game(args: &[String]) -> GameResult {
+    let time_control: Option<TimeControl> = match args.iter().position(|arg| arg == "--time") {
+        Some(index) => match args.get(index + 1).map_or("", String::as_str) {
+            "none" => None,
+            notation => match TimeControl::from_notation(notation) {
+                Some(time_control) => Some(time_control),
+                None => return Err(GameError::CustomError(format!("Invalid time control '{}', expected e.g. 300+3, 40/600, 1/move or none", notation)))
+            }
+        },
+        None => Some(DEFAULT_TIME_CONTROL)
+    };
+
     let conf = Conf::default()
         // .cache(Some(include_bytes!("../assets.tar")))
         .window_resizable(true)
         .window_title("Chess AI | Purpurax".to_string());
 
-    let white_agent: Option<Agent> =
+    let white_agent: Option<Box<dyn Agent>> =
         // None;
-        // Some(Agent::new(AgentType::Random, 1.0));
-        // Some(Agent::new(AgentType::Minimax, 1.0));
-        // Some(Agent::new(AgentType::MonteCarlo(Tree::new()), 1.0)); // Currently ~37000 nodes
-        Some(Agent::new(AgentType::NeuralNetwork(neural_network::read_network_from_file("/home/master/Project/Rust/chess-ai/data/test.nn").expect("Couldn't load neural network")), 1.0));
-    let black_agent: Option<Agent> =
+        // Some(Box::new(agent::random::RandomAgent::new()));
+        // Some(Box::new(agent::minimax::MinimaxAgent::with_config(agent::minimax::MinimaxConfig::default())));
+        // Some(Box::new(agent::monte_carlo::MonteCarloAgent::with_config(agent::monte_carlo::MctsConfig::default()))); // Currently ~37000 nodes
+        Some(Box::new(NeuralNetworkAgent::new(neural_network::read_network_from_file("/home/master/Project/Rust/chess-ai/data/test.nn").expect("Couldn't load neural network"))));
+    let black_agent: Option<Box<dyn Agent>> =
         None;
-        // Some(Agent::new(AgentType::Random, 1.0));
-        // Some(Agent::new(AgentType::Minimax, 1.0)); // Currently ~5
-        // Some(Agent::new(AgentType::MonteCarlo(Tree::new()), 1.0));
-        // Some(Agent::new(AgentType::NeuralNetwork(neural_network::read_network_from_file("/home/master/Project/Rust/chess-ai/data/test.nn").expect("Couldn't load neural network")), 1.0));
-
+        // Some(Box::new(agent::random::RandomAgent::new()));
+        // Some(Box::new(agent::minimax::MinimaxAgent::with_config(agent::minimax::MinimaxConfig::default()))); // Currently ~5
+        // Some(Box::new(agent::monte_carlo::MonteCarloAgent::with_config(agent::monte_carlo::MctsConfig::default())));
+        // Some(Box::new(NeuralNetworkAgent::new(neural_network::read_network_from_file("/home/master/Project/Rust/chess-ai/data/test.nn").expect("Couldn't load neural network"))));
+    let agent_limits: SearchLimits = SearchLimits::default();
     ggez::start(conf, move |context, quad_ctx| {
-        Box::new(Engine::new(context, quad_ctx, white_agent, black_agent).unwrap())
+        Box::new(Engine::new(context, quad_ctx, white_agent, black_agent, agent_limits, time_control).unwrap())
     })
 }
 
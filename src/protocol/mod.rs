@@ -0,0 +1,163 @@
+use std::sync::Arc;
+
+use crate::{agent::{book::{BookAgent, BookConfig, OpeningBook}, minimax::{MinimaxAgent, MinimaxConfig}, monte_carlo::{MctsConfig, MonteCarloAgent}, neural_network::{self, Network, NeuralNetworkAgent}, playout::PlayoutPolicy, random::RandomAgent, Agent}, core::{game::Game, move_generator::get_all_possible_moves, piece::PieceType, position::Position}};
+
+pub mod uci;
+pub mod xboard;
+
+pub const AGENT_NAMES: [&str; 4] = ["Minimax", "MonteCarlo", "NeuralNetwork", "Random"];
+/// Minimax techniques that can be switched on and off, e.g. to measure their strength
+pub const MINIMAX_SWITCHES: [&str; 5] = ["NullMovePruning", "LateMoveReductions", "FutilityPruning", "CheckExtensions", "QuiescenceChecks"];
+
+type Move = (Position, Position);
+
+/// Settings of all agents, which the front-ends expose as engine options
+#[derive(Clone, Debug)]
+pub struct AgentOptions {
+    pub network_file: String,
+    pub minimax: MinimaxConfig,
+    pub monte_carlo: MctsConfig,
+    /// Centipawns a draw is worse than an equal position for the agent
+    pub contempt: isize,
+    pub book: BookConfig
+}
+
+impl Default for AgentOptions {
+    fn default() -> AgentOptions {
+        AgentOptions {
+            network_file: String::from("data/test.nn"),
+            minimax: MinimaxConfig::default(),
+            monte_carlo: MctsConfig::default(),
+            contempt: 0,
+            book: BookConfig::default()
+        }
+    }
+}
+
+/// Parses coordinate notation like `e2e4`. Promotions like `e7e8q` are rejected
+pub fn parse_move(notation: &str) -> Option<Move> {
+    if notation.len() != 4 || !notation.is_ascii() {
+        return None
+    }
+
+    let from_pos: Position = Position::from_notation(&notation[0..2])?;
+    let to_pos: Position = Position::from_notation(&notation[2..4])?;
+    Some((from_pos, to_pos))
+}
+
+pub fn format_move((from_pos, to_pos): &Move) -> String {
+    format!("{}{}", from_pos.to_notation(), to_pos.to_notation())
+}
+
+/// Parses standard algebraic notation like `Nbd7`, `exd5+` or `O-O` in the position of `game`.
+/// Promotions are rejected
+pub fn parse_san(game: &Game, notation: &str) -> Option<Move> {
+    let notation: &str = notation.trim_end_matches(['+', '#', '!', '?']);
+    let row: u8 = if game.player_turn { 0 } else { 7 };
+    let castling_column: Option<u8> = match notation {
+        "O-O" | "0-0" => Some(6),
+        "O-O-O" | "0-0-0" => Some(2),
+        _ => None
+    };
+    if let Some(column) = castling_column {
+        let castling: Move = (Position::new(row, 4), Position::new(row, column));
+        let is_king: bool = game.board.get_piece_at(&castling.0).piece_type() == PieceType::King;
+        return (is_king && game.valid_turn(&castling.0, &castling.1)).then_some(castling)
+    }
+
+    if notation.contains('=') || notation.len() < 2 || !notation.is_ascii() {
+        return None
+    }
+    let piece_type: PieceType = match notation.as_bytes()[0] {
+        b'N' => PieceType::Knight,
+        b'B' => PieceType::Bishop,
+        b'R' => PieceType::Rook,
+        b'Q' => PieceType::Queen,
+        b'K' => PieceType::King,
+        _ => PieceType::Pawn
+    };
+    let piece_length: usize = usize::from(piece_type != PieceType::Pawn);
+    let (disambiguation, target) = notation[piece_length..].split_at(notation.len() - piece_length - 2);
+    let to_pos: Position = Position::from_notation(target)?;
+    let disambiguation: String = disambiguation.replace('x', "");
+
+    let mut candidates = get_all_possible_moves(&game.board, game.player_turn).into_iter()
+        .filter(|(from_pos, move_to_pos)| {
+            *move_to_pos == to_pos
+            && game.board.get_piece_at(from_pos).piece_type() == piece_type
+            && disambiguation.chars().all(|symbol| match symbol {
+                'a'..='h' => from_pos.column == symbol as u8 - b'a',
+                '1'..='8' => from_pos.row == symbol as u8 - b'1',
+                _ => false
+            })
+        });
+    // The generator may list a move twice, only different moves make the notation ambiguous
+    let san_move: Move = candidates.next()?;
+    candidates.all(|candidate| candidate == san_move).then_some(san_move)
+}
+
+/// Applies a move given in coordinate notation, if it is legal in `game`
+pub fn apply_move(game: &mut Game, notation: &str) -> Option<Move> {
+    let (from_pos, to_pos) = parse_move(notation)?;
+    if game.get_winner().is_some() || !game.valid_turn(&from_pos, &to_pos) {
+        return None
+    }
+
+    game.perform_move(&from_pos, &to_pos);
+    Some((from_pos, to_pos))
+}
+
+impl AgentOptions {
+    /// The setting behind one of the `MINIMAX_SWITCHES`, matched case insensitively
+    pub fn minimax_switch(&mut self, name: &str) -> Option<&mut bool> {
+        match name.to_ascii_lowercase().as_str() {
+            "nullmovepruning" => Some(&mut self.minimax.null_move_pruning),
+            "latemovereductions" => Some(&mut self.minimax.late_move_reductions),
+            "futilitypruning" => Some(&mut self.minimax.futility_pruning),
+            "checkextensions" => Some(&mut self.minimax.check_extensions),
+            "quiescencechecks" => Some(&mut self.minimax.quiescence_checks),
+            _ => None
+        }
+    }
+}
+
+/// Wraps the agent in a `BookAgent` if an opening book is configured
+pub fn create_agent(name: &str, options: &AgentOptions) -> Result<Box<dyn Agent>, String> {
+    let agent: Box<dyn Agent> = create_plain_agent(name, options)?;
+    let Some(book_file) = &options.book.book_file else {
+        return Ok(agent)
+    };
+
+    let book: OpeningBook = OpeningBook::open(book_file)
+        .map_err(|error| format!("Unable to open book '{}': {}", book_file, error))?;
+    Ok(Box::new(BookAgent::new(agent, Arc::new(book), options.book.depth, options.book.selection)))
+}
+
+fn create_plain_agent(name: &str, options: &AgentOptions) -> Result<Box<dyn Agent>, String> {
+    let network_file: &str = &options.network_file;
+    match name.to_ascii_lowercase().as_str() {
+        "random" => Ok(Box::new(RandomAgent::new())),
+        "minimax" => Ok(Box::new(MinimaxAgent::with_config(
+            MinimaxConfig { contempt: options.contempt, ..options.minimax.clone() }
+        ))),
+        "montecarlo" | "mcts" => {
+            let config: MctsConfig = MctsConfig { contempt: options.contempt, ..options.monte_carlo.clone() };
+            if config.playout != PlayoutPolicy::Neural {
+                return Ok(Box::new(MonteCarloAgent::with_config(config)))
+            }
+            let network: Network = read_network(network_file)?;
+            let evaluator = config.playout.evaluator(config.simulation_depth_limit, config.epsilon_simulation, Some(network));
+            Ok(Box::new(MonteCarloAgent::with_evaluator(config, evaluator)))
+        },
+        "neuralnetwork" | "neural" => read_network(network_file)
+            .map(|network| Box::new(NeuralNetworkAgent::new(network)) as Box<dyn Agent>),
+        _ => Err(format!("Unknown agent type '{}'", name))
+    }
+}
+
+fn read_network(network_file: &str) -> Result<Network, String> {
+    if !std::path::Path::new(network_file).is_file() {
+        return Err(format!("Network file '{}' does not exist", network_file));
+    }
+    neural_network::read_network_from_file(network_file).map_err(|error| error.to_string())
+}
@@ -0,0 +1,528 @@
+use std::{io::{self, BufRead}, sync::{atomic::{AtomicBool, Ordering}, Arc}, thread::{self, JoinHandle}};
+
+use crate::{agent::{book::BookSelection, monte_carlo::FinalMovePolicy, playout::PlayoutPolicy, Agent, SearchInfo, SearchLimits, MOVE_OVERHEAD}, core::{game::Game, move_generator::get_all_possible_moves, position::Position}};
+
+use super::{create_agent, apply_move, format_move, AgentOptions, AGENT_NAMES, MINIMAX_SWITCHES};
+
+const ENGINE_NAME: &str = "Chess AI";
+const ENGINE_AUTHOR: &str = "Purpurax";
+const DEFAULT_MOVE_TIME_MS: u64 = 1000;
+
+type Move = (Position, Position);
+
+/// Runs the UCI protocol on stdin and stdout until `quit` is received
+pub fn run() -> io::Result<()> {
+    let mut engine: UciEngine = UciEngine::new();
+
+    for line in io::stdin().lock().lines() {
+        let line: String = line?;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+
+        match tokens.first() {
+            Some(&"uci") => engine.identify(),
+            Some(&"isready") => println!("readyok"),
+            Some(&"setoption") => engine.set_option(&tokens[1..]),
+            Some(&"ucinewgame") => engine.new_game(),
+            Some(&"position") => engine.set_position(&tokens[1..]),
+            Some(&"go") => engine.go(GoParameters::parse(&tokens[1..])),
+            Some(&"stop") => engine.finish_search(),
+            Some(&"quit") => break,
+            _ => ()
+        }
+    }
+
+    engine.finish_search();
+    Ok(())
+}
+
+/// Parameters of a `go` command, times are given in milliseconds
+#[derive(Default, Debug, PartialEq)]
+struct GoParameters {
+    wtime: Option<u64>,
+    btime: Option<u64>,
+    winc: Option<u64>,
+    binc: Option<u64>,
+    movestogo: Option<u64>,
+    movetime: Option<u64>,
+    depth: Option<usize>,
+    nodes: Option<usize>,
+    infinite: bool
+}
+
+impl GoParameters {
+    fn parse(tokens: &[&str]) -> GoParameters {
+        let mut parameters: GoParameters = GoParameters::default();
+        let mut tokens = tokens.iter();
+
+        while let Some(token) = tokens.next() {
+            let mut value = || tokens.next().and_then(|value| value.parse::<u64>().ok());
+            match *token {
+                "wtime" => parameters.wtime = value(),
+                "btime" => parameters.btime = value(),
+                "winc" => parameters.winc = value(),
+                "binc" => parameters.binc = value(),
+                "movestogo" => parameters.movestogo = value(),
+                "movetime" => parameters.movetime = value(),
+                "depth" => parameters.depth = value().map(|depth| depth as usize),
+                "nodes" => parameters.nodes = value().map(|nodes| nodes as usize),
+                "infinite" => parameters.infinite = true,
+                _ => ()
+            }
+        }
+
+        parameters
+    }
+
+    /// Without a clock or move time the default move time applies,
+    /// unless the search is bounded by depth or nodes instead
+    fn limits(&self, player_turn: bool, default_move_time: u64) -> SearchLimits {
+        let (time_left, increment) = if player_turn {
+            (self.wtime, self.winc.unwrap_or(0))
+        } else {
+            (self.btime, self.binc.unwrap_or(0))
+        };
+
+        let time: Option<f64> = if let Some(move_time) = self.movetime {
+            Some(move_time as f64 / 1000.0 - MOVE_OVERHEAD)
+        } else if time_left.is_none() && self.depth.is_none() && self.nodes.is_none() {
+            Some(default_move_time as f64 / 1000.0)
+        } else {
+            None
+        };
+
+        SearchLimits {
+            time,
+            remaining_time: time_left.map(|time_left| time_left as f64 / 1000.0),
+            increment: increment as f64 / 1000.0,
+            moves_to_go: self.movestogo.map(|moves_to_go| moves_to_go as usize),
+            depth: self.depth,
+            nodes: self.nodes,
+            infinite: self.infinite,
+            stop: Arc::new(AtomicBool::new(false))
+        }
+    }
+}
+
+struct Search {
+    handle: JoinHandle<(Box<dyn Agent>, Option<Move>)>,
+    infinite: bool,
+    stop: Arc<AtomicBool>
+}
+
+struct UciEngine {
+    agent_name: String,
+    options: AgentOptions,
+    move_time: u64,
+
+    base_position: String,
+    moves: Vec<String>,
+    game: Game,
+
+    agent: Option<Box<dyn Agent>>,
+    search: Option<Search>
+}
+
+impl UciEngine {
+    fn new() -> UciEngine {
+        UciEngine {
+            agent_name: String::from("Minimax"),
+            options: AgentOptions::default(),
+            move_time: DEFAULT_MOVE_TIME_MS,
+            base_position: String::from("startpos"),
+            moves: vec![],
+            game: Game::new(),
+            agent: None,
+            search: None
+        }
+    }
+
+    fn identify(&self) {
+        println!("id name {}", ENGINE_NAME);
+        println!("id author {}", ENGINE_AUTHOR);
+        println!(
+            "option name Agent type combo default {} {}",
+            self.agent_name,
+            AGENT_NAMES.map(|name| format!("var {}", name)).join(" ")
+        );
+        println!("option name MoveTime type spin default {} min 10 max 3600000", self.move_time);
+        println!("option name NetworkFile type string default {}", self.options.network_file);
+        println!("option name Hash type spin default {} min 1 max 4096", self.options.minimax.hash_size_mb);
+        println!("option name Threads type spin default {} min 1 max 256", self.options.minimax.threads);
+        println!("option name Contempt type spin default {} min -1000 max 1000", self.options.contempt);
+        println!("option name MctsExploration type string default {}", self.options.monte_carlo.exploration_c);
+        println!("option name MctsSimulationDepth type spin default {} min 0 max 1000", self.options.monte_carlo.simulation_depth_limit);
+        println!("option name MctsThreads type spin default {} min 1 max 256", self.options.monte_carlo.threads);
+        println!("option name MctsEpsilon type string default {}", self.options.monte_carlo.epsilon_simulation);
+        println!(
+            "option name MctsPlayout type combo default {} {}",
+            self.options.monte_carlo.playout.name(),
+            PlayoutPolicy::NAMES.map(|name| format!("var {}", name)).join(" ")
+        );
+        println!("option name MctsNodes type spin default {} min 1000 max 50000000", self.options.monte_carlo.node_budget);
+        println!(
+            "option name MctsFinalMove type combo default {} {}",
+            self.options.monte_carlo.final_move.name(),
+            FinalMovePolicy::NAMES.map(|name| format!("var {}", name)).join(" ")
+        );
+        println!("option name BookFile type string default {}", self.options.book.book_file.as_deref().unwrap_or("<empty>"));
+        println!("option name BookDepth type spin default {} min 0 max 500", self.options.book.depth);
+        println!(
+            "option name BookSelection type combo default {} {}",
+            self.options.book.selection.name(),
+            BookSelection::NAMES.map(|name| format!("var {}", name)).join(" ")
+        );
+        let mut options: AgentOptions = self.options.clone();
+        for name in MINIMAX_SWITCHES {
+            if let Some(enabled) = options.minimax_switch(name) {
+                println!("option name {} type check default {}", name, enabled);
+            }
+        }
+        println!("uciok");
+    }
+
+    fn set_option(&mut self, tokens: &[&str]) {
+        let value_index: usize = tokens.iter().position(|token| *token == "value").unwrap_or(tokens.len());
+        let name: String = tokens.get(1..value_index).unwrap_or_default().join(" ");
+        let value: String = tokens.get(value_index + 1..).unwrap_or_default().join(" ");
+
+        self.finish_search();
+        match name.to_ascii_lowercase().as_str() {
+            "agent" => {
+                self.agent_name = value;
+                self.agent = None;
+            },
+            "movetime" => match value.parse::<u64>() {
+                Ok(move_time) => self.move_time = move_time,
+                Err(_) => println!("info string invalid MoveTime '{}'", value)
+            },
+            "networkfile" => {
+                self.options.network_file = value;
+                self.agent = None;
+            },
+            "hash" => match value.parse::<usize>() {
+                Ok(hash_size_mb) => {
+                    self.options.minimax.hash_size_mb = hash_size_mb;
+                    self.agent = None;
+                },
+                Err(_) => println!("info string invalid Hash '{}'", value)
+            },
+            "threads" => match value.parse::<usize>() {
+                Ok(threads) if threads > 0 => {
+                    self.options.minimax.threads = threads;
+                    self.agent = None;
+                },
+                _ => println!("info string invalid Threads '{}'", value)
+            },
+            "contempt" => match value.parse::<isize>() {
+                Ok(contempt) => {
+                    self.options.contempt = contempt;
+                    self.agent = None;
+                },
+                Err(_) => println!("info string invalid Contempt '{}'", value)
+            },
+            "mctsexploration" => match value.parse::<f64>() {
+                Ok(exploration_c) if exploration_c >= 0.0 => {
+                    self.options.monte_carlo.exploration_c = exploration_c;
+                    self.agent = None;
+                },
+                _ => println!("info string invalid MctsExploration '{}'", value)
+            },
+            "mctssimulationdepth" => match value.parse::<usize>() {
+                Ok(depth) => {
+                    self.options.monte_carlo.simulation_depth_limit = depth;
+                    self.agent = None;
+                },
+                Err(_) => println!("info string invalid MctsSimulationDepth '{}'", value)
+            },
+            "mctsthreads" => match value.parse::<usize>() {
+                Ok(threads) if threads > 0 => {
+                    self.options.monte_carlo.threads = threads;
+                    self.agent = None;
+                },
+                _ => println!("info string invalid MctsThreads '{}'", value)
+            },
+            "mctsepsilon" => match value.parse::<f64>() {
+                Ok(epsilon) if (0.0..=1.0).contains(&epsilon) => {
+                    self.options.monte_carlo.epsilon_simulation = epsilon;
+                    self.agent = None;
+                },
+                _ => println!("info string invalid MctsEpsilon '{}'", value)
+            },
+            "mctsplayout" => match PlayoutPolicy::from_name(&value) {
+                Some(playout) => {
+                    self.options.monte_carlo.playout = playout;
+                    self.agent = None;
+                },
+                None => println!("info string invalid MctsPlayout '{}'", value)
+            },
+            "mctsnodes" => match value.parse::<usize>() {
+                Ok(node_budget) if node_budget >= 1000 => {
+                    self.options.monte_carlo.node_budget = node_budget;
+                    self.agent = None;
+                },
+                _ => println!("info string invalid MctsNodes '{}'", value)
+            },
+            "mctsfinalmove" => match FinalMovePolicy::from_name(&value) {
+                Some(final_move) => {
+                    self.options.monte_carlo.final_move = final_move;
+                    self.agent = None;
+                },
+                None => println!("info string invalid MctsFinalMove '{}'", value)
+            },
+            "bookfile" => {
+                self.options.book.book_file = Some(value).filter(|file| !file.is_empty() && file != "<empty>");
+                self.agent = None;
+            },
+            "bookdepth" => match value.parse::<usize>() {
+                Ok(depth) => {
+                    self.options.book.depth = depth;
+                    self.agent = None;
+                },
+                Err(_) => println!("info string invalid BookDepth '{}'", value)
+            },
+            "bookselection" => match BookSelection::from_name(&value) {
+                Some(selection) => {
+                    self.options.book.selection = selection;
+                    self.agent = None;
+                },
+                None => println!("info string invalid BookSelection '{}'", value)
+            },
+            switch => match (self.options.minimax_switch(switch), value.parse::<bool>()) {
+                (Some(enabled), Ok(value)) => {
+                    *enabled = value;
+                    self.agent = None;
+                },
+                (Some(_), Err(_)) => println!("info string invalid {} '{}'", name, value),
+                (None, _) => println!("info string unknown option '{}'", name)
+            }
+        }
+    }
+
+    fn new_game(&mut self) {
+        self.finish_search();
+        self.agent = None;
+        self.base_position = String::from("startpos");
+        self.moves = vec![];
+        self.game = Game::new();
+    }
+
+    fn set_position(&mut self, tokens: &[&str]) {
+        self.finish_search();
+
+        let moves_index: usize = tokens.iter().position(|token| *token == "moves").unwrap_or(tokens.len());
+        let base_position: String = tokens[..moves_index].join(" ");
+        let moves: Vec<String> = tokens.get(moves_index + 1..).unwrap_or_default()
+            .iter()
+            .map(|token| token.to_string())
+            .collect();
+
+        let base_game: Result<Game, String> = match tokens.first() {
+            Some(&"startpos") => Ok(Game::new()),
+            Some(&"fen") => Game::from_fen(&tokens[1..moves_index].join(" ")),
+            _ => Err(String::from("expected startpos or fen"))
+        };
+        let mut game: Game = match base_game {
+            Ok(game) => game,
+            Err(error) => return println!("info string invalid position: {}", error)
+        };
+
+        for notation in moves.iter() {
+            if apply_move(&mut game, notation).is_none() {
+                return println!("info string illegal move {}", notation);
+            }
+        }
+
+        // Keep the agent state, e.g. the MCTS tree, if the game just continued
+        let continues_game: bool = base_position == self.base_position
+            && moves.starts_with(&self.moves);
+        if let Some(agent) = &mut self.agent {
+            if continues_game {
+                moves[self.moves.len()..].iter()
+                    .filter_map(|notation| super::parse_move(notation))
+                    .for_each(|(from_pos, to_pos)| agent.notify_move(&from_pos, &to_pos));
+            } else {
+                agent.new_game(&game);
+            }
+        }
+
+        self.base_position = base_position;
+        self.moves = moves;
+        self.game = game;
+    }
+
+    fn go(&mut self, parameters: GoParameters) {
+        self.finish_search();
+
+        if self.game.get_winner().is_some()
+        || get_all_possible_moves(&self.game.board, self.game.player_turn).is_empty() {
+            return println!("bestmove 0000");
+        }
+
+        let mut agent: Box<dyn Agent> = match self.agent.take() {
+            Some(agent) => agent,
+            None => match self.create_agent() {
+                Ok(agent) => agent,
+                Err(error) => return println!("info string {}", error)
+            }
+        };
+        let limits: SearchLimits = parameters.limits(self.game.player_turn, self.move_time);
+        let stop: Arc<AtomicBool> = limits.stop.clone();
+
+        let infinite: bool = parameters.infinite;
+        let handle = thread::spawn(move || {
+            let best_move: Move = agent.choose_move(&limits);
+            if !infinite {
+                println!("bestmove {}", format_move(&best_move));
+            }
+            (agent, Some(best_move))
+        });
+
+        self.search = Some(Search { handle, infinite, stop });
+    }
+
+    fn create_agent(&self) -> Result<Box<dyn Agent>, String> {
+        let mut agent: Box<dyn Agent> = create_agent(&self.agent_name, &self.options)?;
+        agent.set_reporter(Arc::new(|info: &SearchInfo| println!("{}", format_info(info))));
+        agent.new_game(&self.game);
+        Ok(agent)
+    }
+
+    /// Stops a running search and reports its move, if not done already
+    fn finish_search(&mut self) {
+        let Some(search) = self.search.take() else {
+            return
+        };
+
+        search.stop.store(true, Ordering::Relaxed);
+        match search.handle.join() {
+            Ok((agent, best_move)) => {
+                if search.infinite {
+                    match best_move {
+                        Some(best_move) => println!("bestmove {}", format_move(&best_move)),
+                        None => println!("bestmove 0000")
+                    }
+                }
+                self.agent = Some(agent);
+            },
+            Err(_) => println!("bestmove 0000")
+        }
+    }
+}
+
+fn format_info(info: &SearchInfo) -> String {
+    let pv: Vec<String> = info.pv.iter().map(format_move).collect();
+    format!(
+        "info depth {} score {} nodes {} time {} pv {}",
+        info.depth,
+        match info.mate {
+            Some(mate) => format!("mate {}", mate),
+            None => format!("cp {}", info.score)
+        },
+        info.nodes,
+        (info.time * 1000.0).round() as u64,
+        pv.join(" ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_go_parameters() {
+        let parameters: GoParameters = GoParameters::parse(
+            &["wtime", "60000", "btime", "30000", "winc", "1000", "depth", "6", "infinite"]
+        );
+
+        assert_eq!(parameters, GoParameters {
+            wtime: Some(60000),
+            btime: Some(30000),
+            winc: Some(1000),
+            depth: Some(6),
+            infinite: true,
+            ..GoParameters::default()
+        });
+    }
+
+    #[test]
+    fn budgets_time_from_clock() {
+        let parameters: GoParameters = GoParameters::parse(&["wtime", "60000", "btime", "3000"]);
+
+        assert_eq!(parameters.limits(true, 1000).time_budget(), Some(2.0));
+        assert_eq!(parameters.limits(false, 1000).time_budget(), Some(0.1));
+
+        let move_time: f64 = GoParameters::parse(&["movetime", "500"]).limits(true, 1000).time_budget().unwrap();
+        assert!((move_time - 0.45).abs() < 1e-9);
+    }
+
+    #[test]
+    fn limits_by_depth_without_clock() {
+        let limits: SearchLimits = GoParameters::parse(&["depth", "4"]).limits(true, 1000);
+        assert_eq!(limits.time, None);
+        assert_eq!(limits.depth, Some(4));
+
+        let limits: SearchLimits = GoParameters::parse(&["nodes", "500", "wtime", "2000"]).limits(true, 1000);
+        assert_eq!(limits.nodes, Some(500));
+        assert_eq!(limits.remaining_time, Some(2.0));
+        assert_eq!(GoParameters::parse(&[]).limits(true, 1000).time, Some(1.0));
+    }
+
+    #[test]
+    fn switches_minimax_techniques() {
+        let mut engine: UciEngine = UciEngine::new();
+        engine.set_option(&["name", "NullMovePruning", "value", "false"]);
+        engine.set_option(&["name", "Hash", "value", "32"]);
+
+        assert!(!engine.options.minimax.null_move_pruning);
+        assert!(engine.options.minimax.late_move_reductions);
+        assert_eq!(engine.options.minimax.hash_size_mb, 32);
+    }
+
+    #[test]
+    fn configures_monte_carlo() {
+        let mut engine: UciEngine = UciEngine::new();
+        engine.set_option(&["name", "MctsExploration", "value", "1.4"]);
+        engine.set_option(&["name", "MctsThreads", "value", "2"]);
+        engine.set_option(&["name", "MctsFinalMove", "value", "RobustMax"]);
+        engine.set_option(&["name", "MctsEpsilon", "value", "2"]);
+        engine.set_option(&["name", "MctsNodes", "value", "20000"]);
+        engine.set_option(&["name", "MctsPlayout", "value", "static"]);
+
+        assert_eq!(engine.options.monte_carlo.exploration_c, 1.4);
+        assert_eq!(engine.options.monte_carlo.threads, 2);
+        assert_eq!(engine.options.monte_carlo.final_move, FinalMovePolicy::RobustMax);
+        assert_eq!(engine.options.monte_carlo.epsilon_simulation, 0.5);
+        assert_eq!(engine.options.monte_carlo.node_budget, 20000);
+        assert_eq!(engine.options.monte_carlo.playout, PlayoutPolicy::Static);
+
+        // Plays with the configured policy
+        engine.set_option(&["name", "Agent", "value", "MonteCarlo"]);
+        engine.set_position(&["startpos"]);
+        let mut agent: Box<dyn Agent> = create_agent(&engine.agent_name, &engine.options).unwrap();
+        agent.new_game(&engine.game);
+        let (from_pos, to_pos) = agent.choose_move(&SearchLimits::nodes(200));
+        assert!(engine.game.valid_turn(&from_pos, &to_pos));
+    }
+
+    #[test]
+    fn rejects_promotions() {
+        let mut engine: UciEngine = UciEngine::new();
+        engine.set_position(&["fen", "4k3/P7/8/8/8/8/8/4K3", "w", "-", "-", "0", "1", "moves", "a7a8q"]);
+        assert!(engine.moves.is_empty());
+        assert!(engine.game.player_turn);
+    }
+
+    #[test]
+    fn continues_known_positions() {
+        let mut engine: UciEngine = UciEngine::new();
+        engine.set_position(&["startpos", "moves", "e2e4", "e7e5", "g1f3"]);
+
+        assert_eq!(engine.moves.len(), 3);
+        assert!(!engine.game.player_turn);
+        assert_eq!(engine.game.step_counter, 3);
+
+        engine.set_position(&["fen", "4k3/8/8/8/8/8/8/4K2R", "w", "K", "-", "0", "1", "moves", "e1g1"]);
+        assert_eq!(engine.game.board.get_piece_at(&Position::new(0, 5)).piece_type(),
+            crate::core::piece::PieceType::Rook);
+    }
+}
@@ -0,0 +1,530 @@
+use std::{io::{self, BufRead}, sync::{atomic::{AtomicBool, Ordering}, Arc}, thread::{self, JoinHandle}};
+
+use crate::{
+    agent::{book::BookSelection, history::{PositionHistory, FIFTY_MOVE_PLIES}, monte_carlo::FinalMovePolicy, playout::PlayoutPolicy, Agent, SearchInfo, SearchLimits, MOVE_OVERHEAD},
+    core::{game::Game, move_generator::get_all_possible_moves, position::Position, zobrist}
+};
+
+use super::{create_agent, apply_move, format_move, parse_move, AgentOptions, AGENT_NAMES, MINIMAX_SWITCHES};
+
+type Move = (Position, Position);
+
+/// Commands that leave a running search alone, all others end it before they are handled
+const COMMANDS_DURING_SEARCH: [&str; 15] = [
+    "?", "post", "nopost", "ping", "time", "otim", "xboard", "accepted", "rejected", "random", "hard", "easy", "computer", "name", "rating"
+];
+
+/// Runs the CECP (xboard/WinBoard) protocol on stdin and stdout until `quit` is received
+pub fn run() -> io::Result<()> {
+    let mut engine: XboardEngine = XboardEngine::new();
+
+    for line in io::stdin().lock().lines() {
+        let line: String = line?;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let argument: String = tokens.get(1..).unwrap_or_default().join(" ");
+
+        if tokens.first().is_some_and(|command| !COMMANDS_DURING_SEARCH.contains(command)) {
+            engine.finish_search(true);
+        }
+        match tokens.first() {
+            Some(&"protover") => engine.announce_features(),
+            Some(&"new") => engine.new_game(),
+            Some(&"setboard") => engine.set_board(&argument),
+            Some(&"usermove") => engine.user_move(&argument),
+            Some(&"go") => {
+                engine.force_mode = false;
+                engine.engine_color = Some(engine.game.player_turn);
+                engine.think();
+            },
+            Some(&"force") => engine.force_mode = true,
+            Some(&"playother") => {
+                engine.force_mode = false;
+                engine.engine_color = Some(!engine.game.player_turn);
+            },
+            Some(&"level") => engine.set_level(&tokens[1..]),
+            Some(&"st") => engine.fixed_move_time = argument.parse::<f64>().ok(),
+            Some(&"sd") => engine.max_depth = argument.parse::<usize>().ok(),
+            Some(&"time") => engine.own_time = centiseconds(&argument),
+            Some(&"otim") => (),
+            Some(&"undo") => engine.take_back(1),
+            Some(&"remove") => engine.take_back(2),
+            Some(&"result") => engine.force_mode = true,
+            Some(&"post") => engine.post.store(true, Ordering::Relaxed),
+            Some(&"nopost") => engine.post.store(false, Ordering::Relaxed),
+            Some(&"option") => engine.set_option(&argument),
+            Some(&"memory") => engine.set_memory(&argument),
+            Some(&"cores") => engine.set_cores(&argument),
+            Some(&"?") => engine.finish_search(false),
+            Some(&"ping") => println!("pong {}", argument),
+            Some(&"quit") => break,
+            Some(&("xboard" | "accepted" | "rejected" | "random" | "hard" | "easy" | "computer" | "name" | "rating")) => (),
+            Some(notation) if parse_move(notation).is_some() => engine.user_move(notation),
+            Some(command) => println!("Error (unknown command): {}", command),
+            None => ()
+        }
+    }
+
+    engine.finish_search(true);
+    Ok(())
+}
+
+fn centiseconds(argument: &str) -> Option<f64> {
+    argument.parse::<f64>().ok().map(|centiseconds| centiseconds / 100.0)
+}
+
+/// Search of the engine's move on a worker thread, which sends the move itself once it is found
+struct Search {
+    /// Returns the move, if it was sent
+    handle: JoinHandle<(Box<dyn Agent>, Option<Move>)>,
+    stop: Arc<AtomicBool>,
+    /// Keeps the move from being sent, e.g. after `force`
+    cancel: Arc<AtomicBool>
+}
+
+struct XboardEngine {
+    agent_name: String,
+    options: AgentOptions,
+
+    base_game: Game,
+    moves: Vec<String>,
+    game: Game,
+    history: PositionHistory,
+
+    force_mode: bool,
+    engine_color: Option<bool>,
+    post: Arc<AtomicBool>,
+
+    moves_per_session: usize,
+    base_time: f64,
+    increment: f64,
+    fixed_move_time: Option<f64>,
+    max_depth: Option<usize>,
+    own_time: Option<f64>,
+
+    agent: Option<Box<dyn Agent>>,
+    search: Option<Search>
+}
+
+impl XboardEngine {
+    fn new() -> XboardEngine {
+        XboardEngine {
+            agent_name: String::from("Minimax"),
+            options: AgentOptions::default(),
+            base_game: Game::new(),
+            moves: vec![],
+            game: Game::new(),
+            history: PositionHistory::new(&Game::new()),
+            force_mode: false,
+            engine_color: Some(false),
+            post: Arc::new(AtomicBool::new(false)),
+            moves_per_session: 0,
+            base_time: 300.0,
+            increment: 0.0,
+            fixed_move_time: None,
+            max_depth: None,
+            own_time: None,
+            agent: None,
+            search: None
+        }
+    }
+
+    fn announce_features(&self) {
+        println!(
+            "feature myname=\"Chess AI\" usermove=1 setboard=1 ping=1 playother=1 memory=1 smp=1 sigint=0 sigterm=0 colors=0 done=0"
+        );
+        println!(
+            "feature option=\"Agent -combo {}\"",
+            AGENT_NAMES.map(|name| if name == self.agent_name { format!("*{}", name) } else { name.to_string() })
+                .join(" /// ")
+        );
+        println!("feature option=\"NetworkFile -file {}\"", self.options.network_file);
+        println!("feature option=\"Contempt -spin {} -1000 1000\"", self.options.contempt);
+        println!("feature option=\"MctsExploration -string {}\"", self.options.monte_carlo.exploration_c);
+        println!("feature option=\"MctsSimulationDepth -spin {} 0 1000\"", self.options.monte_carlo.simulation_depth_limit);
+        println!("feature option=\"MctsThreads -spin {} 1 256\"", self.options.monte_carlo.threads);
+        println!("feature option=\"MctsEpsilon -string {}\"", self.options.monte_carlo.epsilon_simulation);
+        println!(
+            "feature option=\"MctsPlayout -combo {}\"",
+            PlayoutPolicy::NAMES.map(|name| if name == self.options.monte_carlo.playout.name() { format!("*{}", name) } else { name.to_string() })
+                .join(" /// ")
+        );
+        println!("feature option=\"MctsNodes -spin {} 1000 50000000\"", self.options.monte_carlo.node_budget);
+        println!(
+            "feature option=\"MctsFinalMove -combo {}\"",
+            FinalMovePolicy::NAMES.map(|name| if name == self.options.monte_carlo.final_move.name() { format!("*{}", name) } else { name.to_string() })
+                .join(" /// ")
+        );
+        println!("feature option=\"BookFile -file {}\"", self.options.book.book_file.as_deref().unwrap_or(""));
+        println!("feature option=\"BookDepth -spin {} 0 500\"", self.options.book.depth);
+        println!(
+            "feature option=\"BookSelection -combo {}\"",
+            BookSelection::NAMES.map(|name| if name == self.options.book.selection.name() { format!("*{}", name) } else { name.to_string() })
+                .join(" /// ")
+        );
+        let mut options: AgentOptions = self.options.clone();
+        for name in MINIMAX_SWITCHES {
+            if let Some(enabled) = options.minimax_switch(name) {
+                println!("feature option=\"{} -check {}\"", name, u8::from(*enabled));
+            }
+        }
+        println!("feature done=1");
+    }
+
+    fn set_option(&mut self, argument: &str) {
+        let Some((name, value)) = argument.split_once('=') else {
+            return println!("Error (malformed option): {}", argument);
+        };
+
+        match name {
+            "Agent" => self.agent_name = value.to_string(),
+            "NetworkFile" => self.options.network_file = value.to_string(),
+            "Contempt" => match value.parse::<isize>() {
+                Ok(contempt) => self.options.contempt = contempt,
+                Err(_) => return println!("Error (malformed Contempt): {}", value)
+            },
+            "MctsExploration" => match value.parse::<f64>() {
+                Ok(exploration_c) if exploration_c >= 0.0 => self.options.monte_carlo.exploration_c = exploration_c,
+                _ => return println!("Error (malformed MctsExploration): {}", value)
+            },
+            "MctsSimulationDepth" => match value.parse::<usize>() {
+                Ok(depth) => self.options.monte_carlo.simulation_depth_limit = depth,
+                Err(_) => return println!("Error (malformed MctsSimulationDepth): {}", value)
+            },
+            "MctsThreads" => match value.parse::<usize>() {
+                Ok(threads) if threads > 0 => self.options.monte_carlo.threads = threads,
+                _ => return println!("Error (malformed MctsThreads): {}", value)
+            },
+            "MctsEpsilon" => match value.parse::<f64>() {
+                Ok(epsilon) if (0.0..=1.0).contains(&epsilon) => self.options.monte_carlo.epsilon_simulation = epsilon,
+                _ => return println!("Error (malformed MctsEpsilon): {}", value)
+            },
+            "MctsPlayout" => match PlayoutPolicy::from_name(value) {
+                Some(playout) => self.options.monte_carlo.playout = playout,
+                None => return println!("Error (malformed MctsPlayout): {}", value)
+            },
+            "MctsNodes" => match value.parse::<usize>() {
+                Ok(node_budget) if node_budget >= 1000 => self.options.monte_carlo.node_budget = node_budget,
+                _ => return println!("Error (malformed MctsNodes): {}", value)
+            },
+            "MctsFinalMove" => match FinalMovePolicy::from_name(value) {
+                Some(final_move) => self.options.monte_carlo.final_move = final_move,
+                None => return println!("Error (malformed MctsFinalMove): {}", value)
+            },
+            "BookFile" => self.options.book.book_file = Some(value.to_string()).filter(|file| !file.is_empty()),
+            "BookDepth" => match value.parse::<usize>() {
+                Ok(depth) => self.options.book.depth = depth,
+                Err(_) => return println!("Error (malformed BookDepth): {}", value)
+            },
+            "BookSelection" => match BookSelection::from_name(value) {
+                Some(selection) => self.options.book.selection = selection,
+                None => return println!("Error (malformed BookSelection): {}", value)
+            },
+            switch => match self.options.minimax_switch(switch) {
+                Some(enabled) => *enabled = value == "1",
+                None => return println!("Error (unknown option): {}", name)
+            }
+        }
+        self.agent = None;
+    }
+
+    fn set_memory(&mut self, argument: &str) {
+        match argument.parse::<usize>() {
+            Ok(size_mb) => {
+                self.options.minimax.hash_size_mb = size_mb;
+                self.agent = None;
+            },
+            Err(_) => println!("Error (malformed memory): {}", argument)
+        }
+    }
+
+    fn set_cores(&mut self, argument: &str) {
+        match argument.parse::<usize>() {
+            Ok(threads) if threads > 0 => {
+                self.options.minimax.threads = threads;
+                self.agent = None;
+            },
+            _ => println!("Error (malformed cores): {}", argument)
+        }
+    }
+
+    fn new_game(&mut self) {
+        self.reset(Game::new());
+        self.force_mode = false;
+        self.engine_color = Some(false);
+        self.fixed_move_time = None;
+        self.max_depth = None;
+    }
+
+    fn set_board(&mut self, fen: &str) {
+        match Game::from_fen(fen) {
+            Ok(game) => self.reset(game),
+            Err(error) => println!("tellusererror Illegal position: {}", error)
+        }
+    }
+
+    fn reset(&mut self, game: Game) {
+        self.reset_board(game);
+        if let Some(agent) = &mut self.agent {
+            agent.new_game(&self.game);
+        }
+    }
+
+    /// Sets up the board without telling the agent
+    fn reset_board(&mut self, game: Game) {
+        self.base_game = game.clone();
+        self.moves = vec![];
+        self.history = PositionHistory::new(&game);
+        self.game = game;
+    }
+
+    fn set_level(&mut self, tokens: &[&str]) {
+        let [moves_per_session, base_time, increment] = tokens else {
+            return println!("Error (malformed level): {}", tokens.join(" "));
+        };
+
+        let base_time: Option<f64> = match base_time.split_once(':') {
+            Some((minutes, seconds)) => minutes.parse::<f64>().ok()
+                .zip(seconds.parse::<f64>().ok())
+                .map(|(minutes, seconds)| minutes * 60.0 + seconds),
+            None => base_time.parse::<f64>().ok().map(|minutes| minutes * 60.0)
+        };
+
+        self.moves_per_session = moves_per_session.parse::<usize>().unwrap_or(0);
+        self.base_time = base_time.unwrap_or(self.base_time);
+        self.increment = increment.parse::<f64>().unwrap_or(0.0);
+        self.fixed_move_time = None;
+    }
+
+    fn user_move(&mut self, notation: &str) {
+        if !self.play_move(notation) {
+            return println!("Illegal move: {}", notation);
+        }
+        if let Some(result) = game_result(&self.game, &self.history) {
+            return println!("{}", result);
+        }
+
+        if !self.force_mode && self.engine_color == Some(self.game.player_turn) {
+            self.think();
+        }
+    }
+
+    fn play_move(&mut self, notation: &str) -> bool {
+        let Some((from_pos, to_pos)) = apply_move(&mut self.game, notation) else {
+            return false
+        };
+
+        self.moves.push(notation.to_string());
+        self.history.push(zobrist::hash_game(&self.game));
+        if let Some(agent) = &mut self.agent {
+            agent.notify_move(&from_pos, &to_pos);
+        }
+        true
+    }
+
+    fn take_back(&mut self, plies: usize) {
+        let remaining: usize = self.moves.len().saturating_sub(plies);
+        let moves: Vec<String> = self.moves[..remaining].to_vec();
+
+        self.reset_board(self.base_game.clone());
+        for notation in moves.iter() {
+            apply_move(&mut self.game, notation);
+            self.history.push(zobrist::hash_game(&self.game));
+        }
+        if let Some(agent) = &mut self.agent {
+            agent.new_game(&self.game);
+        }
+        self.moves = moves;
+    }
+
+    /// Starts searching the engine's move, commands are read on while it runs
+    fn think(&mut self) {
+        if game_result(&self.game, &self.history).is_some()
+        || get_all_possible_moves(&self.game.board, self.game.player_turn).is_empty() {
+            return;
+        }
+
+        let mut agent: Box<dyn Agent> = match self.agent.take() {
+            Some(agent) => agent,
+            None => match self.create_agent() {
+                Ok(agent) => agent,
+                Err(error) => return println!("tellusererror {}", error)
+            }
+        };
+        let limits: SearchLimits = self.limits();
+        let stop: Arc<AtomicBool> = limits.stop.clone();
+        let cancel: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+
+        let cancelled: Arc<AtomicBool> = cancel.clone();
+        let mut game: Game = self.game.clone();
+        let mut history: PositionHistory = self.history.clone();
+        let handle = thread::spawn(move || {
+            let best_move: Move = agent.choose_move(&limits);
+            if cancelled.load(Ordering::Relaxed) {
+                return (agent, None)
+            }
+
+            println!("move {}", format_move(&best_move));
+            game.perform_move(&best_move.0, &best_move.1);
+            history.push(zobrist::hash_game(&game));
+            if let Some(result) = game_result(&game, &history) {
+                println!("{}", result);
+            }
+            (agent, Some(best_move))
+        });
+
+        self.search = Some(Search { handle, stop, cancel });
+    }
+
+    /// Stops a running search and plays the move it sent. With `cancel` the move is not sent anymore,
+    /// unless it was already
+    fn finish_search(&mut self, cancel: bool) {
+        let Some(search) = self.search.take() else {
+            return
+        };
+
+        search.cancel.store(cancel, Ordering::Relaxed);
+        search.stop.store(true, Ordering::Relaxed);
+        let Ok((agent, best_move)) = search.handle.join() else {
+            return
+        };
+        self.agent = Some(agent);
+        if let Some(best_move) = best_move {
+            self.play_move(&format_move(&best_move));
+        }
+    }
+
+    fn limits(&self) -> SearchLimits {
+        if let Some(fixed_move_time) = self.fixed_move_time {
+            return SearchLimits {
+                depth: self.max_depth,
+                ..SearchLimits::time(fixed_move_time - MOVE_OVERHEAD)
+            };
+        }
+
+        let moves_to_go: Option<usize> = (self.moves_per_session > 0).then(|| {
+            let own_moves_played: usize = self.moves.len() / 2;
+            self.moves_per_session - own_moves_played % self.moves_per_session
+        });
+
+        SearchLimits {
+            remaining_time: Some(self.own_time.unwrap_or(self.base_time)),
+            increment: self.increment,
+            moves_to_go,
+            depth: self.max_depth,
+            ..SearchLimits::default()
+        }
+    }
+
+    fn create_agent(&self) -> Result<Box<dyn Agent>, String> {
+        let mut agent: Box<dyn Agent> = create_agent(&self.agent_name, &self.options)?;
+        let post: Arc<AtomicBool> = self.post.clone();
+        agent.set_reporter(Arc::new(move |info: &SearchInfo| {
+            if post.load(Ordering::Relaxed) {
+                println!("{}", format_thinking(info));
+            }
+        }));
+        agent.new_game(&self.game);
+        Ok(agent)
+    }
+}
+
+/// The result command for a finished game, with the reason it ended
+fn game_result(game: &Game, history: &PositionHistory) -> Option<&'static str> {
+    match game.get_winner() {
+        Some(1) => Some("1-0 {White mates}"),
+        Some(0) => Some("0-1 {Black mates}"),
+        Some(_) => Some("1/2-1/2 {Stalemate}"),
+        None if game.halfmove_clock >= FIFTY_MOVE_PLIES => Some("1/2-1/2 {Draw by fifty move rule}"),
+        None if history.is_repetition(game.halfmove_clock) => Some("1/2-1/2 {Draw by repetition}"),
+        None => None
+    }
+}
+
+/// Thinking output as `ply score time nodes pv`, with the time in centiseconds.
+/// Mates in N are scored 100000 + N, getting mated in N -100000 - N
+fn format_thinking(info: &SearchInfo) -> String {
+    let pv: Vec<String> = info.pv.iter().map(format_move).collect();
+    format!(
+        "{} {} {} {} {}",
+        info.depth,
+        match info.mate {
+            Some(mate) => mate.signum() * 100000 + mate,
+            None => info.score
+        },
+        (info.time * 100.0).round() as u64,
+        info.nodes,
+        pv.join(" ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn undo_replays_the_game() {
+        let mut engine: XboardEngine = XboardEngine::new();
+        engine.force_mode = true;
+        engine.user_move("e2e4");
+        engine.user_move("e7e5");
+        engine.user_move("g1f3");
+        engine.take_back(2);
+
+        assert_eq!(engine.moves, vec![String::from("e2e4")]);
+        assert!(!engine.game.player_turn);
+        assert_eq!(engine.game.step_counter, 1);
+    }
+
+    #[test]
+    fn moves_now_or_not_at_all() {
+        let mut engine: XboardEngine = XboardEngine::new();
+        engine.options.minimax.hash_size_mb = 1;
+        engine.fixed_move_time = Some(1000.0);
+
+        // `?` plays the best move found so far
+        engine.engine_color = Some(true);
+        engine.think();
+        engine.finish_search(false);
+        assert_eq!(engine.moves.len(), 1);
+        assert!(engine.agent.is_some());
+
+        // `force` ends the search before its move is sent
+        engine.force_mode = true;
+        engine.user_move("e7e5");
+        engine.think();
+        engine.finish_search(true);
+        assert_eq!(engine.moves.len(), 2);
+        assert!(engine.game.player_turn);
+    }
+
+    #[test]
+    fn tells_why_games_are_drawn() {
+        let mut engine: XboardEngine = XboardEngine::new();
+        engine.force_mode = true;
+        for notation in ["g1f3", "g8f6", "f3g1", "f6g8", "g1f3", "g8f6", "f3g1"] {
+            engine.user_move(notation);
+            assert_eq!(game_result(&engine.game, &engine.history), None);
+        }
+        engine.user_move("f6g8");
+        assert_eq!(game_result(&engine.game, &engine.history), Some("1/2-1/2 {Draw by repetition}"));
+
+        engine.set_board("4k3/8/8/8/8/8/8/4K2R w - - 99 80");
+        engine.user_move("h1h2");
+        assert_eq!(game_result(&engine.game, &engine.history), Some("1/2-1/2 {Draw by fifty move rule}"));
+
+        engine.set_board("7k/8/6Q1/8/8/8/8/K7 w - - 0 1");
+        engine.user_move("g6f7");
+        assert_eq!(game_result(&engine.game, &engine.history), Some("1/2-1/2 {Stalemate}"));
+    }
+
+    #[test]
+    fn budgets_time_from_level() {
+        let mut engine: XboardEngine = XboardEngine::new();
+        engine.set_level(&["40", "0:30", "0"]);
+        assert_eq!(engine.base_time, 30.0);
+        assert_eq!(engine.limits().time_budget(), Some(0.75));
+
+        engine.fixed_move_time = Some(2.0);
+        assert!((engine.limits().time_budget().unwrap() - 1.95).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,328 @@
+use std::{cmp::max, error::Error, path::Path};
+
+use good_web_game::timer;
+use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
+
+use crate::{
+    agent::tablebase::{Material, Probe, Table, Tablebases, Wdl, MAX_PIECES, TABLEBASE_DIR},
+    core::{board::{piece_attacks, Board}, move_generator::get_all_possible_moves, move_validator::is_check, piece::PieceType, position::Position}
+};
+
+const USAGE: &str = "Usage: chess-ai tablebase [materials like KQvK or KBNvK...] [--dir <directory>]";
+/// Marks positions the player to move cannot lose, as a capture wins or draws
+const CANNOT_LOSE: u8 = u8::MAX;
+
+/// Generates tables with `chess-ai tablebase [materials...] [--dir <directory>]`, by default all
+/// tables with up to `MAX_PIECES` pieces. Tables the requested ones capture into are generated first,
+/// tables already in the directory are kept
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut directory: &str = TABLEBASE_DIR;
+    let mut materials: Vec<Material> = vec![];
+
+    let mut arguments = args.iter();
+    while let Some(argument) = arguments.next() {
+        match argument.as_str() {
+            "--dir" => directory = arguments.next().ok_or(USAGE)?,
+            option if option.starts_with("--") => return Err(format!("Unknown option '{}'\n{}", option, USAGE).into()),
+            name => {
+                let material: Material = Material::from_name(name)?;
+                if material.piece_count() > MAX_PIECES {
+                    return Err(format!("Tables are limited to {} pieces, {} has {}", MAX_PIECES, name, material.piece_count()).into());
+                }
+                materials.push(material.canonical());
+            }
+        }
+    }
+    if materials.is_empty() {
+        materials = Material::all(MAX_PIECES);
+    }
+
+    let mut tablebases: Tablebases = if Path::new(directory).is_dir() {
+        Tablebases::load(directory)?
+    } else {
+        Tablebases::default()
+    };
+    for material in &materials {
+        generate_with_captures(material, &mut tablebases, directory)?;
+    }
+    Ok(())
+}
+
+fn generate_with_captures(material: &Material, tablebases: &mut Tablebases, directory: &str) -> Result<(), Box<dyn Error>> {
+    if tablebases.contains(material) {
+        return Ok(())
+    }
+    for captured in material.captures() {
+        generate_with_captures(&captured, tablebases, directory)?;
+    }
+
+    let start_time: f64 = timer::time();
+    let table: Table = generate(material, tablebases);
+    let file_path: String = table.write_to_directory(directory)?;
+
+    let probes: Vec<Probe> = table.values().iter().map(|value| Probe::from_value(*value)).collect();
+    let count = |wdl: Wdl| probes.iter().filter(|probe| probe.wdl == wdl).count();
+    println!(
+        "{}: {} wins, {} losses, longest mate {} plies, {:.1}s, written to {}",
+        material.name(),
+        count(Wdl::Win),
+        count(Wdl::Loss),
+        probes.iter().map(|probe| probe.dtm).max().unwrap_or(0),
+        timer::time() - start_time,
+        file_path
+    );
+    tablebases.insert(table);
+    Ok(())
+}
+
+/// What the moves of a position decide before the retrograde analysis starts
+#[derive(Clone, Copy)]
+struct Start {
+    /// Distinct positions of the same table that the moves without capture lead to
+    quiet_successors: u8,
+    /// `CANNOT_LOSE`, otherwise the longest loss after a capture plus 1, or 0 without captures
+    capture_loss: u8,
+    /// Value of the position if the captures or the lack of moves decide it, otherwise 0
+    value: u8
+}
+
+/// Solves all positions of the material by retrograde analysis. Positions are decided in the order
+/// of their distance to mate: a position is won once a move reaches a lost one, and lost once all
+/// of its moves reach won ones. The tables of all materials a capture leads to have to be in `tablebases`
+pub fn generate(material: &Material, tablebases: &Tablebases) -> Table {
+    let mut table: Table = Table::new(material.clone());
+
+    let starts: Vec<Start> = (0..table.size()).into_par_iter()
+        .map(|index| analyze_moves(&table, index, tablebases))
+        .collect();
+
+    let mut buckets: Vec<Vec<usize>> = vec![];
+    for (index, start) in starts.iter().enumerate() {
+        if start.value != 0 {
+            push_to_bucket(&mut buckets, start.value as usize - 1, index);
+        }
+    }
+    let mut remaining: Vec<u8> = starts.iter().map(|start| start.quiet_successors).collect();
+    let capture_loss: Vec<u8> = starts.iter().map(|start| start.capture_loss).collect();
+    drop(starts);
+
+    let mut distance: usize = 0;
+    while distance < buckets.len() {
+        assert!(distance + 1 < CANNOT_LOSE as usize, "Mates in {} take too long to be stored", material.name());
+
+        let mut decided: Vec<usize> = std::mem::take(&mut buckets[distance]);
+        decided.retain(|index| {
+            let undecided: bool = table.value(*index) == 0;
+            if undecided {
+                table.set_value(*index, distance as u8 + 1);
+            }
+            undecided
+        });
+
+        let predecessors: Vec<Vec<usize>> = decided.par_iter().map(|index| predecessors(&table, *index)).collect();
+        for predecessor in predecessors.into_iter().flatten() {
+            if table.value(predecessor) != 0 {
+                continue;
+            }
+
+            if distance.is_multiple_of(2) {
+                // Moving into a lost position wins
+                push_to_bucket(&mut buckets, distance + 1, predecessor);
+            } else if capture_loss[predecessor] != CANNOT_LOSE {
+                remaining[predecessor] -= 1;
+                if remaining[predecessor] == 0 {
+                    let longest_loss: usize = max(distance + 1, (capture_loss[predecessor] as usize).saturating_sub(1));
+                    push_to_bucket(&mut buckets, longest_loss, predecessor);
+                }
+            }
+        }
+        distance += 1;
+    }
+
+    table
+}
+
+fn push_to_bucket(buckets: &mut Vec<Vec<usize>>, distance: usize, index: usize) {
+    if buckets.len() <= distance {
+        buckets.resize_with(distance + 1, Vec::new);
+    }
+    buckets[distance].push(index);
+}
+
+/// Plays all moves of the position with the move generator of `Game`
+fn analyze_moves(table: &Table, index: usize, tablebases: &Tablebases) -> Start {
+    let Some((board, white_turn)) = table.position(index) else {
+        // Never probed, so it is left as a draw
+        return Start { quiet_successors: 0, capture_loss: CANNOT_LOSE, value: 0 }
+    };
+
+    let moves: Vec<(Position, Position)> = get_all_possible_moves(&board, white_turn);
+    if moves.is_empty() {
+        return if is_check(&board, !white_turn) {
+            Start { quiet_successors: 0, capture_loss: 0, value: Probe { wdl: Wdl::Loss, dtm: 0 }.to_value() }
+        } else {
+            Start { quiet_successors: 0, capture_loss: CANNOT_LOSE, value: 0 }
+        }
+    }
+
+    let mut quiet_successors: Vec<usize> = vec![];
+    let mut fastest_win: Option<usize> = None;
+    let mut longest_loss: Option<usize> = None;
+    let mut can_draw: bool = false;
+
+    for (from_pos, to_pos) in moves.iter() {
+        let captured: PieceType = board.get_piece_at(to_pos).piece_type();
+        let mut next_board: Board = board.clone();
+        next_board.move_from_to(from_pos, to_pos);
+
+        let outcome: Probe = match captured {
+            PieceType::Empty => {
+                let successor: usize = table.index(&next_board, !white_turn).expect("Moves keep the material");
+                if !quiet_successors.contains(&successor) {
+                    quiet_successors.push(successor);
+                }
+                continue;
+            },
+            // Capturing the king ends the game like a mate
+            PieceType::King => Probe { wdl: Wdl::Win, dtm: 1 },
+            _ => tablebases.probe_board(&next_board, !white_turn)
+                .unwrap_or_else(|| panic!("The table after a capture in {} is missing", table.material.name()))
+                .parent()
+        };
+
+        match outcome.wdl {
+            Wdl::Win => fastest_win = Some(fastest_win.map_or(outcome.dtm, |dtm| dtm.min(outcome.dtm))),
+            Wdl::Draw => can_draw = true,
+            Wdl::Loss => longest_loss = Some(longest_loss.map_or(outcome.dtm, |dtm| dtm.max(outcome.dtm)))
+        }
+    }
+
+    let quiet_successors: u8 = quiet_successors.len() as u8;
+    if let Some(dtm) = fastest_win {
+        return Start { quiet_successors, capture_loss: CANNOT_LOSE, value: Probe { wdl: Wdl::Win, dtm }.to_value() }
+    }
+    if can_draw {
+        return Start { quiet_successors, capture_loss: CANNOT_LOSE, value: 0 }
+    }
+
+    let capture_loss: u8 = longest_loss.map_or(0, |dtm| dtm as u8 + 1);
+    let value: u8 = if quiet_successors == 0 { capture_loss } else { 0 };
+    Start { quiet_successors, capture_loss, value }
+}
+
+/// Indices of the positions with a move without capture into the position, each listed once.
+/// Candidates are found by moving the pieces of the player who just moved backwards and checked
+/// with the move validator of `Game`
+fn predecessors(table: &Table, index: usize) -> Vec<usize> {
+    let (board, white_turn) = table.position(index).expect("Only positions in the table are decided");
+    let moved_white: bool = !white_turn;
+    let occupied: u64 = !board.get_empty_layer();
+    let color_layer: u64 = if moved_white { board.layer_color } else { !board.layer_color };
+
+    let mut predecessors: Vec<usize> = vec![];
+    let mut pieces: u64 = occupied & color_layer;
+    while pieces != 0 {
+        let square: u32 = pieces.trailing_zeros();
+        pieces &= pieces - 1;
+
+        let to_pos: Position = Position::from_usize(square as usize);
+        let piece_type: PieceType = board.get_piece_at(&to_pos).piece_type();
+        let mut origins: u64 = match piece_type {
+            PieceType::Pawn => pawn_origins(square, moved_white, occupied),
+            _ => piece_attacks(piece_type, square, occupied) & !occupied
+        };
+
+        while origins != 0 {
+            let from_pos: Position = Position::from_usize(origins.trailing_zeros() as usize);
+            origins &= origins - 1;
+
+            let mut previous_board: Board = board.clone();
+            previous_board.move_from_to(&to_pos, &from_pos);
+            if !previous_board.is_move_valid(moved_white, &from_pos, &to_pos) {
+                continue;
+            }
+            if let Some(predecessor) = table.index(&previous_board, moved_white) {
+                if !predecessors.contains(&predecessor) {
+                    predecessors.push(predecessor);
+                }
+            }
+        }
+    }
+    predecessors
+}
+
+/// Empty squares a pawn on `square` may have come from without capturing
+fn pawn_origins(square: u32, white: bool, occupied: u64) -> u64 {
+    let row: u32 = square / 8;
+    let (single, double, double_row) = if white {
+        (square.checked_sub(8), square.checked_sub(16), 3)
+    } else {
+        (Some(square + 8).filter(|origin| *origin < 64), Some(square + 16).filter(|origin| *origin < 64), 4)
+    };
+
+    let mut origins: u64 = 0;
+    // Pawns never stand on their own first row
+    if let Some(single) = single.filter(|origin| (1..7).contains(&(origin / 8))) {
+        if occupied & (0b1 << single) == 0 {
+            origins |= 0b1 << single;
+            if let Some(double) = double.filter(|_| row == double_row) {
+                if occupied & (0b1 << double) == 0 {
+                    origins |= 0b1 << double;
+                }
+            }
+        }
+    }
+    origins
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::game::Game;
+
+    fn probe(tablebases: &Tablebases, fen: &str) -> Probe {
+        tablebases.probe(&Game::from_fen(fen).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn solves_king_and_rook_against_king() {
+        let mut tablebases: Tablebases = Tablebases::default();
+        for name in ["KvK", "KRvK"] {
+            let table: Table = generate(&Material::from_name(name).unwrap(), &tablebases);
+            tablebases.insert(table);
+        }
+
+        assert_eq!(probe(&tablebases, "8/8/8/8/8/2k5/8/2K5 w - - 0 1"), Probe::DRAW);
+        // Kings may step next to each other in `Game`, so the checked king escapes there and is captured
+        assert_eq!(probe(&tablebases, "R5k1/8/6K1/8/8/8/8/8 b - - 0 1"), Probe { wdl: Wdl::Loss, dtm: 2 });
+        assert_eq!(probe(&tablebases, "6k1/8/6K1/8/8/8/8/R7 w - - 0 1"), Probe { wdl: Wdl::Win, dtm: 3 });
+        // The same position mirrored and with the colors swapped
+        assert_eq!(probe(&tablebases, "1k6/8/1K6/8/8/8/8/7R w - - 0 1"), Probe { wdl: Wdl::Win, dtm: 3 });
+        assert_eq!(probe(&tablebases, "r7/8/8/8/8/6k1/8/6K1 b - - 0 1"), Probe { wdl: Wdl::Win, dtm: 3 });
+
+        let white_to_move: Probe = probe(&tablebases, "8/8/8/3k4/8/8/8/R6K w - - 0 1");
+        let black_to_move: Probe = probe(&tablebases, "8/8/8/3k4/8/8/8/R6K b - - 0 1");
+        assert_eq!(white_to_move, Probe { wdl: Wdl::Win, dtm: 23 });
+        assert_eq!(black_to_move, Probe { wdl: Wdl::Loss, dtm: 24 });
+    }
+
+    #[test]
+    fn reads_written_tables() {
+        let material: Material = Material::from_name("KBNK").unwrap();
+        assert_eq!(material.name(), "KBNvK");
+        assert_eq!(Material::from_name("KvKQ").unwrap().canonical().name(), "KQvK");
+        assert_eq!(material.captures().iter().map(Material::name).collect::<Vec<_>>(), vec!["KNvK", "KBvK"]);
+        assert_eq!(Material::all(3).len(), 6);
+        assert_eq!(Material::all(4).len(), 36);
+
+        let table: Table = generate(&Material::from_name("KvK").unwrap(), &Tablebases::default());
+        let directory: String = std::env::temp_dir().join("chess-ai-tablebases").to_string_lossy().into_owned();
+        let file_path: String = table.write_to_directory(&directory).unwrap();
+        assert_eq!(Table::read_from_file(&file_path).unwrap().values(), table.values());
+
+        // Kings next to each other can be captured
+        let tablebases: Tablebases = Tablebases::load(&directory).unwrap();
+        assert_eq!(probe(&tablebases, "8/8/8/8/8/2k5/2K5/8 b - - 0 1"), Probe { wdl: Wdl::Win, dtm: 1 });
+        assert_eq!(probe(&tablebases, "8/8/8/8/2k5/8/2K5/8 w - - 0 1"), Probe::DRAW);
+    }
+}
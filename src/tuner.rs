@@ -0,0 +1,229 @@
+use std::{error::Error, fs, path::Path};
+
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::{agent::eval::{self, EvalParams}, core::game::Game};
+
+/// Largest change a weight is tried with, it is halved whenever a pass finds no improvement
+const INITIAL_STEP: isize = 8;
+const MAX_PASSES: usize = 1000;
+
+/// A position and the result of the game it occurred in, 1 if white won, 0.5 for a draw and 0 if black won
+pub struct LabeledPosition {
+    pub game: Game,
+    pub result: f64
+}
+
+/// Tunes the evaluation weights with `chess-ai tune <positions file> [output file]`.
+/// Every line of the positions file holds a FEN followed by the result, e.g. `1-0`, `1/2-1/2`
+/// or `[0.5]`, or an EPD line with the result in its `c9` operation, e.g. `c9 "0-1";`.
+/// The tuning starts from the weights the agents currently load
+/// and writes the tuned weights after every pass, by default to the file the agents load
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let Some(positions_file) = args.first() else {
+        return Err("Usage: chess-ai tune <positions file> [output file]".into());
+    };
+    let output_file: &str = args.get(1).map_or(eval::PARAMS_FILE, |file| file.as_str());
+
+    let positions: Vec<LabeledPosition> = read_positions(positions_file)?;
+    if positions.is_empty() {
+        return Err(format!("No labeled positions found in {}", positions_file).into());
+    }
+    if let Some(directory) = Path::new(output_file).parent().filter(|directory| !directory.as_os_str().is_empty()) {
+        fs::create_dir_all(directory)?;
+    }
+
+    let start_params: EvalParams = eval::params().clone();
+    let scaling: f64 = optimal_scaling(&positions, &start_params);
+    println!("Tuning {} weights on {} positions, scaling {:.3}", start_params.to_vector().len(), positions.len(), scaling);
+
+    let mut write_error: Option<Box<dyn Error>> = None;
+    tune(&positions, start_params, scaling, MAX_PASSES, |pass, error, params| {
+        println!("Pass {}: error {:.6}", pass + 1, error);
+        if let Err(error) = params.write_to_file(output_file) {
+            write_error.get_or_insert(error);
+        }
+    });
+
+    match write_error {
+        Some(error) => Err(error),
+        None => {
+            println!("Tuned weights written to {}", output_file);
+            Ok(())
+        }
+    }
+}
+
+/// Lines that cannot be parsed are skipped
+pub fn read_positions(file_path: &str) -> Result<Vec<LabeledPosition>, Box<dyn Error>> {
+    let file_content: String = fs::read_to_string(file_path)?;
+    Ok(file_content.lines().filter_map(parse_position).collect())
+}
+
+fn parse_position(line: &str) -> Option<LabeledPosition> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    // Placement, side to move, castling and en passant are followed by the move counters, which EPD leaves out
+    let counters: usize = fields.iter()
+        .skip(4)
+        .take(2)
+        .take_while(|field| field.parse::<usize>().is_ok())
+        .count();
+    if fields.len() <= 4 + counters {
+        return None
+    }
+    let (fen, rest) = fields.split_at(4 + counters);
+
+    let operations: String = rest.join(" ");
+    let result: f64 = match (epd_operand(&operations, "c9"), rest) {
+        (Some(operand), _) => parse_result(operand)?,
+        (None, [result]) => parse_result(result)?,
+        (None, _) => return None
+    };
+    let game: Game = Game::from_fen(&fen.join(" ")).ok()?;
+    Some(LabeledPosition { game, result })
+}
+
+/// The operand of `opcode` among EPD operations like `bm e4; c9 "1-0";`
+fn epd_operand<'a>(operations: &'a str, opcode: &str) -> Option<&'a str> {
+    operations.split(';')
+        .filter_map(|operation| operation.trim().split_once(char::is_whitespace))
+        .find(|(name, _)| *name == opcode)
+        .map(|(_, operand)| operand.trim())
+}
+
+fn parse_result(token: &str) -> Option<f64> {
+    match token.trim_matches(|symbol: char| matches!(symbol, '[' | ']' | '"' | ';')) {
+        "1-0" => Some(1.0),
+        "0-1" => Some(0.0),
+        "1/2-1/2" => Some(0.5),
+        result => result.parse::<f64>().ok().filter(|result| (0.0..=1.0).contains(result))
+    }
+}
+
+/// Expected result for white of a position evaluated with `score` centipawns
+fn sigmoid(score: isize, scaling: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-scaling * score as f64 / 400.0))
+}
+
+/// Mean squared difference between the game results and the results the static evaluation
+/// predicts. The positions should be quiet, as no captures are resolved
+pub fn error(positions: &[LabeledPosition], params: &EvalParams, scaling: f64) -> f64 {
+    let total: f64 = positions.par_iter()
+        .map(|position| {
+            let score: isize = position_score(&position.game, params);
+            (position.result - sigmoid(score, scaling)).powi(2)
+        })
+        .sum();
+    total / positions.len() as f64
+}
+
+/// Finished games are scored by their result, so only the other positions influence the weights
+fn position_score(game: &Game, params: &EvalParams) -> isize {
+    match game.get_winner() {
+        Some(1) => 10_000,
+        Some(0) => -10_000,
+        Some(_) => 0,
+        None => eval::evaluate_with(&game.board, params)
+    }
+}
+
+/// The scaling of the sigmoid that fits the results best, found by ternary search
+pub fn optimal_scaling(positions: &[LabeledPosition], params: &EvalParams) -> f64 {
+    let (mut low, mut high) = (0.0, 4.0);
+    for _ in 0..40 {
+        let first: f64 = low + (high - low) / 3.0;
+        let second: f64 = high - (high - low) / 3.0;
+        if error(positions, params, first) < error(positions, params, second) {
+            high = second;
+        } else {
+            low = first;
+        }
+    }
+    (low + high) / 2.0
+}
+
+/// Local search: every weight is moved by the step in both directions, changes that lower
+/// the error are kept. `report` gets every pass with its error and the weights so far
+pub fn tune(
+    positions: &[LabeledPosition],
+    params: EvalParams,
+    scaling: f64,
+    max_passes: usize,
+    mut report: impl FnMut(usize, f64, &EvalParams)
+) -> EvalParams {
+    let mut weights: Vec<isize> = params.to_vector();
+    let mut best_params: EvalParams = params;
+    let mut best_error: f64 = error(positions, &best_params, scaling);
+    let mut step: isize = INITIAL_STEP;
+
+    for pass in 0..max_passes {
+        let mut improved: bool = false;
+
+        for index in 0..weights.len() {
+            for direction in [1, -1] {
+                weights[index] += direction * step;
+                let candidate: EvalParams = EvalParams::from_vector(&weights).unwrap();
+                let candidate_error: f64 = error(positions, &candidate, scaling);
+
+                if candidate_error < best_error {
+                    best_error = candidate_error;
+                    best_params = candidate;
+                    improved = true;
+                    break;
+                }
+                weights[index] -= direction * step;
+            }
+        }
+
+        report(pass, best_error, &best_params);
+        if !improved {
+            if step == 1 {
+                break;
+            }
+            step /= 2;
+        }
+    }
+
+    best_params
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_labeled_positions() {
+        let fen: &str = "4k3/8/8/8/8/8/8/4KQ2 w - - 0 1";
+        for (line, result) in [
+            (format!("{} 1-0", fen), 1.0),
+            (format!("{} [0.5]", fen), 0.5),
+            (format!("{} c9 \"0-1\";", "4k3/8/8/8/8/8/8/4KQ2 w - -"), 0.0),
+            (format!("{} bm Qf8+; c9 \"1/2-1/2\"; id \"test 1\";", "4k3/8/8/8/8/8/8/4KQ2 w - -"), 0.5),
+            (format!("{} id \"c9\"; c0 \"won\"; c9 \"1-0\";", fen), 1.0)
+        ] {
+            assert_eq!(parse_position(&line).map(|position| position.result), Some(result));
+        }
+        assert!(parse_position(&format!("{} 2-0", fen)).is_none());
+        assert!(parse_position("no position 1-0").is_none());
+        assert!(parse_position(&format!("{} bm Qf8+; id \"1-0\";", fen)).is_none());
+    }
+
+    #[test]
+    fn tuning_lowers_the_error() {
+        // White wins the games with an extra knight, although the evaluation sees them as close
+        let positions: Vec<LabeledPosition> = [
+            ("4k3/pppp4/8/8/8/8/PPP5/1N2K3 w - - 0 1", 1.0),
+            ("4k3/ppp5/8/8/8/8/PPPP4/4K3 b - - 0 1", 0.5),
+            ("4k3/pppp4/8/8/8/8/PPP5/4KN2 b - - 0 1", 1.0)
+        ].into_iter()
+            .map(|(fen, result)| LabeledPosition { game: Game::from_fen(fen).unwrap(), result })
+            .collect();
+
+        let params: EvalParams = EvalParams::default();
+        let start_error: f64 = error(&positions, &params, 1.0);
+        let tuned: EvalParams = tune(&positions, params.clone(), 1.0, 1, |_, _, _| ());
+
+        assert!(error(&positions, &tuned, 1.0) < start_error);
+        assert_eq!(EvalParams::from_vector(&tuned.to_vector()).unwrap(), tuned);
+    }
+}
@@ -6,13 +6,15 @@ use ggez::event::EventHandler;
 use ggez::graphics::{DrawParam, Image, Rect};
 use ggez::{event, graphics, Context, GameError, GameResult};
 use good_web_game::{self as ggez, timer};
-use good_web_game::graphics::Color;
+use good_web_game::graphics::{Color, Text, TextFragment};
 
 use miniquad::GraphicsContext;
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 
-use crate::agent::Agent;
+use crate::agent::{console_reporter, Agent, SearchInfo, SearchLimits};
 use crate::core::board::Board;
+use crate::core::clock::{format_time, TimeControl};
 use crate::core::game::Game;
 use crate::core::move_generator::get_possible_moves;
 use crate::core::piece::Piece;
@@ -24,6 +26,8 @@ use self::carry_piece::CarryPiece;
 use self::logic::{determine_image, determine_image_position};
 
 const COOLDOWN_TIME: f64 = 0.2;
+/// Seconds an agent thinks per move in games without clocks, unless its limits set a time
+const UNTIMED_MOVE_TIME: f64 = 1.0;
 
 pub struct Engine {
     game: Game,
@@ -35,8 +39,11 @@ pub struct Engine {
     carry_piece: CarryPiece,
     cooldown_until: f64,
 
-    white_agent: Option<Agent>,
-    black_agent: Option<Agent>,
+    white_agent: Option<Box<dyn Agent>>,
+    black_agent: Option<Box<dyn Agent>>,
+    agent_limits: SearchLimits,
+    /// Latest score reported by an agent, like `Minimax: #3`
+    agent_score: Arc<Mutex<Option<String>>>,
 
     debug: bool,
 }
@@ -45,10 +52,28 @@ impl Engine {
     pub fn new(
         ctx: &mut Context,
         quad_ctx: &mut GraphicsContext,
-        white_agent: Option<Agent>,
-        black_agent: Option<Agent>
+        mut white_agent: Option<Box<dyn Agent>>,
+        mut black_agent: Option<Box<dyn Agent>>,
+        agent_limits: SearchLimits,
+        time_control: Option<TimeControl>
     ) -> GameResult<Engine> {
-        let game: Game = Game::new();
+        let mut game: Game = match time_control {
+            Some(time_control) => Game::with_time_control(time_control),
+            None => Game::new()
+        };
+
+        let agent_score: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
+        for agent in [&mut white_agent, &mut black_agent].into_iter().flatten() {
+            let name: String = agent.name();
+            let console_reporter = console_reporter(&name);
+            let agent_score = agent_score.clone();
+
+            agent.new_game(&game);
+            agent.set_reporter(Arc::new(move |info: &SearchInfo| {
+                console_reporter(info);
+                *agent_score.lock().unwrap() = Some(format!("{}: {}", name, info.format_score()));
+            }));
+        }
 
         let images: HashMap<String, Image> = Engine::load_images(ctx, quad_ctx);
 
@@ -59,6 +84,10 @@ impl Engine {
         let carry_piece: CarryPiece = CarryPiece::new();
         let cooldown_until: f64 = timer::time();
 
+        if let Some(clock) = &mut game.clock {
+            clock.start(timer::time());
+        }
+
         Ok(Engine {
             game,
             images,
@@ -68,6 +97,8 @@ impl Engine {
             cooldown_until,
             white_agent,
             black_agent,
+            agent_limits,
+            agent_score,
             debug: false,
         })
     }
@@ -124,36 +155,103 @@ impl Engine {
     }
 
     fn perform_move(&mut self, from_pos: &Position, to_pos: &Position) {
+        let step_counter: usize = self.game.step_counter;
+        self.game.perform_timed_move(from_pos, to_pos, timer::time());
+        if self.game.step_counter == step_counter {
+            return;
+        }
+
         if let Some(white_agent) = &mut self.white_agent {
-            white_agent.inform_about_move(from_pos, to_pos);
+            white_agent.notify_move(from_pos, to_pos);
         }
         if let Some(black_agent) = &mut self.black_agent {
-            black_agent.inform_about_move(from_pos, to_pos);
+            black_agent.notify_move(from_pos, to_pos);
         }
-        
-        self.game.perform_move(from_pos, to_pos);
 
         self.cooldown_until = timer::time() + COOLDOWN_TIME;
     }
+
+    /// The fixed agent limits, plus the clock of the player to move in timed games
+    /// or `UNTIMED_MOVE_TIME` in the others
+    fn agent_limits(&self) -> SearchLimits {
+        let Some(clock) = &self.game.clock else {
+            return SearchLimits {
+                time: self.agent_limits.time.or(Some(UNTIMED_MOVE_TIME)),
+                ..self.agent_limits.clone()
+            };
+        };
+
+        let player: bool = self.game.player_turn;
+        SearchLimits {
+            remaining_time: self.game.remaining_time(player, timer::time()),
+            increment: clock.increment(),
+            moves_to_go: clock.moves_to_go(player),
+            ..self.agent_limits.clone()
+        }
+    }
+
+    fn draw_clocks(&self, ctx: &mut Context, quad_ctx: &mut GraphicsContext) -> GameResult {
+        let now: f64 = timer::time();
+        let (_, window_height): (f32, f32) = graphics::drawable_size(quad_ctx);
+        let font_size: f32 = 80.0 * self.scales.y;
+
+        for player in [true, false] {
+            let Some(remaining_time) = self.game.remaining_time(player, now) else {
+                return Ok(())
+            };
+
+            let color: Color = if player == self.game.player_turn && self.game.get_winner().is_none() {
+                Color::from_rgb_u32(0xF0E6D2)
+            } else {
+                Color::from_rgb_u32(0x8C7B80)
+            };
+            let text: Text = Text::new(
+                TextFragment::new(format_time(remaining_time)).scale(font_size).color(color)
+            );
+
+            let y: f32 = if player { window_height - font_size - 10.0 } else { 10.0 };
+            graphics::draw(ctx, quad_ctx, &text, DrawParam::new().dest(Point2::new(10.0, y)))?;
+        }
+
+        Ok(())
+    }
+
+    fn draw_agent_score(&self, ctx: &mut Context, quad_ctx: &mut GraphicsContext) -> GameResult {
+        let Some(agent_score) = self.agent_score.lock().unwrap().clone() else {
+            return Ok(())
+        };
+
+        let (window_width, _): (f32, f32) = graphics::drawable_size(quad_ctx);
+        let font_size: f32 = 60.0 * self.scales.y;
+        let text: Text = Text::new(
+            TextFragment::new(agent_score).scale(font_size).color(Color::from_rgb_u32(0xF0E6D2))
+        );
+
+        let x: f32 = window_width - text.width(ctx) - 10.0;
+        graphics::draw(ctx, quad_ctx, &text, DrawParam::new().dest(Point2::new(x, 10.0)))
+    }
 }
 
 impl EventHandler<GameError> for Engine {
     fn update(&mut self, _ctx: &mut Context, _quad_ctx: &mut GraphicsContext) -> GameResult {
+        self.game.update_clock(timer::time());
+
         if timer::time() < self.cooldown_until {
             return Ok(())
         }
 
+        let limits: SearchLimits = self.agent_limits();
         if self.game.get_winner().is_some() {
             match self.game.get_winner().unwrap() {
                 0 => println!("Black has won the game !!!"),
                 1 => println!("White has won the game !!!"),
                 _ => println!("Remis"),
             }
-        } else if self.game.player_turn && self.white_agent.is_some() {
-            let agent_move: (Position, Position) = self.white_agent.clone().unwrap().get_next_turn();
+        } else if let (true, Some(white_agent)) = (self.game.player_turn, &mut self.white_agent) {
+            let agent_move: (Position, Position) = white_agent.choose_move(&limits);
             self.perform_move(&agent_move.0, &agent_move.1);
-        } else if !self.game.player_turn && self.black_agent.is_some() {
-            let agent_move: (Position, Position) = self.black_agent.clone().unwrap().get_next_turn();
+        } else if let (false, Some(black_agent)) = (self.game.player_turn, &mut self.black_agent) {
+            let agent_move: (Position, Position) = black_agent.choose_move(&limits);
             self.perform_move(&agent_move.0, &agent_move.1);
         }
 
@@ -239,6 +337,10 @@ impl EventHandler<GameError> for Engine {
                 });
         }
 
+        /* Clocks and agent score */
+        self.draw_clocks(ctx, quad_ctx)?;
+        self.draw_agent_score(ctx, quad_ctx)?;
+
         /* Grabbed Piece */
         if let Some(piece) = self.carry_piece.piece() {
             let image: Option<Image> = determine_image(&self.images, piece);